@@ -0,0 +1,49 @@
+/*
+ * Layer provider plugins for the borealis filesystem, see `filesystem/src/layered/layers/plugin.rs`.
+ *
+ * A plugin is a shared library in the `bin/` or `plugins/` folder of the game, which exports
+ * `borealis_layer_providers()`, returning an array of providers which stays valid while the library is loaded.
+ * All strings are NUL-terminated UTF-8, paths inside archives are lowercase, with `/` as separator.
+ * Functions may be called from any thread, with the same archive too.
+ */
+#ifndef BOREALIS_LAYER_PLUGIN_H
+#define BOREALIS_LAYER_PLUGIN_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define BOREALIS_PLUGIN_ABI_VERSION 1
+
+typedef void ( *BorealisDirCallback )( void* ctx, const char* name, int32_t is_dir );
+
+typedef struct BorealisLayerProvider {
+	/* must be BOREALIS_PLUGIN_ABI_VERSION */
+	uint32_t abi_version;
+	/* name of the format, for logs */
+	const char* name;
+	/* returns non-zero if the provider can open the archive at this path */
+	int32_t ( *supports )( const char* path );
+	/* opens an archive, returns NULL on failure */
+	void* ( *open )( const char* path );
+	/* closes an archive, it isn't used afterward */
+	void ( *close )( void* archive );
+	/* returns the size of a file, negative if the archive doesn't have it */
+	int64_t ( *file_size )( void* archive, const char* filename );
+	/* reads up to `len` bytes of a file starting at `offset` into `buf`, returns how many, negative on errors */
+	int64_t ( *read )( void* archive, const char* filename, uint64_t offset, uint8_t* buf, uint64_t len );
+	/* calls `entry` for each entry of a directory, returns zero if the archive doesn't have the directory */
+	int32_t ( *read_dir )( void* archive, const char* dirname, void* ctx, BorealisDirCallback entry );
+} BorealisLayerProvider;
+
+/* writes how many providers there are into `count` */
+const BorealisLayerProvider* borealis_layer_providers( size_t* count );
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
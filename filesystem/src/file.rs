@@ -0,0 +1,335 @@
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Cursor, Error, ErrorKind, Lines, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use log::warn;
+use uuid::Uuid;
+
+use crate::layered::{Layer, LayeredFile, LayeredFS, LayeredFSError};
+use crate::layered::stream::LayeredStream;
+
+/**
+ * How a `File` is opened.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+	/// reads an existing file, from whichever layer has it
+	Read,
+	/// creates or truncates a file, in the write layer
+	Write,
+	/// writes at the end of a file, created if missing, starting from its current contents in any layer
+	Append,
+	/// reads and writes a file, created if missing, starting from its current contents in any layer
+	ReadWrite
+}
+
+impl OpenMode {
+	pub fn is_readable( &self ) -> bool {
+		matches!( self, OpenMode::Read | OpenMode::ReadWrite )
+	}
+
+	pub fn is_writable( &self ) -> bool {
+		!matches!( self, OpenMode::Read )
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMetadata {
+	pub size: u64,
+	/// time of the last modification, files in archives have the one of the archive,
+	/// `None` for files which aren't on disk, like the ones of memory layers, or not written yet
+	pub modified: Option<SystemTime>,
+	/// the layer the file is in, `None` for host files
+	pub layer: Option<Uuid>,
+	/// where the file is, see `Layer::resolve()`
+	pub source: PathBuf
+}
+
+/**
+ * A file handle for engine code, over a file of the layered filesystem or of the host.
+ *
+ * Layered files are read from the layer which has them, and written to the write layer: writes are buffered,
+ * and reach the layer on `flush()`, `close()`, or when the handle is dropped.
+ * Errors are `io::Error`s with consistent kinds for all backings:
+ * - `NotFound` when the file is missing
+ * - `PermissionDenied` when the mode doesn't allow the operation, there is no write layer, or the path escapes the root
+ * - `InvalidData` when text isn't valid UTF-8
+ */
+pub struct File {
+	path: String,
+	mode: OpenMode,
+	backing: Backing
+}
+
+enum Backing {
+	Layered { file: LayeredFile, stream: BufReader<LayeredStream>, layer: Arc<dyn Layer> },
+	/// contents to write to the layer, `dirty` once they differ from the ones in it
+	Buffered { data: Cursor<Vec<u8>>, layer: Arc<dyn Layer>, dirty: bool },
+	Host { file: std::fs::File, path: PathBuf }
+}
+
+impl File {
+	/**
+	 * Opens a file of the host, outside of the layered filesystem, like a path given on the command line.
+	 */
+	pub fn open_host( path: &Path, mode: OpenMode ) -> Result<File, Error> {
+		let file = OpenOptions::new()
+			.read( mode.is_readable() )
+			.write( matches!( mode, OpenMode::Write | OpenMode::ReadWrite ) )
+			.append( mode == OpenMode::Append )
+			.truncate( mode == OpenMode::Write )
+			.create( mode.is_writable() )
+			.open( path )?;
+		Ok( File {
+			path: path.to_string_lossy().to_string(),
+			mode,
+			backing: Backing::Host { file, path: path.to_path_buf() }
+		} )
+	}
+
+	/**
+	 * The path the file was opened with.
+	 */
+	pub fn path( &self ) -> &str {
+		self.path.as_str()
+	}
+
+	pub fn mode( &self ) -> OpenMode {
+		self.mode
+	}
+
+	pub fn metadata( &self ) -> Result<FileMetadata, Error> {
+		match &self.backing {
+			Backing::Layered { file, layer, .. } => Ok( FileMetadata {
+				size: file.size(),
+				modified: modified( layer.as_ref(), self.path.as_str() ),
+				layer: Some( *layer.uuid() ),
+				source: layer.resolve( self.path.as_str() )
+			} ),
+			Backing::Buffered { data, layer, .. } => Ok( FileMetadata {
+				size: data.get_ref().len() as u64,
+				modified: modified( layer.as_ref(), self.path.as_str() ),
+				layer: Some( *layer.uuid() ),
+				source: layer.resolve( self.path.as_str() )
+			} ),
+			Backing::Host { file, path } => {
+				let meta = file.metadata()?;
+				Ok( FileMetadata { size: meta.len(), modified: meta.modified().ok(), layer: None, source: path.clone() } )
+			}
+		}
+	}
+
+	/**
+	 * Reads the rest of the file.
+	 */
+	pub fn read_bytes( &mut self ) -> Result<Vec<u8>, Error> {
+		let mut data = Vec::new();
+		self.read_to_end( &mut data )?;
+		Ok( data )
+	}
+
+	/**
+	 * Reads the rest of the file as UTF-8 text.
+	 */
+	pub fn read_text( &mut self ) -> Result<String, Error> {
+		let mut text = String::new();
+		self.read_to_string( &mut text )?;
+		Ok( text )
+	}
+
+	/**
+	 * Iterates over the rest of the lines of the file, without line terminators.
+	 */
+	pub fn lines( &mut self ) -> Lines<BufReader<&mut File>> {
+		BufReader::new( self ).lines()
+	}
+
+	pub fn write_text( &mut self, text: &str ) -> Result<(), Error> {
+		self.write_all( text.as_bytes() )
+	}
+
+	/**
+	 * Flushes the file and closes it, unlike dropping it, which only logs failures.
+	 */
+	pub fn close( mut self ) -> Result<(), Error> {
+		self.flush()
+	}
+
+	fn denied( &self, operation: &str ) -> Error {
+		Error::new( ErrorKind::PermissionDenied, format!( "{} was opened as {:?}, it can't be {operation}", self.path, self.mode ) )
+	}
+}
+
+/**
+ * Finds when a file was last modified: loose files have their own time, files in archives the one of the archive.
+ */
+fn modified( layer: &dyn Layer, filename: &str ) -> Option<SystemTime> {
+	let mut paths = vec![ layer.resolve( filename ) ];
+	if layer.is_archive() {
+		paths.push( PathBuf::from( layer.meta().filename ) );
+	}
+	paths.iter().find_map( |path| std::fs::metadata( path ).and_then( |meta| meta.modified() ).ok() )
+}
+
+impl Read for File {
+	fn read( &mut self, buf: &mut [u8] ) -> Result<usize, Error> {
+		if !self.mode.is_readable() {
+			return Err( self.denied( "read" ) );
+		}
+		match &mut self.backing {
+			Backing::Layered { stream, .. } => stream.read( buf ),
+			Backing::Buffered { data, .. } => data.read( buf ),
+			Backing::Host { file, .. } => file.read( buf )
+		}
+	}
+}
+
+impl Write for File {
+	fn write( &mut self, buf: &[u8] ) -> Result<usize, Error> {
+		if !self.mode.is_writable() {
+			return Err( self.denied( "written" ) );
+		}
+		match &mut self.backing {
+			Backing::Buffered { data, dirty, .. } => {
+				if self.mode == OpenMode::Append {
+					data.seek( SeekFrom::End( 0 ) )?;
+				}
+				*dirty = true;
+				data.write( buf )
+			}
+			Backing::Host { file, .. } => file.write( buf ),
+			Backing::Layered { .. } => unreachable!( "layered files are only opened for reading" )
+		}
+	}
+
+	fn flush( &mut self ) -> Result<(), Error> {
+		match &mut self.backing {
+			Backing::Buffered { data, layer, dirty } if *dirty => {
+				layer.write( self.path.as_str(), data.get_ref() )?;
+				*dirty = false;
+				Ok(())
+			}
+			Backing::Host { file, .. } => file.flush(),
+			_ => Ok(())
+		}
+	}
+}
+
+impl Seek for File {
+	fn seek( &mut self, from: SeekFrom ) -> Result<u64, Error> {
+		match &mut self.backing {
+			Backing::Layered { stream, .. } => stream.seek( from ),
+			Backing::Buffered { data, .. } => data.seek( from ),
+			Backing::Host { file, .. } => file.seek( from )
+		}
+	}
+}
+
+impl Drop for File {
+	fn drop( &mut self ) {
+		if let Err( err ) = self.flush() {
+			warn!( "Failed to write {} when closing it: {err}", self.path );
+		}
+	}
+}
+
+impl LayeredFS {
+	/**
+	 * Opens a file of the layered filesystem, see `File`.
+	 * Writing modes need a write layer, `Append` and `ReadWrite` copy the current contents of the file
+	 * into it on the first flush, from whichever layer has it.
+	 */
+	pub fn open( &self, filename: &str, mode: OpenMode ) -> Result<File, Error> {
+		if mode == OpenMode::Read {
+			let file = self.get_file( filename )?;
+			let layer = self.find_layer( &file.layer() ).ok_or( LayeredFSError::UnknownLayer( file.layer() ) )?;
+			let stream = BufReader::new( file.stream()? );
+			return Ok( File { path: filename.to_string(), mode, backing: Backing::Layered { file, stream, layer } } );
+		}
+
+		let layer = self.expect_write_layer()?;
+		let ( mut data, dirty ) = match mode {
+			OpenMode::Write => {
+				layer.write( filename, b"" )?;
+				( Cursor::new( Vec::new() ), false )
+			}
+			_ => {
+				let data = match self.get_file( filename ) {
+					Ok( file ) => file.read()?,
+					Err( err ) if err.kind() == ErrorKind::NotFound => Vec::new(),
+					Err( err ) => return Err( err )
+				};
+				( Cursor::new( data ), !layer.contains( filename ) )
+			}
+		};
+		if mode == OpenMode::Append {
+			data.seek( SeekFrom::End( 0 ) )?;
+		}
+		Ok( File { path: filename.to_string(), mode, backing: Backing::Buffered { data, layer, dirty } } )
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+	use std::sync::Arc;
+
+	use crate::file::{File, OpenMode};
+	use crate::layered::{Layer, LayeredFS};
+	use crate::layered::layers::folder::FolderLayer;
+	use crate::layered::layers::memory::MemoryLayer;
+	use crate::layered::tests::scratch_dir;
+
+	#[test]
+	fn open_modes() {
+		let root = scratch_dir( "file" );
+
+		let mut fs = LayeredFS::new();
+		let write = Arc::new( FolderLayer::writable( root.join( "write" ) ) );
+		fs.insert_layer( write.clone(), false, &[] );
+		let game = Arc::new( MemoryLayer::new( "game" ).with( "cfg/config.cfg", "fov 90\nsensitivity 2\n" ) );
+		fs.insert_layer( game.clone(), false, &[] );
+		fs.set_write_layer( write.uuid() ).unwrap();
+
+		let mut file = fs.open( "CFG/Config.cfg", OpenMode::Read ).unwrap();
+		assert_eq!( vec![ "fov 90", "sensitivity 2" ], file.lines().collect::<Result<Vec<String>, _>>().unwrap() );
+		let meta = file.metadata().unwrap();
+		assert_eq!( ( 21, Some( *game.uuid() ), None ), ( meta.size, meta.layer, meta.modified ) );
+		assert_eq!( ErrorKind::PermissionDenied, file.write( b"x" ).unwrap_err().kind() );
+		assert_eq!( ErrorKind::NotFound, fs.open( "cfg/missing.cfg", OpenMode::Read ).err().unwrap().kind() );
+
+		// appending copies the file into the write layer
+		let mut file = fs.open( "cfg/config.cfg", OpenMode::Append ).unwrap();
+		file.seek( SeekFrom::Start( 0 ) ).unwrap();
+		file.write_text( "volume 1\n" ).unwrap();
+		assert_eq!( ErrorKind::PermissionDenied, file.read( &mut [0; 4] ).unwrap_err().kind() );
+		file.close().unwrap();
+		assert_eq!( "fov 90\nsensitivity 2\nvolume 1\n", fs.get_file( "cfg/config.cfg" ).unwrap().read_string().unwrap() );
+		assert_eq!( "fov 90\nsensitivity 2\n", game.get_file( "cfg/config.cfg" ).unwrap().read_string().unwrap() );
+
+		// writes reach the layer when the file is dropped
+		let mut file = fs.open( "save/slot1.sav", OpenMode::ReadWrite ).unwrap();
+		file.write_all( b"SAVE" ).unwrap();
+		file.seek( SeekFrom::Start( 0 ) ).unwrap();
+		assert_eq!( b"SAVE".to_vec(), file.read_bytes().unwrap() );
+		drop( file );
+		let meta = fs.open( "save/slot1.sav", OpenMode::Read ).unwrap().metadata().unwrap();
+		assert_eq!( ( Some( *write.uuid() ), root.join( "write/save/slot1.sav" ) ), ( meta.layer, meta.source ) );
+		assert!( meta.modified.is_some() );
+
+		// host files behave the same
+		let path = root.join( "host.txt" );
+		let mut file = File::open_host( &path, OpenMode::Write ).unwrap();
+		file.write_text( "host" ).unwrap();
+		assert_eq!( ErrorKind::PermissionDenied, file.read_text().unwrap_err().kind() );
+		file.close().unwrap();
+		let mut file = File::open_host( &path, OpenMode::Read ).unwrap();
+		assert_eq!( "host", file.read_text().unwrap() );
+		assert_eq!( ( 4, None ), ( file.metadata().unwrap().size, file.metadata().unwrap().layer ) );
+		assert_eq!( ErrorKind::NotFound, File::open_host( &root.join( "missing.txt" ), OpenMode::Read ).err().unwrap().kind() );
+
+		std::fs::remove_dir_all( root ).unwrap();
+	}
+}
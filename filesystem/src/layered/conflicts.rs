@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+
+use json::{JsonValue, object};
+use uuid::Uuid;
+
+use crate::layered::{Layer, LayeredFS, normalize_path};
+use crate::layered::hash::hash_file;
+use crate::layered::whiteout::{Hit, find, is_whiteout};
+
+/**
+ * The copy of a file in one layer.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerCopy {
+	pub layer: Uuid,
+	pub layer_name: String,
+	/// SHA-256 of the contents, only when hashing
+	pub hash: Option<String>
+}
+
+/**
+ * A path which more than one layer has.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+	/// normalized path
+	pub path: String,
+	/// the copy which gets read, `None` if a whiteout hides the path
+	pub winner: Option<LayerCopy>,
+	/// the copies which are never read, in priority order
+	pub shadowed: Vec<LayerCopy>
+}
+
+impl Conflict {
+	/**
+	 * The shadowed copies with the same contents as the winner, there are none without hashes.
+	 */
+	pub fn duplicates( &self ) -> impl Iterator<Item = &LayerCopy> {
+		let hash = self.winner.as_ref().and_then( |winner| winner.hash.as_ref() );
+		self.shadowed.iter()
+			.filter( move |copy| hash.is_some() && copy.hash.as_ref() == hash )
+	}
+
+	pub fn to_json( &self ) -> JsonValue {
+		let copy = |copy: &LayerCopy| {
+			let mut value = object! { layer: copy.layer.to_string(), layer_name: copy.layer_name.as_str() };
+			if let Some( hash ) = &copy.hash {
+				value[ "hash" ] = hash.as_str().into();
+			}
+			value
+		};
+		object! {
+			path: self.path.as_str(),
+			winner: self.winner.as_ref().map_or( JsonValue::Null, copy ),
+			shadowed: self.shadowed.iter().map( copy ).collect::<Vec<JsonValue>>(),
+			duplicates: self.duplicates().map( |copy| copy.layer.to_string() ).collect::<Vec<String>>()
+		}
+	}
+}
+
+impl LayeredFS {
+	/**
+	 * Reports every file under a directory which more than one enabled layer has, with the copy which wins
+	 * and the shadowed ones, for finding which files of a mod override base content and which are dead.
+	 * With `hashes`, all copies are hashed, to flag shadowed copies which are identical to the winner.
+	 */
+	pub fn conflicts( &self, dirname: &str, hashes: bool ) -> Result<Vec<Conflict>, Error> {
+		// normalized path -> layers which have it, in priority order
+		let mut paths: BTreeMap<String, Vec<&Arc<dyn Layer>>> = BTreeMap::new();
+		for layer in self.active_layers() {
+			let mut files = Vec::new();
+			match list_files( layer.as_ref(), dirname, &mut files ) {
+				Err( err ) if err.kind() != ErrorKind::NotFound => return Err( err ),
+				_ => { }
+			}
+			for file in files {
+				paths.entry( normalize_path( file.as_str() ) ).or_default().push( layer );
+			}
+		}
+
+		let mut conflicts = Vec::new();
+		for ( path, layers ) in paths.into_iter().filter( |( _, layers )| layers.len() > 1 ) {
+			let winner = match find( self.active_layers(), path.as_str() ) {
+				Hit::Found( layer ) => Some( layer.uuid() ),
+				_ => None
+			};
+			let mut conflict = Conflict { path: path.clone(), winner: None, shadowed: Vec::new() };
+			for layer in layers {
+				let copy = LayerCopy {
+					layer: *layer.uuid(),
+					layer_name: layer.meta().filename,
+					hash: match hashes {
+						true => Some( hash_file( layer.get_file( path.as_str() )?.as_ref() )? ),
+						false => None
+					}
+				};
+				match Some( layer.uuid() ) == winner {
+					true => conflict.winner = Some( copy ),
+					false => conflict.shadowed.push( copy )
+				}
+			}
+			conflicts.push( conflict );
+		}
+		Ok( conflicts )
+	}
+}
+
+/**
+ * Collects the paths of all files under a directory of a layer, whiteout markers aren't files.
+ */
+pub(crate) fn list_files( layer: &dyn Layer, dirname: &str, files: &mut Vec<String> ) -> Result<(), Error> {
+	for entry in layer.read_dir( dirname )? {
+		if entry.is_dir {
+			list_files( layer, entry.path.as_str(), files )?;
+		} else if !is_whiteout( entry.name.as_str() ) {
+			files.push( entry.path );
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+
+	use crate::layered::LayeredFS;
+	use crate::layered::layers::memory::MemoryLayer;
+	use crate::layered::whiteout::whiteout_path;
+
+	#[test]
+	fn report_shadowed_files() {
+		let mut fs = LayeredFS::new();
+		fs.insert_layer( Arc::new(
+			MemoryLayer::new( "mod" )
+				.with( "materials/brick/wall01.vmt", "mod wall" )
+				.with( "Materials/Brick/Wall02.vmt", "wall02" )
+				.with( whiteout_path( "sound" ).as_str(), "" )
+		), false, &[] );
+		fs.insert_layer( Arc::new(
+			MemoryLayer::new( "game" )
+				.with( "materials/brick/wall01.vmt", "wall01" )
+				.with( "materials/brick/wall02.vmt", "wall02" )
+				.with( "materials/brick/wall03.vmt", "wall03" )
+				.with( "sound/theme.wav", "theme" )
+		), false, &[] );
+		fs.insert_layer( Arc::new( MemoryLayer::new( "platform" ).with( "sound/theme.wav", "theme" ) ), false, &[] );
+
+		let conflicts = fs.conflicts( "", false ).unwrap();
+		let paths: Vec<&str> = conflicts.iter().map( |conflict| conflict.path.as_str() ).collect();
+		assert_eq!( vec![ "materials/brick/wall01.vmt", "materials/brick/wall02.vmt", "sound/theme.wav" ], paths );
+		assert_eq!( "memory:mod", conflicts[0].winner.as_ref().unwrap().layer_name );
+		assert_eq!( vec![ "memory:game" ], conflicts[0].shadowed.iter().map( |copy| copy.layer_name.as_str() ).collect::<Vec<&str>>() );
+		// hidden by a whiteout, all copies are dead
+		assert!( conflicts[2].winner.is_none() );
+		assert_eq!( 2, conflicts[2].shadowed.len() );
+		assert_eq!( 0, conflicts[1].duplicates().count() );
+
+		let conflicts = fs.conflicts( "materials", true ).unwrap();
+		assert_eq!( 2, conflicts.len() );
+		assert_eq!( 0, conflicts[0].duplicates().count() );
+		assert_eq!( vec![ "memory:game" ], conflicts[1].duplicates().map( |copy| copy.layer_name.as_str() ).collect::<Vec<&str>>() );
+		assert_eq!( 1, conflicts[1].to_json()[ "duplicates" ].len() );
+	}
+}
@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+
+use crate::layered::{DirEntry, LayeredFS, normalize_path, trim_dir};
+
+/**
+ * A glob pattern over `/`-separated paths.
+ *
+ * Supports `*` ( anything but `/` ), `?` ( a single character ), character classes like `[abc]`, `[a-z]` and
+ * `[!abc]`, and `**` as a whole segment, which matches any number of directories.
+ * Like all lookups, matching is case-insensitive.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlobPattern {
+	segments: Vec<Segment>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+	/// `**`
+	AnyDirs,
+	Pattern( Vec<char> )
+}
+
+impl Segment {
+	fn is_literal( &self ) -> bool {
+		match self {
+			Segment::AnyDirs => false,
+			Segment::Pattern( chars ) => !chars.iter().any( |chr| matches!( chr, '*' | '?' | '[' ) )
+		}
+	}
+}
+
+impl GlobPattern {
+	pub fn new( pattern: &str ) -> Self {
+		let segments = trim_dir( pattern )
+			.split( |chr| chr == '/' || chr == '\\' )
+			.filter( |segment| !segment.is_empty() )
+			.map( |segment| match segment {
+				"**" => Segment::AnyDirs,
+				segment => Segment::Pattern( segment.to_lowercase().chars().collect() )
+			} )
+			.collect();
+		GlobPattern { segments }
+	}
+
+	/**
+	 * Checks whether a whole path matches this pattern.
+	 */
+	pub fn matches( &self, path: &str ) -> bool {
+		let path = normalize_path( path );
+		let parts: Vec<&str> = path.split( '/' )
+			.filter( |part| !part.is_empty() )
+			.collect();
+		matches_parts( &self.segments, &parts )
+	}
+}
+
+fn matches_parts( segments: &[Segment], parts: &[&str] ) -> bool {
+	match segments.first() {
+		None => parts.is_empty(),
+		Some( Segment::AnyDirs ) => ( 0 ..= parts.len() ).any( |skip| matches_parts( &segments[ 1 .. ], &parts[ skip .. ] ) ),
+		Some( Segment::Pattern( pattern ) ) => match parts.first() {
+			Some( part ) => matches_name( pattern, &part.chars().collect::<Vec<char>>() ) && matches_parts( &segments[ 1 .. ], &parts[ 1 .. ] ),
+			None => false
+		}
+	}
+}
+
+/**
+ * Matches a single path segment against a pattern segment.
+ */
+fn matches_name( pattern: &[char], name: &[char] ) -> bool {
+	match pattern.first() {
+		None => name.is_empty(),
+		Some( '*' ) => ( 0 ..= name.len() ).any( |skip| matches_name( &pattern[ 1 .. ], &name[ skip .. ] ) ),
+		Some( '?' ) => !name.is_empty() && matches_name( &pattern[ 1 .. ], &name[ 1 .. ] ),
+		Some( '[' ) => {
+			let Some( end ) = pattern.iter().skip( 2 ).position( |chr| *chr == ']' ).map( |index| index + 2 ) else {
+				// not a class, match the `[` literally
+				return name.first() == Some( &'[' ) && matches_name( &pattern[ 1 .. ], &name[ 1 .. ] );
+			};
+			let Some( chr ) = name.first() else {
+				return false;
+			};
+
+			let ( negated, class ) = match pattern[1] {
+				'!' | '^' => ( true, &pattern[ 2 .. end ] ),
+				_ => ( false, &pattern[ 1 .. end ] )
+			};
+			let mut found = false;
+			let mut index = 0;
+			while index < class.len() {
+				if index + 2 < class.len() && class[ index + 1 ] == '-' {
+					found |= ( class[index] ..= class[ index + 2 ] ).contains( chr );
+					index += 3;
+				} else {
+					found |= class[index] == *chr;
+					index += 1;
+				}
+			}
+			found != negated && matches_name( &pattern[ end + 1 .. ], &name[ 1 .. ] )
+		}
+		Some( chr ) => name.first() == Some( chr ) && matches_name( &pattern[ 1 .. ], &name[ 1 .. ] )
+	}
+}
+
+impl LayeredFS {
+	/**
+	 * Finds all files and directories matching a glob pattern, across all layers.
+	 * When more layers have the same path, the entry of the top-priority one is returned.
+	 */
+	pub fn glob( &self, pattern: &str ) -> Result<Vec<DirEntry>, Error> {
+		let pattern = GlobPattern::new( pattern );
+
+		// start from the deepest directory which doesn't need matching
+		let literal = pattern.segments.iter()
+			.take_while( |segment| segment.is_literal() )
+			.count()
+			.min( pattern.segments.len().saturating_sub( 1 ) );
+		let base = pattern.segments[ .. literal ].iter()
+			.map( |segment| match segment {
+				Segment::Pattern( chars ) => chars.iter().collect::<String>(),
+				Segment::AnyDirs => unreachable!()
+			} )
+			.collect::<Vec<String>>()
+			.join( "/" );
+
+		let mut found = BTreeMap::new();
+		match self.glob_dir( base.as_str(), &pattern.segments[ literal .. ], &mut found ) {
+			Err( err ) if err.kind() != ErrorKind::NotFound => return Err( err ),
+			_ => { }
+		}
+		Ok( found.into_values().collect() )
+	}
+
+	fn glob_dir( &self, dir: &str, segments: &[Segment], found: &mut BTreeMap<String, DirEntry> ) -> Result<(), Error> {
+		let Some( segment ) = segments.first() else {
+			return Ok(());
+		};
+
+		match segment {
+			Segment::AnyDirs => {
+				// `**` may match no directory at all
+				if segments.len() > 1 {
+					self.glob_dir( dir, &segments[ 1 .. ], found )?;
+				}
+				for entry in self.read_dir( dir )? {
+					if entry.is_dir {
+						self.glob_dir( entry.path.as_str(), segments, found )?;
+					}
+					if segments.len() == 1 {
+						found.entry( normalize_path( entry.path.as_str() ) ).or_insert( entry );
+					}
+				}
+			}
+			Segment::Pattern( pattern ) => {
+				for entry in self.read_dir( dir )? {
+					if !matches_name( pattern, &entry.name.to_lowercase().chars().collect::<Vec<char>>() ) {
+						continue;
+					}
+					if segments.len() == 1 {
+						found.entry( normalize_path( entry.path.as_str() ) ).or_insert( entry );
+					} else if entry.is_dir {
+						self.glob_dir( entry.path.as_str(), &segments[ 1 .. ], found )?;
+					}
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::layered::glob::GlobPattern;
+
+	#[test]
+	fn glob_patterns() {
+		assert!( GlobPattern::new( "materials/**/*.vmt" ).matches( "materials/brick/wall01.vmt" ) );
+		assert!( GlobPattern::new( "materials/**/*.vmt" ).matches( "materials/wall01.vmt" ) );
+		assert!( !GlobPattern::new( "materials/**/*.vmt" ).matches( "materials/brick/wall01.vtf" ) );
+		assert!( GlobPattern::new( "scripts/uscript/*" ).matches( "scripts/uscript/gamescript0.as" ) );
+		assert!( !GlobPattern::new( "scripts/uscript/*" ).matches( "scripts/uscript/sub/gamescript0.as" ) );
+		assert!( GlobPattern::new( "maps/map0?.bsp" ).matches( "maps/map01.bsp" ) );
+		assert!( GlobPattern::new( "maps/map[0-2][!a-z].bsp" ).matches( "maps/map15.bsp" ) );
+		assert!( !GlobPattern::new( "maps/map[0-2][!a-z].bsp" ).matches( "maps/map1a.bsp" ) );
+		assert!( GlobPattern::new( "Maps/*.BSP" ).matches( "maps\\MAP01.bsp" ) );
+	}
+}
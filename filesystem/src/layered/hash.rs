@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Read};
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+use tier0::format::e;
+use tier0::format::e::{E, KeyValue};
+
+use crate::layered::{ILayeredFile, Layer, normalize_path, not_utf8};
+use crate::layered::conflicts::list_files;
+
+/**
+ * Hashes the contents of a file with SHA-256, streaming it, returns the hash as lowercase hex.
+ */
+pub fn hash_file( file: &dyn ILayeredFile ) -> Result<String, Error> {
+	let mut stream = file.stream()?;
+	let mut hasher = Sha256::new();
+	let mut buf = [0u8; 64 * 1024];
+	loop {
+		match stream.read( &mut buf )? {
+			0 => break,
+			read => hasher.update( &buf[ .. read ] )
+		}
+	}
+	Ok( hex( hasher.finalize().as_slice() ) )
+}
+
+/**
+ * Same as `hash_file()`, for data already in memory.
+ */
+pub fn hash_bytes( data: &[u8] ) -> String {
+	hex( Sha256::digest( data ).as_slice() )
+}
+
+fn hex( bytes: &[u8] ) -> String {
+	bytes.iter().map( |byte| format!( "{byte:02x}" ) ).collect()
+}
+
+/**
+ * The expected hash and size of a file.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileHash {
+	/// SHA-256 of the contents, lowercase hex
+	pub sha256: String,
+	pub size: u64
+}
+
+/**
+ * The hashes of a set of files by normalized path, to verify content coming from untrusted places.
+ *
+ * ```text
+ * files:
+ *	.File:
+ *		path: materials/brick/wall01.vmt
+ *		sha256: 5d41402abc4b2a76b9719d911017c592...
+ *		size: 5
+ * ```
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct HashManifest {
+	pub files: BTreeMap<String, FileHash>
+}
+
+impl HashManifest {
+	/**
+	 * Hashes all files under a directory of a layer, whiteout markers aren't files.
+	 */
+	pub fn of_layer( layer: &dyn Layer, dirname: &str ) -> Result<HashManifest, Error> {
+		let mut paths = Vec::new();
+		list_files( layer, dirname, &mut paths )?;
+
+		let mut manifest = HashManifest::default();
+		for path in paths {
+			let file = layer.get_file( path.as_str() )?;
+			manifest.insert( path.as_str(), FileHash { sha256: hash_file( file.as_ref() )?, size: file.size() } );
+		}
+		Ok( manifest )
+	}
+
+	pub fn load( path: &Path ) -> Result<HashManifest, Error> {
+		let name = path.to_str().ok_or_else( || not_utf8( path ) )?;
+		let data = std::fs::read_to_string( path )?;
+		Ok( HashManifest::from_e( &e::loads( data.as_str(), name )? ) )
+	}
+
+	pub fn save( &self, path: &Path ) -> Result<(), Error> {
+		std::fs::write( path, e::dumps( &self.to_e() ) )
+	}
+
+	pub fn insert( &mut self, filename: &str, hash: FileHash ) {
+		self.files.insert( normalize_path( filename ), hash );
+	}
+
+	pub fn get( &self, filename: &str ) -> Option<&FileHash> {
+		self.files.get( normalize_path( filename ).as_str() )
+	}
+
+	/**
+	 * Checks data against the expected hash of a file, errors with `InvalidData` if it doesn't match,
+	 * and with `NotFound` if the manifest doesn't list the file.
+	 */
+	pub fn verify( &self, filename: &str, data: &[u8] ) -> Result<(), Error> {
+		let Some( expected ) = self.get( filename ) else {
+			return Err( Error::new( ErrorKind::NotFound, format!( "{filename} is not in the hash manifest" ) ) );
+		};
+		if expected.size != data.len() as u64 || expected.sha256 != hash_bytes( data ) {
+			return Err( Error::new( ErrorKind::InvalidData, format!( "{filename} doesn't match its hash in the manifest" ) ) );
+		}
+		Ok(())
+	}
+
+	pub fn from_e( manifest: &E ) -> HashManifest {
+		let string = |value: &E, key: &str| value.get( key ).and_then( |value| value.as_str() ).unwrap_or_default().to_string();
+
+		let mut files = BTreeMap::new();
+		for file in manifest.get( "files" ).and_then( |files| files.as_list() ).into_iter().flatten() {
+			files.insert( normalize_path( string( file, "path" ).as_str() ), FileHash {
+				sha256: string( file, "sha256" ).to_lowercase(),
+				size: string( file, "size" ).parse().unwrap_or( 0 )
+			} );
+		}
+		HashManifest { files }
+	}
+
+	pub fn to_e( &self ) -> E {
+		let files = self.files.iter()
+			.map( |( path, hash )| E::Object {
+				class: "File".to_string(),
+				fields: vec![
+					KeyValue::new( "path", E::String { val: path.clone() } ),
+					KeyValue::new( "sha256", E::String { val: hash.sha256.clone() } ),
+					KeyValue::new( "size", E::Integer { val: hash.size as i64 } )
+				]
+			} )
+			.collect();
+		E::Map { values: vec![ KeyValue::new( "files", E::List { values: files } ) ] }
+	}
+}
@@ -1,10 +1,14 @@
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{Error, Read};
-use std::path::PathBuf;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
 use uuid::Uuid;
 
 use crate::layered::*;
+use crate::layered::stream::{LayeredStream, SectionStream};
 
 pub struct FolderLayerProvider { }
 impl LayerProvider for FolderLayerProvider {
@@ -12,42 +16,228 @@ impl LayerProvider for FolderLayerProvider {
 		path.is_dir()
 	}
 
-	fn create<'a>( &self, path: PathBuf ) -> Result<Arc<dyn Layer + 'a>, LayeredFSError> {
+	fn create( &self, path: PathBuf ) -> Result<Arc<dyn Layer>, LayeredFSError> {
 		Ok( Arc::new( FolderLayer::new( path ) ) )
 	}
 }
 
+/**
+ * A layer over a folder on disk, files are looked up case-insensitively.
+ *
+ * The listings of the directories looked up are cached, and each is read again when the modification time
+ * of its directory changes, so files added or removed behind the back of the layer are seen too.
+ * Writes through the layer drop the listings of the directories they touch.
+ *
+ * Paths are sanitized with `sanitize_path()`, and must really be inside the root once symlinks are followed,
+ * lookups of paths which escape it fail with `LayeredFSError::EscapesRoot`.
+ * This is checked when a directory on the way was listed again, or the path goes through a symlink,
+ * as a directory can only be swapped for one by changing its parent.
+ */
 pub struct FolderLayer {
 	path: PathBuf,
-	uuid: Uuid
+	/// canonical version of `path`, for the escape checks
+	root: PathBuf,
+	uuid: Uuid,
+	writable: bool,
+	/// normalized directory -> its listing, only locked to get or store a listing, never while touching the disk
+	index: Mutex<HashMap<String, Arc<Listing>>>
+}
+
+struct Listing {
+	/// modification time of the directory when it was listed, `None` if it didn't exist
+	modified: Option<SystemTime>,
+	/// lowercase name -> entry on disk
+	names: HashMap<String, IndexEntry>
+}
+
+struct IndexEntry {
+	name: String,
+	/// symlinks may point anywhere, and are always checked
+	symlink: bool
 }
 
 impl FolderLayer {
-	pub fn new<'a>( path: PathBuf ) -> FolderLayer {
-		FolderLayer { path, uuid: Uuid::new_v4() }
+	pub fn new( path: PathBuf ) -> FolderLayer {
+		FolderLayer {
+			root: path.canonicalize().unwrap_or_else( |_| path.clone() ),
+			path,
+			uuid: Uuid::new_v4(),
+			writable: false,
+			index: Mutex::new( HashMap::new() )
+		}
+	}
+
+	/**
+	 * Creates a folder layer which can be written to.
+	 */
+	pub fn writable( path: PathBuf ) -> FolderLayer {
+		FolderLayer { writable: true, ..FolderLayer::new( path ) }
+	}
+
+	/**
+	 * Drops the cached directory listings, for filesystems whose modification times are too coarse
+	 * to notice changes made from outside.
+	 */
+	pub fn refresh( &self ) {
+		self.index.lock().unwrap().clear();
+	}
+
+	/**
+	 * Drops the cached listings of a file's directories, and of the file itself and everything below it
+	 * in case it's a directory, after writing to it.
+	 */
+	fn invalidate( &self, filename: &str ) {
+		let Ok( filename ) = sanitize_path( filename ) else {
+			return;
+		};
+		let below = |dir: &str, parent: &str| parent.is_empty() || dir == parent || dir.starts_with( format!( "{parent}/" ).as_str() );
+		self.index.lock().unwrap().retain( |dir, _| !below( filename.as_str(), dir ) && !below( dir, filename.as_str() ) );
+	}
+
+	/**
+	 * Gets the listing of a directory, reading it again if the directory was modified since.
+	 * Also returns whether it was read again.
+	 */
+	fn listing( &self, path: &Path, dir: &str ) -> ( Arc<Listing>, bool ) {
+		let modified = std::fs::metadata( path ).and_then( |meta| meta.modified() ).ok();
+		if let Some( listing ) = self.index.lock().unwrap().get( dir ).filter( |listing| listing.modified == modified ) {
+			return ( listing.clone(), false );
+		}
+
+		let listing = Arc::new( list_dir( path, modified ) );
+		self.index.lock().unwrap().insert( dir.to_string(), listing.clone() );
+		( listing, true )
+	}
+
+	/**
+	 * Finds the path on disk of a file, returns whether it exists too.
+	 * Segments past the last existing directory are kept normalized.
+	 */
+	fn lookup( &self, filename: &str ) -> Result<( PathBuf, bool ), Error> {
+		let filename = sanitize_path( filename )?;
+		let mut path = self.path.clone();
+		let mut dir = String::new();
+		let mut found = true;
+		// whether anything on the way changed since it was last checked against the root
+		let mut changed = false;
+
+		for segment in filename.split( '/' ).filter( |segment| !segment.is_empty() ) {
+			let listing = match found {
+				true => {
+					let ( listing, listed ) = self.listing( &path, dir.as_str() );
+					changed |= listed;
+					Some( listing )
+				}
+				false => None
+			};
+			match listing.as_ref().and_then( |listing| listing.names.get( segment ) ) {
+				Some( entry ) => {
+					changed |= entry.symlink;
+					path.push( entry.name.as_str() );
+				}
+				None => {
+					found = false;
+					path.push( segment );
+				}
+			}
+			dir = join_path( dir.as_str(), segment );
+		}
+
+		if changed {
+			self.check_inside( &path, filename.as_str() )?;
+		}
+		Ok( ( path, found ) )
+	}
+
+	/**
+	 * Checks that a path ends up inside the root, by where its deepest existing ancestor really is.
+	 */
+	fn check_inside( &self, path: &Path, filename: &str ) -> Result<(), Error> {
+		let existing = path.ancestors()
+			.take_while( |ancestor| ancestor.starts_with( &self.path ) )
+			.find( |ancestor| ancestor.symlink_metadata().is_ok() );
+		let inside = match existing {
+			// dangling symlinks can't be canonicalized, and could point anywhere
+			Some( ancestor ) => ancestor.canonicalize().is_ok_and( |real| real.starts_with( &self.root ) ),
+			// the root doesn't exist yet, so there's nothing in it which could lead out
+			None => true
+		};
+		match inside {
+			true => Ok(()),
+			false => Err( LayeredFSError::EscapesRoot( filename.to_string() ).into() )
+		}
+	}
+
+	fn check_writable( &self, filename: &str ) -> Result<PathBuf, Error> {
+		if !self.writable {
+			return Err( read_only( &self.meta(), filename ) );
+		}
+		Ok( self.lookup( filename )?.0 )
 	}
 }
 
-impl<'a> Layer<'a> for FolderLayer {
+/**
+ * Lists the names in a directory by their lowercase version, a missing directory has no names.
+ */
+fn list_dir( path: &Path, modified: Option<SystemTime> ) -> Listing {
+	let mut names = HashMap::new();
+	if let Ok( entries ) = std::fs::read_dir( path ) {
+		for entry in entries.flatten() {
+			let name = entry.file_name().to_string_lossy().to_string();
+			let lower = name.to_lowercase();
+			// if more names only differ by case, the lowercase one wins
+			if lower == name || !names.contains_key( &lower ) {
+				let symlink = entry.file_type().is_ok_and( |typ| typ.is_symlink() );
+				names.insert( lower, IndexEntry { name, symlink } );
+			}
+		}
+	}
+	Listing { modified, names }
+}
+
+impl Layer for FolderLayer {
+	/**
+	 * Paths which escape the root resolve to the root itself.
+	 */
 	fn resolve( &self, filename: &str ) -> PathBuf {
-		let mut path = self.path.clone();
-		path.push( filename );
-		path
+		self.lookup( filename ).map_or_else( |_| self.path.clone(), |( path, _ )| path )
 	}
 
 	fn contains( &self, filename: &str ) -> bool {
-		self.resolve( filename ).exists()
+		self.lookup( filename ).is_ok_and( |( _, found )| found )
 	}
 
 	fn get_file(&self, filename: &str ) -> Result<LayeredFile, Error> {
-		let file = File::open( self.resolve( filename ) )?;
+		let file = File::open( self.lookup( filename )?.0 )?;
 		Ok( Box::new( FolderLayeredFile {
-			file: file,
+			size: file.metadata()?.len(),
+			file: Arc::new( Mutex::new( file ) ),
 			path: filename.to_string(),
-			layer: &self.uuid
+			layer: self.uuid
 		}))
 	}
 
+	fn locate( &self, filename: &str ) -> Result<ReadLocation, Error> {
+		let path = self.lookup( filename )?.0;
+		let len = std::fs::metadata( &path )?.len();
+		Ok( ReadLocation::Section { archive: path, offset: 0, len, preload: Vec::new() } )
+	}
+
+	fn read_dir( &self, dirname: &str ) -> Result<Vec<DirEntry>, Error> {
+		let mut entries = Vec::new();
+		for entry in std::fs::read_dir( self.lookup( dirname )?.0 )? {
+			let entry = entry?;
+			let name = entry.file_name().to_string_lossy().to_string();
+			entries.push( DirEntry {
+				path: join_path( dirname, name.as_str() ),
+				name,
+				is_dir: entry.file_type()?.is_dir(),
+				layer: self.uuid
+			} );
+		}
+		Ok( entries )
+	}
+
 	fn meta( &self ) -> LayerMeta {
 		LayerMeta {
 			origin: None,
@@ -59,34 +249,72 @@ impl<'a> Layer<'a> for FolderLayer {
 	fn uuid( &self ) -> &Uuid {
 		&self.uuid
 	}
+
+	fn is_writable( &self ) -> bool {
+		self.writable
+	}
+
+	fn write( &self, filename: &str, data: &[u8] ) -> Result<(), Error> {
+		let path = self.check_writable( filename )?;
+		if let Some( parent ) = path.parent() {
+			std::fs::create_dir_all( parent )?;
+		}
+		let result = std::fs::write( path, data );
+		self.invalidate( filename );
+		result
+	}
+
+	fn create( &self, filename: &str ) -> Result<(), Error> {
+		let path = self.check_writable( filename )?;
+		if let Some( parent ) = path.parent() {
+			std::fs::create_dir_all( parent )?;
+		}
+		let result = File::options().write( true ).create_new( true ).open( path );
+		self.invalidate( filename );
+		result.map( |_| () )
+	}
+
+	fn remove( &self, filename: &str ) -> Result<(), Error> {
+		let path = self.check_writable( filename )?;
+		let result = match path.is_dir() {
+			true => std::fs::remove_dir( path ),
+			false => std::fs::remove_file( path )
+		};
+		self.invalidate( filename );
+		result
+	}
+
+	fn rename( &self, from: &str, to: &str ) -> Result<(), Error> {
+		let from_path = self.check_writable( from )?;
+		let to_path = self.check_writable( to )?;
+		if let Some( parent ) = to_path.parent() {
+			std::fs::create_dir_all( parent )?;
+		}
+		let result = std::fs::rename( from_path, to_path );
+		self.invalidate( from );
+		self.invalidate( to );
+		result
+	}
 }
 
 
-struct FolderLayeredFile<'a> {
-	file: File,
+struct FolderLayeredFile {
+	file: Arc<Mutex<File>>,
+	size: u64,
 	path: String,
-	layer: &'a Uuid
+	layer: Uuid
 }
 
-impl<'a> ILayeredFile<'a> for FolderLayeredFile<'a> {
+impl ILayeredFile for FolderLayeredFile {
 	fn size(&self) -> u64 {
-		self.file.metadata().unwrap().len()
-	}
-
-	fn read(&self) -> Result<Vec<u8>, Error> {
-		let mut file2 = self.file.try_clone()?;
-		let mut vec: Vec<u8> = Vec::new();
-		file2.read_to_end( &mut vec )?;
-		Ok( vec )
+		self.size
 	}
 
-	fn read_string(&self) -> Result<String, Error> {
-		let mut string = String::new();
-		self.file.try_clone().unwrap().read_to_string( &mut string )?;
-		Ok( string )
+	fn stream(&self) -> Result<LayeredStream, Error> {
+		Ok( Box::new( SectionStream::new( self.file.clone(), 0, self.size ) ) )
 	}
 
-	fn layer(&self) -> &'a Uuid {
+	fn layer(&self) -> Uuid {
 		self.layer
 	}
 
@@ -94,3 +322,150 @@ impl<'a> ILayeredFile<'a> for FolderLayeredFile<'a> {
 		self.path.clone()
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use std::fs::{create_dir_all, remove_dir_all, write};
+
+	use std::io::ErrorKind;
+
+	use crate::layered::{Layer, LayeredFSError};
+	use crate::layered::layers::folder::FolderLayer;
+	use crate::layered::tests::scratch_dir;
+
+	#[test]
+	fn case_insensitive_lookups() {
+		let root = scratch_dir( "folder_case" );
+		create_dir_all( root.join( "Materials/Brick" ) ).unwrap();
+		write( root.join( "Materials/Brick/Wall01.VMT" ), "brick" ).unwrap();
+
+		let layer = FolderLayer::writable( root.clone() );
+		assert!( layer.contains( "materials\\brick//wall01.vmt" ) );
+		assert!( layer.contains( "./MATERIALS/Brick/wall01.vmt/" ) );
+		assert_eq!( root.join( "Materials/Brick/Wall01.VMT" ), layer.resolve( "materials/brick/wall01.vmt" ) );
+		assert_eq!( "brick", layer.get_file( "MATERIALS/BRICK/WALL01.VMT" ).unwrap().read_string().unwrap() );
+
+		// new files keep the case of the existing directories, and are seen right away
+		assert!( !layer.contains( "materials/brick/wall02.vmt" ) );
+		layer.write( "materials/brick/Wall02.vmt", b"brick" ).unwrap();
+		assert!( root.join( "Materials/Brick/wall02.vmt" ).exists() );
+		assert!( layer.contains( "materials/brick/wall02.vmt" ) );
+
+		// and so are the ones added or removed from outside
+		write( root.join( "Materials/Brick/Wall03.vmt" ), "brick" ).unwrap();
+		assert!( layer.contains( "materials/brick/wall03.vmt" ) );
+		std::fs::remove_file( root.join( "Materials/Brick/Wall01.VMT" ) ).unwrap();
+		assert!( !layer.contains( "materials/brick/wall01.vmt" ) );
+
+		remove_dir_all( root ).unwrap();
+	}
+
+	#[test]
+	fn writes_drop_their_listings() {
+		let root = scratch_dir( "folder_listings" );
+		create_dir_all( root.join( "cfg" ) ).unwrap();
+		create_dir_all( root.join( "maps/Workshop" ) ).unwrap();
+		write( root.join( "maps/Workshop/map01.bsp" ), "map01" ).unwrap();
+
+		let layer = FolderLayer::writable( root.clone() );
+		assert!( layer.contains( "maps/workshop/map01.bsp" ) );
+		assert!( !layer.contains( "cfg/config.cfg" ) );
+		let listed = |dir: &str| layer.index.lock().unwrap().contains_key( dir );
+		assert!( listed( "" ) && listed( "maps" ) && listed( "maps/workshop" ) && listed( "cfg" ) );
+
+		// only the directories on the way to the written file are read again
+		layer.write( "cfg/config.cfg", b"volume 1" ).unwrap();
+		assert!( !listed( "" ) && !listed( "cfg" ) );
+		assert!( listed( "maps" ) && listed( "maps/workshop" ) );
+		assert!( layer.contains( "cfg/config.cfg" ) );
+
+		// and everything below a removed directory
+		layer.remove( "maps/workshop/map01.bsp" ).unwrap();
+		layer.remove( "maps/workshop" ).unwrap();
+		assert!( !listed( "maps" ) && !listed( "maps/workshop" ) );
+		assert!( !layer.contains( "maps/workshop" ) );
+
+		remove_dir_all( root ).unwrap();
+	}
+
+	fn escapes( result: Result<Vec<u8>, std::io::Error> ) -> bool {
+		let err = result.unwrap_err();
+		err.kind() == ErrorKind::PermissionDenied && matches!( err.get_ref().and_then( |err| err.downcast_ref() ), Some( LayeredFSError::EscapesRoot(_) ) )
+	}
+
+	#[test]
+	fn parent_segments_stay_inside() {
+		let root = scratch_dir( "folder_sandbox" );
+		create_dir_all( root.join( "layer/cfg" ) ).unwrap();
+		write( root.join( "layer/cfg/config.cfg" ), "config" ).unwrap();
+		write( root.join( "secret.txt" ), "secret" ).unwrap();
+
+		let layer = FolderLayer::writable( root.join( "layer" ) );
+		assert!( layer.contains( "cfg/../cfg/./config.cfg" ) );
+		assert!( !layer.contains( "../secret.txt" ) );
+		assert!( escapes( layer.get_file( "cfg/../../secret.txt" ).and_then( |file| file.read() ) ) );
+		assert_eq!( ErrorKind::PermissionDenied, layer.write( "..\\..\\evil.txt", b"evil" ).unwrap_err().kind() );
+		assert_eq!( ErrorKind::PermissionDenied, layer.read_dir( ".." ).unwrap_err().kind() );
+
+		// absolute paths are relative to the root of the layer, drives are refused
+		let absolute = root.join( "secret.txt" ).to_string_lossy().to_string();
+		assert!( !layer.contains( absolute.as_str() ) );
+		assert!( layer.resolve( absolute.as_str() ).starts_with( root.join( "layer" ) ) );
+		assert!( matches!(
+			layer.get_file( "C:\\Windows\\win.ini" ).err().unwrap().into_inner().unwrap().downcast::<LayeredFSError>().map( |err| *err ),
+			Ok( LayeredFSError::AbsolutePath(_) )
+		) );
+
+		remove_dir_all( root ).unwrap();
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn symlinks_stay_inside() {
+		use std::os::unix::fs::symlink;
+
+		let root = scratch_dir( "folder_symlink" );
+		create_dir_all( root.join( "layer/materials" ) ).unwrap();
+		create_dir_all( root.join( "outside" ) ).unwrap();
+		write( root.join( "outside/secret.txt" ), "secret" ).unwrap();
+		write( root.join( "layer/materials/wall.vmt" ), "wall" ).unwrap();
+		symlink( root.join( "outside" ), root.join( "layer/linked" ) ).unwrap();
+		symlink( root.join( "layer/materials" ), root.join( "layer/textures" ) ).unwrap();
+		symlink( root.join( "nowhere" ), root.join( "layer/dangling" ) ).unwrap();
+
+		let layer = FolderLayer::writable( root.join( "layer" ) );
+		assert!( layer.contains( "textures/wall.vmt" ) );
+		assert!( !layer.contains( "linked/secret.txt" ) );
+		assert!( escapes( layer.get_file( "linked/secret.txt" ).and_then( |file| file.read() ) ) );
+		assert_eq!( ErrorKind::PermissionDenied, layer.write( "linked/evil.txt", b"evil" ).unwrap_err().kind() );
+		assert_eq!( ErrorKind::PermissionDenied, layer.write( "dangling", b"evil" ).unwrap_err().kind() );
+		assert!( !root.join( "outside/evil.txt" ).exists() );
+		assert!( !root.join( "nowhere" ).exists() );
+
+		remove_dir_all( root ).unwrap();
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn swapped_directories_stay_inside() {
+		use std::os::unix::fs::symlink;
+
+		let root = scratch_dir( "folder_swap" );
+		create_dir_all( root.join( "layer/cfg" ) ).unwrap();
+		create_dir_all( root.join( "outside" ) ).unwrap();
+		write( root.join( "layer/cfg/config.cfg" ), "config" ).unwrap();
+		write( root.join( "outside/config.cfg" ), "secret" ).unwrap();
+
+		let layer = FolderLayer::writable( root.join( "layer" ) );
+		assert_eq!( "config", layer.get_file( "cfg/config.cfg" ).unwrap().read_string().unwrap() );
+
+		// the listings are already cached when the directory becomes a symlink
+		remove_dir_all( root.join( "layer/cfg" ) ).unwrap();
+		symlink( root.join( "outside" ), root.join( "layer/cfg" ) ).unwrap();
+		assert!( escapes( layer.get_file( "cfg/config.cfg" ).and_then( |file| file.read() ) ) );
+		assert_eq!( ErrorKind::PermissionDenied, layer.write( "cfg/evil.cfg", b"evil" ).unwrap_err().kind() );
+		assert!( !root.join( "outside/evil.cfg" ).exists() );
+
+		remove_dir_all( root ).unwrap();
+	}
+}
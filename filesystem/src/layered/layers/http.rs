@@ -0,0 +1,566 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{debug, warn};
+use uuid::Uuid;
+
+use crate::layered::*;
+use crate::layered::hash::{HashManifest, hash_file};
+use crate::layered::layers::folder::FolderLayer;
+use crate::layered::stream::LayeredStream;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs( 10 );
+const READ_TIMEOUT: Duration = Duration::from_secs( 30 );
+const MAX_REDIRECTS: usize = 5;
+/// the most memory reserved for a body up front, whatever its `Content-Length` says
+const MAX_RESERVE: u64 = 16 * 1024 * 1024;
+
+/**
+ * Creates `HttpLayer`s for `http://` URLs, caching each server in its own folder under `cache`.
+ * It isn't registered by default, as it needs somewhere to cache to, see `LayeredFS::add_layer_provider()`.
+ */
+pub struct HttpLayerProvider {
+	cache: PathBuf
+}
+
+impl HttpLayerProvider {
+	pub fn new( cache: PathBuf ) -> HttpLayerProvider {
+		HttpLayerProvider { cache }
+	}
+}
+
+impl LayerProvider for HttpLayerProvider {
+	fn supports( &self, path: &PathBuf ) -> bool {
+		path.to_str().is_some_and( |path| path.starts_with( "http://" ) )
+	}
+
+	fn create( &self, path: PathBuf ) -> Result<Arc<dyn Layer>, LayeredFSError> {
+		let url = path.to_str().unwrap();
+		let folder: String = url.trim_start_matches( "http://" )
+			.chars()
+			.map( |chr| if chr.is_ascii_alphanumeric() || chr == '.' || chr == '-' { chr } else { '_' } )
+			.collect();
+		Ok( Arc::new( HttpLayer::new( url, self.cache.join( folder ) )? ) )
+	}
+}
+
+/**
+ * A layer which downloads the files it doesn't have from an HTTP server, like `sv_downloadurl` fast-downloads,
+ * and caches them into a writable folder, where they are read from afterward.
+ *
+ * Files are requested by their normalized path under the base URL, downloaded files are read from the cache,
+ * and a 404 or a failed request is remembered, so each file is only asked for once, until `forget_missing()`.
+ * As a lookup of a file which isn't known yet hits the network, it's meant to be the last of the stack.
+ * Whiteouts are only read from the cache.
+ *
+ * With a hash manifest, only the files it lists are downloaded, downloads which don't match their hash
+ * are refused, and cached files are checked the first time they are used.
+ * Only plain `http://` is supported.
+ */
+pub struct HttpLayer {
+	url: HttpUrl,
+	cache: FolderLayer,
+	manifest: Option<HashManifest>,
+	uuid: Uuid,
+	/// normalized paths the server doesn't have, or which it couldn't be asked for
+	missing: Mutex<HashSet<String>>,
+	/// normalized paths whose cached copy matched the manifest
+	verified: Mutex<HashSet<String>>
+}
+
+impl HttpLayer {
+	/**
+	 * Creates a layer over a base URL like `http://fastdl.example.com/borealis`, caching into a folder.
+	 */
+	pub fn new( url: &str, cache: PathBuf ) -> Result<HttpLayer, LayeredFSError> {
+		std::fs::create_dir_all( &cache ).map_err( |_| LayeredFSError::NotFound( cache.clone() ) )?;
+		Ok( HttpLayer {
+			url: HttpUrl::parse( url )?,
+			cache: FolderLayer::writable( cache ),
+			manifest: None,
+			uuid: Uuid::new_v4(),
+			missing: Mutex::new( HashSet::new() ),
+			verified: Mutex::new( HashSet::new() )
+		} )
+	}
+
+	/**
+	 * Verifies the files against a manifest, and only downloads the ones it lists.
+	 */
+	pub fn with_manifest( mut self, manifest: HashManifest ) -> HttpLayer {
+		self.manifest = Some( manifest );
+		self
+	}
+
+	pub fn manifest( &self ) -> Option<&HashManifest> {
+		self.manifest.as_ref()
+	}
+
+	/**
+	 * Forgets which files the server didn't have or couldn't be asked for, so they are requested again.
+	 */
+	pub fn forget_missing( &self ) {
+		self.missing.lock().unwrap().clear();
+	}
+
+	/**
+	 * Downloads a file into the cache, replacing the cached copy if there is one.
+	 */
+	pub fn download( &self, filename: &str ) -> Result<(), Error> {
+		let path = sanitize_path( filename )?;
+		// checked before requesting, so unlisted files never reach the server
+		let expected = match &self.manifest {
+			Some( manifest ) => Some( manifest.get( path.as_str() ).ok_or_else( || self.not_found( filename ) )?.size ),
+			None => None
+		};
+
+		debug!( "Downloading {path} from {}", self.url );
+		let Some( data ) = self.url.get( path.as_str(), expected )? else {
+			self.missing.lock().unwrap().insert( path );
+			return Err( self.not_found( filename ) );
+		};
+		if let Some( manifest ) = &self.manifest {
+			manifest.verify( path.as_str(), &data )?;
+			self.verified.lock().unwrap().insert( path.clone() );
+		}
+		self.cache.write( path.as_str(), &data )
+	}
+
+	/**
+	 * Makes sure the cache has a usable copy of a file, downloading it if needed.
+	 */
+	fn fetch( &self, filename: &str ) -> Result<(), Error> {
+		let path = sanitize_path( filename )?;
+		if self.cache.contains( path.as_str() ) {
+			return match self.manifest.is_some() && !self.verified.lock().unwrap().contains( &path ) {
+				true => self.check_cached( path.as_str() ),
+				false => Ok(())
+			};
+		}
+		if self.missing.lock().unwrap().contains( &path ) {
+			return Err( self.not_found( filename ) );
+		}
+		// so a server which is down doesn't block every lookup until it times out
+		self.download( path.as_str() ).inspect_err( |_| { self.missing.lock().unwrap().insert( path ); } )
+	}
+
+	/**
+	 * Checks a cached file against the manifest, downloading it again if it doesn't match.
+	 */
+	fn check_cached( &self, path: &str ) -> Result<(), Error> {
+		let file = self.cache.get_file( path )?;
+		let matches = self.manifest.as_ref()
+			.and_then( |manifest| manifest.get( path ) )
+			.is_some_and( |expected| expected.size == file.size() && hash_file( file.as_ref() ).is_ok_and( |hash| hash == expected.sha256 ) );
+		drop( file );
+
+		match matches {
+			true => {
+				self.verified.lock().unwrap().insert( path.to_string() );
+				Ok(())
+			}
+			false => {
+				warn!( "Cached copy of {path} from {} doesn't match the manifest, downloading it again", self.url );
+				self.download( path )
+			}
+		}
+	}
+
+	fn not_found( &self, filename: &str ) -> Error {
+		Error::new( ErrorKind::NotFound, format!( "{} doesn't have {filename}", self.url ) )
+	}
+}
+
+impl Layer for HttpLayer {
+	/**
+	 * Resolves to where the file is, or would be, cached.
+	 */
+	fn resolve( &self, filename: &str ) -> PathBuf {
+		self.cache.resolve( filename )
+	}
+
+	/**
+	 * With a manifest, files are looked up in it without requests, otherwise the ones which
+	 * aren't cached or known to be missing are downloaded.
+	 */
+	fn contains( &self, filename: &str ) -> bool {
+		match &self.manifest {
+			Some( manifest ) => self.cache.contains( filename ) || manifest.get( filename ).is_some(),
+			None => self.fetch( filename ).is_ok()
+		}
+	}
+
+	fn get_file( &self, filename: &str ) -> Result<LayeredFile, Error> {
+		self.fetch( filename )?;
+		Ok( Box::new( HttpLayeredFile { file: self.cache.get_file( filename )?, layer: self.uuid } ) )
+	}
+
+	/**
+	 * Only locates files which are already cached and checked, anything else has to be downloaded first,
+	 * which `get_file()` does, so the `AsyncLoader` falls back to it on its workers.
+	 */
+	fn locate( &self, filename: &str ) -> Result<ReadLocation, Error> {
+		let path = sanitize_path( filename )?;
+		let ready = self.cache.contains( path.as_str() )
+			&& ( self.manifest.is_none() || self.verified.lock().unwrap().contains( &path ) );
+		match ready {
+			true => self.cache.locate( filename ),
+			false => Err( Error::new( ErrorKind::Unsupported, format!( "{filename} from {} has to be downloaded first", self.url ) ) )
+		}
+	}
+
+	/**
+	 * Lists what's cached, and what the manifest has, the server isn't asked.
+	 */
+	fn read_dir( &self, dirname: &str ) -> Result<Vec<DirEntry>, Error> {
+		let cached = self.cache.read_dir( dirname );
+		let mut entries: BTreeMap<String, DirEntry> = BTreeMap::new();
+		for entry in cached.iter().flatten() {
+			entries.insert( entry.name.to_lowercase(), DirEntry { layer: self.uuid, ..entry.clone() } );
+		}
+
+		let dir = normalize_path( dirname );
+		let prefix = match dir.as_str() {
+			"" => String::new(),
+			dir => format!( "{dir}/" )
+		};
+		let mut listed = false;
+		for path in self.manifest.iter().flat_map( |manifest| manifest.files.keys() ) {
+			let Some( rest ) = path.strip_prefix( prefix.as_str() ) else {
+				continue;
+			};
+			listed = true;
+			let name = rest.split( '/' ).next().unwrap();
+			entries.entry( name.to_string() ).or_insert_with( || DirEntry {
+				path: join_path( dirname, name ),
+				name: name.to_string(),
+				is_dir: rest.contains( '/' ),
+				layer: self.uuid
+			} );
+		}
+
+		match cached {
+			Err( err ) if !listed => Err( err ),
+			_ => Ok( entries.into_values().collect() )
+		}
+	}
+
+	fn meta( &self ) -> LayerMeta {
+		LayerMeta {
+			origin: None,
+			filename: self.url.to_string(),
+			size: None
+		}
+	}
+
+	fn uuid( &self ) -> &Uuid {
+		&self.uuid
+	}
+
+	fn hides( &self, filename: &str ) -> bool {
+		self.cache.hides( filename )
+	}
+}
+
+/**
+ * A cached file, reported as coming from the `HttpLayer`.
+ */
+struct HttpLayeredFile {
+	file: LayeredFile,
+	layer: Uuid
+}
+
+impl ILayeredFile for HttpLayeredFile {
+	fn size( &self ) -> u64 {
+		self.file.size()
+	}
+
+	fn stream( &self ) -> Result<LayeredStream, Error> {
+		self.file.stream()
+	}
+
+	fn layer( &self ) -> Uuid {
+		self.layer
+	}
+
+	fn path( &self ) -> String {
+		self.file.path()
+	}
+}
+
+/**
+ * An `http://host[:port][/path]` URL.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct HttpUrl {
+	host: String,
+	port: u16,
+	/// without a trailing slash
+	path: String
+}
+
+impl HttpUrl {
+	fn parse( url: &str ) -> Result<HttpUrl, LayeredFSError> {
+		let Some( rest ) = url.strip_prefix( "http://" ) else {
+			return Err( LayeredFSError::Unsupported( url.to_string() ) );
+		};
+		let ( authority, path ) = rest.split_at( rest.find( '/' ).unwrap_or( rest.len() ) );
+		let ( host, port ) = match authority.rsplit_once( ':' ) {
+			Some( ( host, port ) ) => ( host, port.parse().map_err( |_| LayeredFSError::Unsupported( url.to_string() ) )? ),
+			None => ( authority, 80 )
+		};
+		if host.is_empty() {
+			return Err( LayeredFSError::Unsupported( url.to_string() ) );
+		}
+		Ok( HttpUrl { host: host.to_string(), port, path: path.trim_end_matches( '/' ).to_string() } )
+	}
+
+	/**
+	 * Downloads a file under this URL, returns `None` if the server doesn't have it.
+	 * With a `limit`, bodies larger than it are refused.
+	 */
+	fn get( &self, filename: &str, limit: Option<u64> ) -> Result<Option<Vec<u8>>, Error> {
+		let mut url = self.clone();
+		let mut target = format!( "{}/{}", self.path, encode( filename ) );
+		for _ in 0 ..= MAX_REDIRECTS {
+			match url.request( target.as_str(), limit.unwrap_or( u64::MAX ) )? {
+				Response::Ok( data ) => return Ok( Some( data ) ),
+				Response::NotFound => return Ok( None ),
+				Response::Redirect( location ) => match location.strip_prefix( "http://" ) {
+					Some( _ ) => {
+						let redirect = HttpUrl::parse( location.as_str() )?;
+						target = match redirect.path.as_str() {
+							"" => "/".to_string(),
+							path => path.to_string()
+						};
+						url = HttpUrl { path: String::new(), ..redirect };
+					}
+					None if location.starts_with( '/' ) => target = location,
+					None => return Err( Error::new( ErrorKind::Unsupported, format!( "{self} redirected {filename} to {location}" ) ) )
+				}
+			}
+		}
+		Err( Error::new( ErrorKind::Other, format!( "{self} redirected {filename} too many times" ) ) )
+	}
+
+	/**
+	 * Sends one `GET` request, as HTTP/1.0 so the body is never chunked.
+	 */
+	fn request( &self, target: &str, limit: u64 ) -> Result<Response, Error> {
+		let addr = ( self.host.as_str(), self.port ).to_socket_addrs()?
+			.next()
+			.ok_or_else( || Error::new( ErrorKind::NotFound, format!( "{} doesn't resolve", self.host ) ) )?;
+		let mut stream = TcpStream::connect_timeout( &addr, CONNECT_TIMEOUT )?;
+		stream.set_read_timeout( Some( READ_TIMEOUT ) )?;
+		write!( stream, "GET {target} HTTP/1.0\r\nHost: {}:{}\r\nUser-Agent: borealis\r\nConnection: close\r\n\r\n", self.host, self.port )?;
+
+		let mut reader = BufReader::new( stream );
+		let mut line = String::new();
+		reader.read_line( &mut line )?;
+		let status: u16 = line.split_whitespace()
+			.nth( 1 )
+			.and_then( |status| status.parse().ok() )
+			.ok_or_else( || Error::new( ErrorKind::InvalidData, format!( "invalid response from {self}: {}", line.trim() ) ) )?;
+
+		let mut length: Option<u64> = None;
+		let mut location: Option<String> = None;
+		loop {
+			line.clear();
+			if reader.read_line( &mut line )? == 0 || line.trim().is_empty() {
+				break;
+			}
+			if let Some( ( name, value ) ) = line.split_once( ':' ) {
+				match name.trim().to_lowercase().as_str() {
+					"content-length" => length = value.trim().parse().ok(),
+					"location" => location = Some( value.trim().to_string() ),
+					_ => { }
+				}
+			}
+		}
+
+		match status {
+			200 => {
+				let too_large = || Error::new( ErrorKind::InvalidData, format!( "{self} answered {target} with more than {limit} bytes" ) );
+				let mut data = Vec::new();
+				match length {
+					Some( length ) if length > limit => return Err( too_large() ),
+					Some( length ) => {
+						data.reserve( length.min( MAX_RESERVE ) as usize );
+						reader.take( length ).read_to_end( &mut data )?;
+						if data.len() as u64 != length {
+							return Err( Error::new( ErrorKind::UnexpectedEof, format!( "{self} closed the connection after {} of {length} bytes", data.len() ) ) );
+						}
+					}
+					None => {
+						reader.take( limit.saturating_add( 1 ) ).read_to_end( &mut data )?;
+						if data.len() as u64 > limit {
+							return Err( too_large() );
+						}
+					}
+				}
+				Ok( Response::Ok( data ) )
+			}
+			404 | 410 => Ok( Response::NotFound ),
+			301 | 302 | 303 | 307 | 308 => location
+				.map( Response::Redirect )
+				.ok_or_else( || Error::new( ErrorKind::InvalidData, format!( "{self} redirected without a location" ) ) ),
+			status => Err( Error::new( ErrorKind::Other, format!( "{self} answered {target} with status {status}" ) ) )
+		}
+	}
+}
+
+impl Display for HttpUrl {
+	fn fmt( &self, f: &mut Formatter<'_> ) -> std::fmt::Result {
+		write!( f, "http://{}:{}{}", self.host, self.port, self.path )
+	}
+}
+
+enum Response {
+	Ok( Vec<u8> ),
+	NotFound,
+	Redirect( String )
+}
+
+/**
+ * Percent-encodes a path for a request line, keeping the slashes.
+ */
+fn encode( path: &str ) -> String {
+	let mut encoded = String::with_capacity( path.len() );
+	for byte in path.bytes() {
+		match byte {
+			b'a' ..= b'z' | b'A' ..= b'Z' | b'0' ..= b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => encoded.push( byte as char ),
+			byte => encoded.push_str( format!( "%{byte:02X}" ).as_str() )
+		}
+	}
+	encoded
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+	use std::io::{BufRead, BufReader, ErrorKind, Write};
+	use std::net::TcpListener;
+	use std::path::PathBuf;
+	use std::sync::{Arc, Mutex};
+
+	use crate::layered::{Layer, LayeredFS};
+	use crate::layered::hash::{FileHash, HashManifest, hash_bytes};
+	use crate::layered::layers::http::{HttpLayer, HttpLayerProvider};
+	use crate::layered::tests::scratch_dir;
+
+	/**
+	 * Serves files on 127.0.0.1 from a background thread, returns the base URL and the requested paths.
+	 */
+	fn serve( files: HashMap<&'static str, &'static str> ) -> ( String, Arc<Mutex<Vec<String>>> ) {
+		let listener = TcpListener::bind( "127.0.0.1:0" ).unwrap();
+		let url = format!( "http://{}/fastdl", listener.local_addr().unwrap() );
+		let requests = Arc::new( Mutex::new( Vec::new() ) );
+		let log = requests.clone();
+		std::thread::spawn( move || {
+			for stream in listener.incoming() {
+				let mut stream = stream.unwrap();
+				let mut line = String::new();
+				let mut reader = BufReader::new( stream.try_clone().unwrap() );
+				reader.read_line( &mut line ).unwrap();
+				while reader.read_line( &mut String::new() ).unwrap() > 2 { }
+
+				let path = line.split_whitespace().nth( 1 ).unwrap().to_string();
+				let data = path.strip_prefix( "/fastdl/" ).and_then( |path| files.get( path ) );
+				// logged before answering, so the client never sees a response which isn't logged yet
+				log.lock().unwrap().push( path );
+				let _ = match data {
+					Some( data ) => write!( stream, "HTTP/1.0 200 OK\r\nContent-Length: {}\r\n\r\n{data}", data.len() ),
+					None => write!( stream, "HTTP/1.0 404 Not Found\r\nContent-Length: 0\r\n\r\n" )
+				};
+			}
+		} );
+		( url, requests )
+	}
+
+	#[test]
+	fn download_and_cache() {
+		let ( url, requests ) = serve( HashMap::from( [
+			( "maps/map01.bsp", "map01" ),
+			( "sound/music%20theme.wav", "theme" ),
+			( "materials/tampered.vmt", "evil" ),
+			( "maps/map03.bsp", "larger than listed" )
+		] ) );
+		let cache = scratch_dir( "http" );
+
+		let mut fs = LayeredFS::new();
+		fs.add_layer_provider( Box::new( HttpLayerProvider::new( cache.clone() ) ) );
+		fs.add_layer( PathBuf::from( url.as_str() ), false ).unwrap();
+
+		// downloaded once, then read from the cache
+		assert_eq!( "map01", fs.get_file( "Maps\\Map01.bsp" ).unwrap().read_string().unwrap() );
+		assert_eq!( "map01", fs.get_file( "maps/map01.bsp" ).unwrap().read_string().unwrap() );
+		assert_eq!( "theme", fs.get_file( "sound/music theme.wav" ).unwrap().read_string().unwrap() );
+		// misses are only requested once
+		assert!( !fs.contains( "maps/map02.bsp" ) );
+		assert_eq!( ErrorKind::NotFound, fs.get_file( "maps/map02.bsp" ).err().unwrap().kind() );
+		assert_eq!(
+			vec![ "/fastdl/maps/map01.bsp", "/fastdl/sound/music%20theme.wav", "/fastdl/maps/map02.bsp" ],
+			*requests.lock().unwrap()
+		);
+
+		// with a manifest, unlisted files aren't requested, and bad ones aren't cached
+		let mut manifest = HashManifest::default();
+		manifest.insert( "maps/map01.bsp", FileHash { sha256: hash_bytes( b"map01" ), size: 5 } );
+		manifest.insert( "materials/tampered.vmt", FileHash { sha256: hash_bytes( b"good" ), size: 4 } );
+		let layer = HttpLayer::new( url.as_str(), cache.join( "verified" ) ).unwrap().with_manifest( manifest );
+		requests.lock().unwrap().clear();
+
+		assert_eq!( ErrorKind::InvalidData, layer.get_file( "materials/tampered.vmt" ).err().unwrap().kind() );
+		assert!( !cache.join( "verified/materials/tampered.vmt" ).exists() );
+		assert_eq!( ErrorKind::NotFound, layer.get_file( "sound/music theme.wav" ).err().unwrap().kind() );
+		assert!( layer.contains( "maps/map01.bsp" ) );
+		// not downloaded yet, so the loader has to go through `get_file()`
+		assert_eq!( ErrorKind::Unsupported, layer.locate( "maps/map01.bsp" ).err().unwrap().kind() );
+		assert_eq!( vec![ "maps", "materials" ], layer.read_dir( "" ).unwrap().into_iter().map( |entry| entry.name ).collect::<Vec<String>>() );
+		assert_eq!( vec![ "/fastdl/materials/tampered.vmt" ], *requests.lock().unwrap() );
+
+		assert_eq!( "map01", layer.get_file( "maps/map01.bsp" ).unwrap().read_string().unwrap() );
+		assert_eq!( layer.uuid(), &layer.get_file( "maps/map01.bsp" ).unwrap().layer() );
+		assert!( layer.locate( "maps/map01.bsp" ).is_ok() );
+		assert_eq!( 2, requests.lock().unwrap().len() );
+
+		// a cached copy which doesn't match is downloaded again
+		std::fs::write( cache.join( "verified/maps/map01.bsp" ), "stale" ).unwrap();
+		let layer = HttpLayer::new( url.as_str(), cache.join( "verified" ) ).unwrap().with_manifest( layer.manifest().unwrap().clone() );
+		assert_eq!( "map01", layer.get_file( "maps/map01.bsp" ).unwrap().read_string().unwrap() );
+		assert_eq!( 3, requests.lock().unwrap().len() );
+
+		// bodies larger than the manifest says are refused
+		let mut manifest = HashManifest::default();
+		manifest.insert( "maps/map03.bsp", FileHash { sha256: hash_bytes( b"map03" ), size: 5 } );
+		let layer = HttpLayer::new( url.as_str(), cache.join( "verified" ) ).unwrap().with_manifest( manifest );
+		assert_eq!( ErrorKind::InvalidData, layer.get_file( "maps/map03.bsp" ).err().unwrap().kind() );
+		assert!( !cache.join( "verified/maps/map03.bsp" ).exists() );
+
+		std::fs::remove_dir_all( cache ).unwrap();
+	}
+
+	#[test]
+	fn failed_requests_are_remembered() {
+		// nothing listens on a port which was just released
+		let listener = TcpListener::bind( "127.0.0.1:0" ).unwrap();
+		let url = format!( "http://{}/fastdl", listener.local_addr().unwrap() );
+		drop( listener );
+		let cache = scratch_dir( "http_down" );
+
+		let layer = HttpLayer::new( url.as_str(), cache.clone() ).unwrap();
+		assert!( !layer.contains( "maps/map01.bsp" ) );
+		assert!( layer.missing.lock().unwrap().contains( "maps/map01.bsp" ) );
+		assert_eq!( ErrorKind::NotFound, layer.get_file( "maps/map01.bsp" ).err().unwrap().kind() );
+
+		layer.forget_missing();
+		assert!( layer.missing.lock().unwrap().is_empty() );
+
+		std::fs::remove_dir_all( cache ).unwrap();
+	}
+}
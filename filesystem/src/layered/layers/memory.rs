@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+use std::io::{Cursor, Error, ErrorKind};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use uuid::Uuid;
+
+use crate::layered::*;
+use crate::layered::stream::LayeredStream;
+
+/**
+ * A layer kept in memory, for generated content like baked lightmaps and downloads, and for tests.
+ *
+ * Files can be added and removed at any time, files handed out keep the contents they had when opened.
+ * Paths are normalized like in all other layers, directories only exist as prefixes of files.
+ */
+pub struct MemoryLayer {
+	name: String,
+	uuid: Uuid,
+	/// normalized path -> contents
+	files: RwLock<BTreeMap<String, Arc<[u8]>>>
+}
+
+impl MemoryLayer {
+	/**
+	 * Creates an empty memory layer, the name is only used to tell it apart from others.
+	 */
+	pub fn new( name: &str ) -> Self {
+		MemoryLayer { name: name.to_string(), uuid: Uuid::new_v4(), files: RwLock::new( BTreeMap::new() ) }
+	}
+
+	/**
+	 * Adds a file, or replaces its contents if it's already there.
+	 */
+	pub fn insert( &self, filename: &str, data: impl Into<Vec<u8>> ) -> Result<(), Error> {
+		let filename = sanitize_path( filename )?;
+		if filename.is_empty() {
+			return Err( Error::new( ErrorKind::InvalidInput, "Cannot write the root of a layer" ) );
+		}
+		self.files.write().unwrap().insert( filename, Arc::from( data.into() ) );
+		Ok(())
+	}
+
+	/**
+	 * Same as `insert()`, for building layers in one go.
+	 */
+	pub fn with( self, filename: &str, data: impl Into<Vec<u8>> ) -> Self {
+		self.insert( filename, data ).expect( "Invalid path for a memory layer file" );
+		self
+	}
+
+	/**
+	 * Removes all files.
+	 */
+	pub fn clear( &self ) {
+		self.files.write().unwrap().clear();
+	}
+
+	pub fn len( &self ) -> usize {
+		self.files.read().unwrap().len()
+	}
+
+	pub fn is_empty( &self ) -> bool {
+		self.files.read().unwrap().is_empty()
+	}
+
+	fn data( &self, filename: &str ) -> Result<Arc<[u8]>, Error> {
+		self.files.read().unwrap()
+			.get( &sanitize_path( filename )? )
+			.cloned()
+			.ok_or_else( || Error::new(ErrorKind::NotFound, format!("File {filename} was not found") ) )
+	}
+}
+
+impl Layer for MemoryLayer {
+	fn resolve( &self, filename: &str ) -> PathBuf {
+		PathBuf::from( format!( "memory:{}!/{}", self.name, normalize_path( filename ) ) )
+	}
+
+	fn contains( &self, filename: &str ) -> bool {
+		self.data( filename ).is_ok()
+	}
+
+	fn get_file( &self, filename: &str ) -> Result<LayeredFile, Error> {
+		Ok( Box::new( MemoryLayeredFile {
+			data: self.data( filename )?,
+			path: filename.to_string(),
+			layer: self.uuid
+		} ) )
+	}
+
+	fn locate( &self, filename: &str ) -> Result<ReadLocation, Error> {
+		Ok( ReadLocation::Bytes( self.data( filename )?.to_vec() ) )
+	}
+
+	fn read_dir( &self, dirname: &str ) -> Result<Vec<DirEntry>, Error> {
+		let dir = sanitize_path( dirname )?;
+		let prefix = if dir.is_empty() { String::new() } else { format!( "{dir}/" ) };
+
+		let mut entries = BTreeMap::new();
+		for path in self.files.read().unwrap().keys() {
+			if let Some( rest ) = path.strip_prefix( prefix.as_str() ) {
+				let ( name, is_dir ) = match rest.split_once( '/' ) {
+					Some( ( dir, _ ) ) => ( dir, true ),
+					None => ( rest, false )
+				};
+				entries.entry( name.to_string() ).or_insert( is_dir );
+			}
+		}
+
+		if entries.is_empty() {
+			return Err( Error::new( ErrorKind::NotFound, format!( "Directory {dirname} was not found" ) ) );
+		}
+		Ok(
+			entries.into_iter()
+				.map( |( name, is_dir )| DirEntry { path: join_path( dir.as_str(), name.as_str() ), name, is_dir, layer: self.uuid } )
+				.collect()
+		)
+	}
+
+	fn meta( &self ) -> LayerMeta {
+		LayerMeta {
+			origin: None,
+			filename: format!( "memory:{}", self.name ),
+			size: Some( self.files.read().unwrap().values().map( |data| data.len() as u64 ).sum() )
+		}
+	}
+
+	fn uuid( &self ) -> &Uuid {
+		&self.uuid
+	}
+
+	fn is_writable( &self ) -> bool {
+		true
+	}
+
+	fn write( &self, filename: &str, data: &[u8] ) -> Result<(), Error> {
+		self.insert( filename, data )
+	}
+
+	fn create( &self, filename: &str ) -> Result<(), Error> {
+		if self.contains( filename ) {
+			return Err( Error::new( ErrorKind::AlreadyExists, format!( "File {filename} already exists" ) ) );
+		}
+		self.insert( filename, Vec::new() )
+	}
+
+	fn remove( &self, filename: &str ) -> Result<(), Error> {
+		match self.files.write().unwrap().remove( &sanitize_path( filename )? ) {
+			Some(_) => Ok(()),
+			None => Err( Error::new(ErrorKind::NotFound, format!("File {filename} was not found") ) )
+		}
+	}
+
+	fn rename( &self, from: &str, to: &str ) -> Result<(), Error> {
+		let to = sanitize_path( to )?;
+		let mut files = self.files.write().unwrap();
+		match files.remove( &sanitize_path( from )? ) {
+			Some( data ) => {
+				files.insert( to, data );
+				Ok(())
+			}
+			None => Err( Error::new(ErrorKind::NotFound, format!("File {from} was not found") ) )
+		}
+	}
+}
+
+
+struct MemoryLayeredFile {
+	data: Arc<[u8]>,
+	path: String,
+	layer: Uuid
+}
+
+impl ILayeredFile for MemoryLayeredFile {
+	fn size( &self ) -> u64 {
+		self.data.len() as u64
+	}
+
+	fn stream( &self ) -> Result<LayeredStream, Error> {
+		Ok( Box::new( Cursor::new( self.data.clone() ) ) )
+	}
+
+	fn layer( &self ) -> Uuid {
+		self.layer
+	}
+
+	fn path( &self ) -> String {
+		self.path.clone()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+
+	use crate::layered::{Layer, LayeredFS};
+	use crate::layered::layers::memory::MemoryLayer;
+
+	#[test]
+	fn memory_layers_in_a_stack() {
+		let mut fs = LayeredFS::new();
+		let generated = Arc::new( MemoryLayer::new( "generated" ).with( "maps/map01/lightmap.dat", "baked" ) );
+		fs.insert_layer( generated.clone(), false, &[] );
+		fs.insert_layer( Arc::new( MemoryLayer::new( "game" ).with( "maps/map01.bsp", "map01" ).with( "Maps/Map01/Lightmap.dat", "stale" ) ), false, &[] );
+
+		assert_eq!( "baked", fs.get_file( "maps\\map01\\LIGHTMAP.dat" ).unwrap().read_string().unwrap() );
+		assert_eq!( vec![ "map01", "map01.bsp" ], fs.read_dir( "maps" ).unwrap().into_iter().map( |entry| entry.name ).collect::<Vec<String>>() );
+
+		// changes show up right away, open files keep their contents
+		let file = fs.get_file( "maps/map01/lightmap.dat" ).unwrap();
+		generated.write( "maps/map01/lightmap.dat", b"rebaked" ).unwrap();
+		assert_eq!( "baked", file.read_string().unwrap() );
+		assert_eq!( "rebaked", fs.get_file( "maps/map01/lightmap.dat" ).unwrap().read_string().unwrap() );
+		generated.rename( "maps/map01/lightmap.dat", "maps/map02/lightmap.dat" ).unwrap();
+		assert_eq!( "stale", fs.get_file( "maps/map01/lightmap.dat" ).unwrap().read_string().unwrap() );
+
+		assert!( generated.insert( "../outside.dat", "" ).is_err() );
+		assert_eq!( 1, generated.len() );
+	}
+}
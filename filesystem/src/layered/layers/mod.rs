@@ -1,2 +1,6 @@
 pub mod vpk;
 pub mod folder;
+pub mod memory;
+pub mod zip;
+pub mod http;
+pub mod plugin;
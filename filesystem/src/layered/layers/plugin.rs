@@ -0,0 +1,442 @@
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use libloading::Library;
+use log::{debug, warn};
+use path_slash::PathBufExt;
+use uuid::Uuid;
+
+use crate::layered::*;
+use crate::layered::stream::{LayeredStream, seek_position};
+
+/**
+ * Version of the plugin ABI, plugins built against another version are refused.
+ */
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+/**
+ * Symbol plugin libraries export their providers with, a `PluginEntry`.
+ */
+pub const PLUGIN_ENTRY: &str = "borealis_layer_providers";
+
+/**
+ * Folders of a game root searched for plugin libraries.
+ */
+pub const PLUGIN_DIRS: [&str; 2] = [ "bin", "plugins" ];
+
+/**
+ * Returns the providers of a plugin library, and writes how many there are into `count`.
+ * The array must stay valid until the library is unloaded.
+ */
+pub type PluginEntry = unsafe extern "C" fn( count: *mut usize ) -> *const PluginProvider;
+
+/**
+ * Called by `PluginProvider.read_dir` for each entry of a directory.
+ */
+pub type DirCallback = unsafe extern "C" fn( ctx: *mut c_void, name: *const c_char, is_dir: i32 );
+
+/**
+ * A layer provider implemented by a plugin, through a stable C ABI, see `include/borealis_layer_plugin.h`.
+ *
+ * All strings are NUL-terminated UTF-8, paths inside archives are normalized like `normalize_path()`.
+ * Functions may be called from any thread, with the same archive too.
+ */
+#[repr(C)]
+pub struct PluginProvider {
+	/// must be `PLUGIN_ABI_VERSION`
+	pub abi_version: u32,
+	/// name of the format, for logs
+	pub name: *const c_char,
+	/// returns non-zero if the provider can open the archive at this path
+	pub supports: unsafe extern "C" fn( path: *const c_char ) -> i32,
+	/// opens an archive, returns null on failure
+	pub open: unsafe extern "C" fn( path: *const c_char ) -> *mut c_void,
+	/// closes an archive, it isn't used afterward
+	pub close: unsafe extern "C" fn( archive: *mut c_void ),
+	/// returns the size of a file, negative if the archive doesn't have it
+	pub file_size: unsafe extern "C" fn( archive: *mut c_void, filename: *const c_char ) -> i64,
+	/// reads up to `len` bytes of a file starting at `offset` into `buf`, returns how many, negative on errors
+	pub read: unsafe extern "C" fn( archive: *mut c_void, filename: *const c_char, offset: u64, buf: *mut u8, len: u64 ) -> i64,
+	/// calls `entry` for each entry of a directory, returns zero if the archive doesn't have the directory
+	pub read_dir: unsafe extern "C" fn( archive: *mut c_void, dirname: *const c_char, ctx: *mut c_void, entry: DirCallback ) -> i32
+}
+
+// the vtable is immutable, and plugins must be callable from any thread
+unsafe impl Send for PluginProvider { }
+unsafe impl Sync for PluginProvider { }
+
+impl PluginProvider {
+	fn name( &self ) -> String {
+		match self.name.is_null() {
+			true => "unnamed".to_string(),
+			false => unsafe { CStr::from_ptr( self.name ) }.to_string_lossy().to_string()
+		}
+	}
+}
+
+/**
+ * Adapts a `PluginProvider` to a `LayerProvider`.
+ */
+pub struct PluginLayerProvider {
+	vtable: &'static PluginProvider,
+	/// keeps the library, and so the vtable, loaded
+	library: Option<Arc<Library>>
+}
+
+impl PluginLayerProvider {
+	/**
+	 * Wraps a provider linked into the program instead of loaded from a library.
+	 *
+	 * # Safety
+	 * The function pointers of the vtable must follow the contract of `PluginProvider`.
+	 */
+	pub unsafe fn from_static( vtable: &'static PluginProvider ) -> Result<PluginLayerProvider, Error> {
+		PluginLayerProvider::new( vtable, None )
+	}
+
+	fn new( vtable: &'static PluginProvider, library: Option<Arc<Library>> ) -> Result<PluginLayerProvider, Error> {
+		if vtable.abi_version != PLUGIN_ABI_VERSION {
+			return Err( Error::new(
+				ErrorKind::Unsupported,
+				format!( "provider {} uses plugin ABI {}, expected {PLUGIN_ABI_VERSION}", vtable.name(), vtable.abi_version )
+			) );
+		}
+		Ok( PluginLayerProvider { vtable, library } )
+	}
+
+	pub fn name( &self ) -> String {
+		self.vtable.name()
+	}
+}
+
+impl LayerProvider for PluginLayerProvider {
+	fn supports( &self, path: &PathBuf ) -> bool {
+		let Ok( path ) = CString::new( path.to_string_lossy().as_bytes() ) else {
+			return false;
+		};
+		unsafe { ( self.vtable.supports )( path.as_ptr() ) != 0 }
+	}
+
+	fn create( &self, path: PathBuf ) -> Result<Arc<dyn Layer>, LayeredFSError> {
+		let cpath = CString::new( path.to_string_lossy().as_bytes() ).map_err( |_| LayeredFSError::NotFound( path.clone() ) )?;
+		let handle = unsafe { ( self.vtable.open )( cpath.as_ptr() ) };
+		if handle.is_null() {
+			return Err( LayeredFSError::InvalidArchive( format!( "{path:?}, provider {} couldn't open it", self.name() ) ) );
+		}
+		Ok( Arc::new( PluginLayer {
+			archive: Arc::new( PluginArchive { handle, vtable: self.vtable, _library: self.library.clone() } ),
+			path,
+			uuid: Uuid::new_v4()
+		} ) )
+	}
+}
+
+/**
+ * An archive opened by a plugin, closed when the layer and all its files are dropped.
+ */
+struct PluginArchive {
+	handle: *mut c_void,
+	vtable: &'static PluginProvider,
+	/// dropped after the archive is closed
+	_library: Option<Arc<Library>>
+}
+
+// plugins must be callable from any thread, with the same archive too
+unsafe impl Send for PluginArchive { }
+unsafe impl Sync for PluginArchive { }
+
+impl PluginArchive {
+	fn file_size( &self, filename: &CStr ) -> Option<u64> {
+		let size = unsafe { ( self.vtable.file_size )( self.handle, filename.as_ptr() ) };
+		u64::try_from( size ).ok()
+	}
+
+	fn read( &self, filename: &CStr, offset: u64, buf: &mut [u8] ) -> Result<usize, Error> {
+		let read = unsafe { ( self.vtable.read )( self.handle, filename.as_ptr(), offset, buf.as_mut_ptr(), buf.len() as u64 ) };
+		match usize::try_from( read ) {
+			Ok( read ) => Ok( read.min( buf.len() ) ),
+			Err(_) => Err( Error::new( ErrorKind::Other, format!( "provider {} failed to read {}", self.vtable.name(), filename.to_string_lossy() ) ) )
+		}
+	}
+
+	fn read_dir( &self, dirname: &CStr ) -> Option<Vec<( String, bool )>> {
+		unsafe extern "C" fn collect( ctx: *mut c_void, name: *const c_char, is_dir: i32 ) {
+			let entries = &mut *( ctx as *mut Vec<( String, bool )> );
+			entries.push( ( CStr::from_ptr( name ).to_string_lossy().to_string(), is_dir != 0 ) );
+		}
+
+		let mut entries: Vec<( String, bool )> = Vec::new();
+		let found = unsafe { ( self.vtable.read_dir )( self.handle, dirname.as_ptr(), &mut entries as *mut _ as *mut c_void, collect ) };
+		match found {
+			0 => None,
+			_ => Some( entries )
+		}
+	}
+}
+
+impl Drop for PluginArchive {
+	fn drop( &mut self ) {
+		unsafe { ( self.vtable.close )( self.handle ) }
+	}
+}
+
+/**
+ * A layer over an archive opened by a plugin.
+ * Its files can't be located on disk, so the `AsyncLoader` reads them through the plugin on its workers.
+ */
+pub struct PluginLayer {
+	path: PathBuf,
+	archive: Arc<PluginArchive>,
+	uuid: Uuid
+}
+
+impl PluginLayer {
+	/**
+	 * The path as the plugin sees it.
+	 */
+	fn cpath( &self, filename: &str ) -> Result<CString, Error> {
+		CString::new( sanitize_path( filename )? ).map_err( |_| Error::new( ErrorKind::InvalidInput, format!( "{filename} has a NUL character" ) ) )
+	}
+}
+
+impl Layer for PluginLayer {
+	fn resolve( &self, filename: &str ) -> PathBuf {
+		let mut path = PathBuf::from( String::from( self.path.to_str().unwrap() ) + "!" );
+		path.push( filename );
+		path.to_slash().unwrap().parse().unwrap()
+	}
+
+	fn contains( &self, filename: &str ) -> bool {
+		self.cpath( filename ).is_ok_and( |path| self.archive.file_size( &path ).is_some() )
+	}
+
+	fn get_file( &self, filename: &str ) -> Result<LayeredFile, Error> {
+		let path = self.cpath( filename )?;
+		let Some( size ) = self.archive.file_size( &path ) else {
+			return Err( Error::new( ErrorKind::NotFound, format!( "{filename} is not in {:?}", self.path ) ) );
+		};
+		Ok( Box::new( PluginLayeredFile { archive: self.archive.clone(), filename: path, path: filename.to_string(), size, layer: self.uuid } ) )
+	}
+
+	fn read_dir( &self, dirname: &str ) -> Result<Vec<DirEntry>, Error> {
+		let Some( entries ) = self.archive.read_dir( &self.cpath( dirname )? ) else {
+			return Err( Error::new( ErrorKind::NotFound, format!( "{dirname} is not in {:?}", self.path ) ) );
+		};
+		Ok( entries.into_iter()
+			.map( |( name, is_dir )| DirEntry { path: join_path( dirname, name.as_str() ), name, is_dir, layer: self.uuid } )
+			.collect() )
+	}
+
+	fn meta( &self ) -> LayerMeta {
+		LayerMeta {
+			origin: None,
+			filename: self.path.to_str().unwrap().to_string(),
+			size: None
+		}
+	}
+
+	fn uuid( &self ) -> &Uuid {
+		&self.uuid
+	}
+
+	fn is_archive( &self ) -> bool {
+		true
+	}
+}
+
+struct PluginLayeredFile {
+	archive: Arc<PluginArchive>,
+	/// normalized path, as passed to the plugin
+	filename: CString,
+	path: String,
+	size: u64,
+	layer: Uuid
+}
+
+impl ILayeredFile for PluginLayeredFile {
+	fn size( &self ) -> u64 {
+		self.size
+	}
+
+	fn stream( &self ) -> Result<LayeredStream, Error> {
+		Ok( Box::new( PluginStream { archive: self.archive.clone(), filename: self.filename.clone(), size: self.size, pos: 0 } ) )
+	}
+
+	fn layer( &self ) -> Uuid {
+		self.layer
+	}
+
+	fn path( &self ) -> String {
+		self.path.clone()
+	}
+}
+
+struct PluginStream {
+	archive: Arc<PluginArchive>,
+	filename: CString,
+	size: u64,
+	pos: u64
+}
+
+impl Read for PluginStream {
+	fn read( &mut self, buf: &mut [u8] ) -> Result<usize, Error> {
+		if self.pos >= self.size {
+			return Ok( 0 );
+		}
+		let len = buf.len().min( ( self.size - self.pos ) as usize );
+		let read = self.archive.read( &self.filename, self.pos, &mut buf[ .. len ] )?;
+		self.pos += read as u64;
+		Ok( read )
+	}
+}
+
+impl Seek for PluginStream {
+	fn seek( &mut self, from: SeekFrom ) -> Result<u64, Error> {
+		self.pos = seek_position( self.pos, self.size, from )?;
+		Ok( self.pos )
+	}
+}
+
+impl LayeredFS {
+	/**
+	 * Loads the layer providers of the plugin libraries in the `bin/` and `plugins/` folders of a game root,
+	 * returns how many providers were added. Libraries which don't export `PLUGIN_ENTRY` are skipped,
+	 * so the engine's own libraries can sit next to plugins, broken plugins are skipped with a warning.
+	 *
+	 * # Safety
+	 * Every library in those folders is loaded like with `load_provider_plugin()`, so they must all be trusted.
+	 */
+	pub unsafe fn load_provider_plugins( &mut self, root: &Path ) -> usize {
+		let mut libraries: Vec<PathBuf> = PLUGIN_DIRS.iter()
+			.filter_map( |dir| std::fs::read_dir( root.join( dir ) ).ok() )
+			.flat_map( |entries| entries.flatten().map( |entry| entry.path() ) )
+			.filter( |path| path.extension().is_some_and( |ext| ext == std::env::consts::DLL_EXTENSION ) )
+			.collect();
+		libraries.sort();
+
+		let mut count = 0;
+		for path in libraries {
+			match self.load_provider_plugin( &path ) {
+				Ok( added ) => count += added,
+				Err( err ) if err.kind() == ErrorKind::NotFound => debug!( "{path:?} is not a layer provider plugin" ),
+				Err( err ) => warn!( "Failed to load layer provider plugin {path:?}: {err}" )
+			}
+		}
+		count
+	}
+
+	/**
+	 * Loads the layer providers of a plugin library, returns how many were added.
+	 * Errors with `NotFound` if the library doesn't export `PLUGIN_ENTRY`, and with `Unsupported`
+	 * if it was built for another ABI version, in which case none of its providers are added.
+	 *
+	 * # Safety
+	 * Loading the library runs its initialization code, and its `PLUGIN_ENTRY` is called,
+	 * it must be a trusted library whose entry and providers follow the contract of `PluginEntry` and `PluginProvider`.
+	 */
+	pub unsafe fn load_provider_plugin( &mut self, path: &Path ) -> Result<usize, Error> {
+		let library = Library::new( path )
+			.map_err( |err| Error::new( ErrorKind::InvalidData, format!( "couldn't load {path:?}: {err}" ) ) )?;
+		let library = Arc::new( library );
+		let entry = library.get::<PluginEntry>( PLUGIN_ENTRY.as_bytes() )
+			.map_err( |_| Error::new( ErrorKind::NotFound, format!( "{path:?} doesn't export {PLUGIN_ENTRY}" ) ) )?;
+		let mut count = 0;
+		let providers = entry( &mut count );
+		let providers: &'static [PluginProvider] = match providers.is_null() {
+			true => &[],
+			// the library is kept loaded by the providers
+			false => std::slice::from_raw_parts( providers, count )
+		};
+
+		let providers = providers.iter()
+			.map( |vtable| PluginLayerProvider::new( vtable, Some( library.clone() ) ) )
+			.collect::<Result<Vec<PluginLayerProvider>, Error>>()?;
+		let count = providers.len();
+		for provider in providers {
+			debug!( "Loaded layer provider {} from {path:?}", provider.name() );
+			self.add_layer_provider( Box::new( provider ) );
+		}
+		Ok( count )
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::{ErrorKind, Read, Seek, SeekFrom};
+	use std::path::Path;
+	use std::process::Command;
+
+	use crate::layered::LayeredFS;
+	use crate::layered::loader::{AsyncLoader, Priority};
+	use crate::layered::layers::plugin::{PluginLayerProvider, PluginProvider};
+	use crate::layered::tests::scratch_dir;
+
+	// the plugin built by `load_plugin_libraries()`, linked into the tests too
+	#[allow(dead_code)]
+	mod txtpak {
+		include!( "../../../tests/fixtures/txtpak_plugin.rs" );
+	}
+
+	#[test]
+	fn plugin_providers() {
+		let root = scratch_dir( "plugin" );
+		std::fs::create_dir_all( root.join( "plugins" ) ).unwrap();
+		std::fs::write( root.join( "content.txtpak" ), "maps/map01.bsp=VBSP map01\nscripts/titles.txt=titles\n" ).unwrap();
+		std::fs::write( root.join( "plugins" ).join( format!( "broken.{}", std::env::consts::DLL_EXTENSION ) ), "not a library" ).unwrap();
+
+		let mut fs = LayeredFS::new();
+		// a library which can't be loaded is skipped
+		assert_eq!( 0, unsafe { fs.load_provider_plugins( &root ) } );
+		assert_eq!( ErrorKind::InvalidData, unsafe { fs.load_provider_plugin( &root.join( "plugins" ).join( format!( "broken.{}", std::env::consts::DLL_EXTENSION ) ) ) }.unwrap_err().kind() );
+
+		// the fixture's vtable has the layout of `PluginProvider`
+		let txtpak = unsafe { &*( txtpak::borealis_layer_providers( &mut 0 ) as *const PluginProvider ) };
+		fs.add_layer_provider( Box::new( unsafe { PluginLayerProvider::from_static( txtpak ) }.unwrap() ) );
+		fs.add_layer( root.join( "content.txtpak" ), false ).unwrap();
+		assert!( fs.contains( "Maps\\Map01.bsp" ) );
+		assert!( !fs.contains( "maps/map02.bsp" ) );
+
+		let mut stream = fs.get_file( "maps/map01.bsp" ).unwrap().stream().unwrap();
+		stream.seek( SeekFrom::Start( 5 ) ).unwrap();
+		let mut contents = String::new();
+		stream.read_to_string( &mut contents ).unwrap();
+		assert_eq!( "map01", contents );
+		assert_eq!( vec![ "maps", "scripts" ], fs.read_dir( "" ).unwrap().into_iter().map( |entry| entry.name ).collect::<Vec<String>>() );
+		assert!( fs.add_layer( root.join( "missing.txtpak" ), false ).is_err() );
+
+		std::fs::remove_dir_all( root ).unwrap();
+	}
+
+	#[test]
+	fn load_plugin_libraries() {
+		let root = scratch_dir( "plugin_library" );
+		std::fs::create_dir_all( root.join( "plugins" ) ).unwrap();
+		std::fs::write( root.join( "content.txtpak" ), "maps/map01.bsp=VBSP map01\nscripts/titles.txt=titles\n" ).unwrap();
+
+		// built like a third-party plugin, against the header only
+		let library = root.join( "plugins" ).join( format!( "{}txtpak{}", std::env::consts::DLL_PREFIX, std::env::consts::DLL_SUFFIX ) );
+		let status = Command::new( std::env::var( "RUSTC" ).unwrap_or( "rustc".to_string() ) )
+			.args( [ "--crate-type", "cdylib", "--edition", "2021", "-o" ] )
+			.arg( &library )
+			.arg( Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "tests/fixtures/txtpak_plugin.rs" ) )
+			.status()
+			.unwrap();
+		assert!( status.success() );
+
+		let mut fs = LayeredFS::new();
+		assert_eq!( 1, unsafe { fs.load_provider_plugins( &root ) } );
+		fs.add_layer( root.join( "content.txtpak" ), false ).unwrap();
+		assert_eq!( "VBSP map01", fs.get_file( "Maps/Map01.bsp" ).unwrap().read_string().unwrap() );
+		assert_eq!( vec![ "maps", "scripts" ], fs.read_dir( "" ).unwrap().into_iter().map( |entry| entry.name ).collect::<Vec<String>>() );
+
+		// plugin layers can't locate their files, the loader reads them through the plugin instead
+		let loader = AsyncLoader::new( 1 );
+		assert_eq!( b"titles".to_vec(), loader.load( &fs, "scripts/titles.txt", Priority::Normal ).wait().unwrap() );
+
+		drop( loader );
+		drop( fs );
+		std::fs::remove_dir_all( root ).unwrap();
+	}
+}
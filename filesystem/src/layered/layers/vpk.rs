@@ -1,7 +1,8 @@
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::io::{Error, ErrorKind, Read, Seek, SeekFrom};
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use path_slash::PathBufExt;
 use uuid::Uuid;
@@ -9,6 +10,7 @@ use vpk::entry::VPKEntry;
 use vpk::VPK;
 
 use crate::layered::*;
+use crate::layered::stream::{LayeredStream, SectionStream, seek_position};
 
 pub struct VpkLayerProvider { }
 impl LayerProvider for VpkLayerProvider {
@@ -19,8 +21,8 @@ impl LayerProvider for VpkLayerProvider {
 		false
 	}
 
-	fn create<'a>( &self, path: PathBuf ) -> Result<Arc<dyn Layer + 'a>, LayeredFSError> {
-		Ok( Arc::new( VpkLayer::new( path ) ) )
+	fn create( &self, path: PathBuf ) -> Result<Arc<dyn Layer>, LayeredFSError> {
+		Ok( Arc::new( VpkLayer::new( path )? ) )
 	}
 }
 
@@ -28,19 +30,43 @@ pub struct VpkLayer {
 	path: PathBuf,
 	vpk: VPK,
 	uuid: Uuid,
+	/// normalized path -> key in the vpk tree
+	index: HashMap<String, String>,
+	/// open handles of the archive files, shared by all streams
+	archives: Mutex<HashMap<String, Arc<Mutex<File>>>>
 }
 
 impl VpkLayer {
-	pub fn new( path: PathBuf ) -> VpkLayer {
-		VpkLayer {
-			vpk: vpk::from_path( path.as_path().to_str().unwrap() ).unwrap(),
-			path: path,
-			uuid: Uuid::new_v4()
+	pub fn new( path: PathBuf ) -> Result<VpkLayer, LayeredFSError> {
+		let invalid = |err: &dyn std::fmt::Display| LayeredFSError::InvalidArchive( format!( "{}: {err}", path.display() ) );
+
+		let name = path.to_str().ok_or_else( || invalid( &"the path is not valid UTF-8" ) )?;
+		let vpk = vpk::from_path( name ).map_err( |err| invalid( &err ) )?;
+		Ok( VpkLayer {
+			index: vpk.tree.keys().map( |key| ( normalize_path( key ), key.clone() ) ).collect(),
+			vpk,
+			path,
+			uuid: Uuid::new_v4(),
+			archives: Mutex::new( HashMap::new() )
+		} )
+	}
+
+	fn entry( &self, filename: &str ) -> Option<&VPKEntry> {
+		self.index.get( &normalize_path( filename ) ).and_then( |key| self.vpk.tree.get( key ) )
+	}
+
+	fn archive( &self, path: &str ) -> Result<Arc<Mutex<File>>, Error> {
+		let mut archives = self.archives.lock().map_err( |_| Error::new( ErrorKind::Other, "Archive handles were poisoned" ) )?;
+		if let Some( file ) = archives.get( path ) {
+			return Ok( file.clone() );
 		}
+		let file = Arc::new( Mutex::new( File::open( path )? ) );
+		archives.insert( path.to_string(), file.clone() );
+		Ok( file )
 	}
 }
 
-impl<'a> Layer<'a> for VpkLayer {
+impl Layer for VpkLayer {
 	fn resolve( &self, filename: &str ) -> PathBuf {
 		let mut path = PathBuf::from( String::from( self.path.to_str().unwrap() ) + "!" );
 		path.push( filename );
@@ -48,20 +74,66 @@ impl<'a> Layer<'a> for VpkLayer {
 	}
 
 	fn contains( &self, filename: &str ) -> bool {
-		self.vpk.tree.contains_key( filename )
+		self.index.contains_key( &normalize_path( filename ) )
+	}
+
+	fn get_file(&self, filename: &str) -> Result<LayeredFile, Error> {
+		let Some( entry ) = self.entry( filename ) else {
+			return Err( Error::new(ErrorKind::NotFound, format!("File {filename} was not found") ) );
+		};
+		// entries with all of their data preloaded in the directory don't need their archive
+		let data = match entry.dir_entry.file_length {
+			0 => None,
+			length => Some( SectionStream::new(
+				self.archive( entry.archive_path.as_str() )?,
+				entry.dir_entry.archive_offset as u64,
+				length as u64
+			) )
+		};
+		Ok( Box::new( VpkLayeredFile {
+			path: self.path.to_str().unwrap().to_string(),
+			preload: Arc::from( entry.preload_data.as_slice() ),
+			data,
+			layer: self.uuid
+		}))
+	}
+
+	fn locate( &self, filename: &str ) -> Result<ReadLocation, Error> {
+		let Some( entry ) = self.entry( filename ) else {
+			return Err( Error::new(ErrorKind::NotFound, format!("File {filename} was not found") ) );
+		};
+		Ok( ReadLocation::Section {
+			archive: PathBuf::from( entry.archive_path.as_str() ),
+			offset: entry.dir_entry.archive_offset as u64,
+			len: entry.dir_entry.file_length as u64,
+			preload: entry.preload_data.clone()
+		} )
 	}
 
-	fn get_file(&'a self, filename: &str) -> Result<LayeredFile<'a>, Error> {
-		for ( name, entry ) in self.vpk.tree.iter() {
-			if name == filename {
-				return Ok( Box::new( VpkLayeredFile {
-					path: self.path.to_str().unwrap().to_string(),
-					entry: Arc::new( &entry ),
-					layer: &self.uuid
-				}))
+	fn read_dir( &self, dirname: &str ) -> Result<Vec<DirEntry>, Error> {
+		let dir = trim_dir( dirname );
+		let normalized = normalize_path( dir );
+		let prefix = if normalized.is_empty() { String::new() } else { format!( "{normalized}/" ) };
+		let depth = prefix.matches( '/' ).count();
+
+		// vpks only store files, directories are the common prefixes of their paths
+		let mut entries = BTreeMap::new();
+		for ( path, key ) in &self.index {
+			if path.starts_with( prefix.as_str() ) {
+				// names keep the case they have in the vpk
+				let segments: Vec<&str> = key.split( |chr| chr == '/' || chr == '\\' ).filter( |segment| !segment.is_empty() ).collect();
+				entries.entry( segments[depth].to_string() ).or_insert( segments.len() > depth + 1 );
 			}
 		}
-		Err( Error::new(ErrorKind::NotFound, format!("File {filename} was not found") ) )
+
+		if entries.is_empty() {
+			return Err( Error::new( ErrorKind::NotFound, format!( "Directory {dirname} was not found" ) ) );
+		}
+		Ok(
+			entries.into_iter()
+				.map( |( name, is_dir )| DirEntry { path: join_path( dir, name.as_str() ), name, is_dir, layer: self.uuid } )
+				.collect()
+		)
 	}
 
 	fn meta( &self ) -> LayerMeta {
@@ -75,37 +147,30 @@ impl<'a> Layer<'a> for VpkLayer {
 	fn uuid(&self) -> &Uuid {
 		&self.uuid
 	}
+	fn is_archive( &self ) -> bool {
+		true
+	}
 }
 
 
-struct VpkLayeredFile<'a> {
+struct VpkLayeredFile {
 	path: String,
-	entry: Arc<&'a VPKEntry>,
-	layer: &'a Uuid
+	preload: Arc<[u8]>,
+	/// the data in the archive, streams share the handle of the archive
+	data: Option<SectionStream>,
+	layer: Uuid
 }
 
-impl<'a> ILayeredFile<'a> for VpkLayeredFile<'a> {
+impl ILayeredFile for VpkLayeredFile {
 	fn size( &self ) -> u64 {
-		self.entry.get().unwrap().len() as u64
+		self.preload.len() as u64 + self.data.as_ref().map_or( 0, |data| data.len() )
 	}
 
-	fn read( &self ) -> Result<Vec<u8>, Error> {
-		let mut buf = vec![ 0u8; self.entry.dir_entry.file_length as usize ];
-		let mut file = File::open( &self.path ).unwrap();
-		file.seek( SeekFrom::Start( self.entry.dir_entry.archive_offset as u64 ) )?;
-		file.take( self.entry.dir_entry.file_length as u64 ).read( buf.as_mut_slice() )?;
-		Ok( buf )
+	fn stream( &self ) -> Result<LayeredStream, Error> {
+		Ok( Box::new( VpkStream { preload: self.preload.clone(), data: self.data.clone(), pos: 0 } ) )
 	}
 
-	fn read_string( &self ) -> Result<String, Error> {
-		let mut string = String::new();
-		let mut file = File::open( &self.path ).unwrap();
-		file.seek( SeekFrom::Start( self.entry.dir_entry.archive_offset as u64 ) )?;
-		file.take( self.entry.dir_entry.file_length as u64 ).read_to_string( &mut string )?;
-		Ok( string )
-	}
-
-	fn layer(&self) -> &'a Uuid {
+	fn layer(&self) -> Uuid {
 		self.layer
 	}
 
@@ -113,3 +178,56 @@ impl<'a> ILayeredFile<'a> for VpkLayeredFile<'a> {
 		self.path.clone()
 	}
 }
+
+/**
+ * A stream over a vpk entry: its preload data, followed by its data in the archive.
+ */
+struct VpkStream {
+	preload: Arc<[u8]>,
+	data: Option<SectionStream>,
+	pos: u64
+}
+
+impl Read for VpkStream {
+	fn read( &mut self, buf: &mut [u8] ) -> Result<usize, Error> {
+		let preload = self.preload.len() as u64;
+		let read = if self.pos < preload {
+			let mut rest = &self.preload[ self.pos as usize .. ];
+			rest.read( buf )?
+		} else if let Some( data ) = &mut self.data {
+			data.seek( SeekFrom::Start( self.pos - preload ) )?;
+			data.read( buf )?
+		} else {
+			0
+		};
+		self.pos += read as u64;
+		Ok( read )
+	}
+}
+
+impl Seek for VpkStream {
+	fn seek( &mut self, from: SeekFrom ) -> Result<u64, Error> {
+		let len = self.preload.len() as u64 + self.data.as_ref().map_or( 0, |data| data.len() );
+		self.pos = seek_position( self.pos, len, from )?;
+		Ok( self.pos )
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::layered::{LayeredFS, LayeredFSError};
+	use crate::layered::tests::scratch_dir;
+
+	#[test]
+	fn invalid_archives_are_refused() {
+		let root = scratch_dir( "vpk" );
+		let path = root.join( "pak01_dir.vpk" );
+		std::fs::write( &path, b"not a vpk" ).unwrap();
+
+		let mut fs = LayeredFS::new();
+		assert!( matches!( fs.add_layer( path.clone(), false ), Err( LayeredFSError::InvalidArchive(_) ) ) );
+		assert_eq!( 0, fs.layer_count() );
+
+		std::fs::remove_dir_all( root ).unwrap();
+	}
+}
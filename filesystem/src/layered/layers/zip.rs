@@ -0,0 +1,452 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{Cursor, Error, ErrorKind, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use libflate::deflate::Decoder;
+use path_slash::PathBufExt;
+use uuid::Uuid;
+
+use crate::layered::*;
+use crate::layered::stream::{LayeredStream, SectionStream};
+
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+const CENTRAL_DIRECTORY_HEADER: u32 = 0x02014b50;
+const LOCAL_FILE_HEADER: u32 = 0x04034b50;
+/// size of the end of central directory record, without its comment
+const END_OF_CENTRAL_DIRECTORY_SIZE: u64 = 22;
+const LOCAL_FILE_HEADER_SIZE: u64 = 30;
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATE: u16 = 8;
+const FLAG_ENCRYPTED: u16 = 1;
+
+pub struct ZipLayerProvider { }
+impl LayerProvider for ZipLayerProvider {
+	fn supports( &self, path: &PathBuf ) -> bool {
+		match path.extension().and_then( |ext| ext.to_str() ) {
+			Some( ext ) => ( ext.eq_ignore_ascii_case( "zip" ) || ext.eq_ignore_ascii_case( "pk3" ) ) && path.is_file(),
+			None => false
+		}
+	}
+
+	fn create( &self, path: PathBuf ) -> Result<Arc<dyn Layer>, LayeredFSError> {
+		Ok( Arc::new( ZipLayer::new( path )? ) )
+	}
+}
+
+/**
+ * An entry of the central directory.
+ */
+struct ZipEntry {
+	name: String,
+	method: u16,
+	flags: u16,
+	compressed_size: u64,
+	size: u64,
+	/// offset of the local file header, the data follows it
+	header_offset: u64
+}
+
+/**
+ * A layer over a zip archive, like `.pk3` files and map pakfiles.
+ *
+ * The central directory is indexed when mounting, entries are read from a single shared handle
+ * of the archive, stored ones as they are and deflated ones through a streaming decoder.
+ */
+pub struct ZipLayer {
+	path: PathBuf,
+	uuid: Uuid,
+	/// normalized path -> entry
+	entries: HashMap<String, ZipEntry>,
+	archive: Arc<Mutex<File>>
+}
+
+impl ZipLayer {
+	pub fn new( path: PathBuf ) -> Result<ZipLayer, LayeredFSError> {
+		let invalid = |err: Error| LayeredFSError::InvalidArchive( format!( "{}: {err}", path.display() ) );
+
+		let mut file = File::open( &path ).map_err( |_| LayeredFSError::NotFound( path.clone() ) )?;
+		let entries = read_central_directory( &mut file ).map_err( invalid )?;
+
+		Ok( ZipLayer {
+			entries: entries.into_iter()
+				.filter( |entry| !entry.name.ends_with( '/' ) )
+				.map( |entry| ( normalize_path( entry.name.as_str() ), entry ) )
+				.collect(),
+			archive: Arc::new( Mutex::new( file ) ),
+			uuid: Uuid::new_v4(),
+			path
+		} )
+	}
+
+	fn entry( &self, filename: &str ) -> Result<&ZipEntry, Error> {
+		self.entries.get( &normalize_path( filename ) )
+			.ok_or_else( || Error::new(ErrorKind::NotFound, format!("File {filename} was not found") ) )
+	}
+
+	/**
+	 * Returns where the data of an entry starts, the local header has its own name and extra field lengths.
+	 */
+	fn data_offset( &self, entry: &ZipEntry ) -> Result<u64, Error> {
+		if entry.flags & FLAG_ENCRYPTED != 0 {
+			return Err( Error::new( ErrorKind::Unsupported, format!( "{} is encrypted", entry.name ) ) );
+		}
+		if entry.method != METHOD_STORED && entry.method != METHOD_DEFLATE {
+			return Err( Error::new( ErrorKind::Unsupported, format!( "{} uses unsupported compression method {}", entry.name, entry.method ) ) );
+		}
+
+		let mut header = [0u8; LOCAL_FILE_HEADER_SIZE as usize];
+		let len = {
+			let mut file = self.archive.lock().map_err( |_| Error::new( ErrorKind::Other, "Archive handle was poisoned" ) )?;
+			file.seek( SeekFrom::Start( entry.header_offset ) )?;
+			file.read_exact( &mut header )?;
+			file.metadata()?.len()
+		};
+		let mut header = Cursor::new( header );
+		if header.read_u32::<LittleEndian>()? != LOCAL_FILE_HEADER {
+			return Err( Error::new( ErrorKind::InvalidData, format!( "{} has no valid local header", entry.name ) ) );
+		}
+		header.seek( SeekFrom::Start( 26 ) )?;
+		let name_length = header.read_u16::<LittleEndian>()? as u64;
+		let extra_length = header.read_u16::<LittleEndian>()? as u64;
+		let offset = entry.header_offset + LOCAL_FILE_HEADER_SIZE + name_length + extra_length;
+		if offset + entry.compressed_size > len {
+			return Err( Error::new( ErrorKind::InvalidData, format!( "{} is past the end of the archive", entry.name ) ) );
+		}
+		Ok( offset )
+	}
+}
+
+/**
+ * Finds the end of central directory record, which is followed by a comment of up to 64KiB,
+ * and reads all the entries of the central directory.
+ */
+fn read_central_directory( file: &mut File ) -> Result<Vec<ZipEntry>, Error> {
+	let len = file.metadata()?.len();
+	if len < END_OF_CENTRAL_DIRECTORY_SIZE {
+		return Err( Error::new( ErrorKind::InvalidData, "file is too small to be a zip archive" ) );
+	}
+	let tail_len = len.min( END_OF_CENTRAL_DIRECTORY_SIZE + u16::MAX as u64 );
+	let mut tail = vec![ 0u8; tail_len as usize ];
+	file.seek( SeekFrom::Start( len - tail_len ) )?;
+	file.read_exact( &mut tail )?;
+
+	let signature = END_OF_CENTRAL_DIRECTORY.to_le_bytes();
+	let Some( record ) = ( 0 ..= tail.len() - END_OF_CENTRAL_DIRECTORY_SIZE as usize ).rev().find( |index| tail[ *index .. *index + 4 ] == signature ) else {
+		return Err( Error::new( ErrorKind::InvalidData, "no end of central directory record" ) );
+	};
+
+	let mut record = Cursor::new( &tail[ record + 10 .. ] );
+	let count = record.read_u16::<LittleEndian>()?;
+	let size = record.read_u32::<LittleEndian>()?;
+	let offset = record.read_u32::<LittleEndian>()?;
+	if count == u16::MAX || size == u32::MAX || offset == u32::MAX {
+		return Err( Error::new( ErrorKind::Unsupported, "zip64 archives are not supported" ) );
+	}
+	if offset as u64 + size as u64 > len {
+		return Err( Error::new( ErrorKind::InvalidData, "central directory is past the end of the archive" ) );
+	}
+
+	let mut directory = vec![ 0u8; size as usize ];
+	file.seek( SeekFrom::Start( offset as u64 ) )?;
+	file.read_exact( &mut directory )?;
+
+	let mut directory = Cursor::new( directory );
+	let mut entries = Vec::with_capacity( count as usize );
+	for _ in 0 .. count {
+		if directory.read_u32::<LittleEndian>()? != CENTRAL_DIRECTORY_HEADER {
+			return Err( Error::new( ErrorKind::InvalidData, "corrupt central directory" ) );
+		}
+		directory.seek( SeekFrom::Current( 4 ) )?; // versions
+		let flags = directory.read_u16::<LittleEndian>()?;
+		let method = directory.read_u16::<LittleEndian>()?;
+		directory.seek( SeekFrom::Current( 8 ) )?; // time, date and crc
+		let compressed_size = directory.read_u32::<LittleEndian>()? as u64;
+		let size = directory.read_u32::<LittleEndian>()? as u64;
+		let name_length = directory.read_u16::<LittleEndian>()? as usize;
+		let extra_length = directory.read_u16::<LittleEndian>()? as i64;
+		let comment_length = directory.read_u16::<LittleEndian>()? as i64;
+		directory.seek( SeekFrom::Current( 8 ) )?; // disk and attributes
+		let header_offset = directory.read_u32::<LittleEndian>()? as u64;
+
+		let mut name = vec![ 0u8; name_length ];
+		directory.read_exact( &mut name )?;
+		directory.seek( SeekFrom::Current( extra_length + comment_length ) )?;
+		let name = String::from_utf8_lossy( &name ).to_string();
+
+		// the data is read with these later on, they must fit in the archive
+		if header_offset + LOCAL_FILE_HEADER_SIZE + compressed_size > len {
+			return Err( Error::new( ErrorKind::InvalidData, format!( "{name} is past the end of the archive" ) ) );
+		}
+		if method == METHOD_STORED && compressed_size != size {
+			return Err( Error::new( ErrorKind::InvalidData, format!( "{name} is stored, but has a different size once compressed" ) ) );
+		}
+
+		entries.push( ZipEntry {
+			name,
+			method,
+			flags,
+			compressed_size,
+			size,
+			header_offset
+		} );
+	}
+	Ok( entries )
+}
+
+impl Layer for ZipLayer {
+	fn resolve( &self, filename: &str ) -> PathBuf {
+		let mut path = PathBuf::from( String::from( self.path.to_str().unwrap() ) + "!" );
+		path.push( filename );
+		path.to_slash().unwrap().parse().unwrap()
+	}
+
+	fn contains( &self, filename: &str ) -> bool {
+		self.entries.contains_key( &normalize_path( filename ) )
+	}
+
+	fn get_file( &self, filename: &str ) -> Result<LayeredFile, Error> {
+		let entry = self.entry( filename )?;
+		Ok( Box::new( ZipLayeredFile {
+			path: filename.to_string(),
+			data: SectionStream::new( self.archive.clone(), self.data_offset( entry )?, entry.compressed_size ),
+			deflated: entry.method == METHOD_DEFLATE,
+			size: entry.size,
+			layer: self.uuid
+		} ) )
+	}
+
+	fn locate( &self, filename: &str ) -> Result<ReadLocation, Error> {
+		let entry = self.entry( filename )?;
+		let offset = self.data_offset( entry )?;
+		Ok( match entry.method {
+			METHOD_DEFLATE => ReadLocation::Deflated { archive: self.path.clone(), offset, len: entry.compressed_size, size: entry.size },
+			_ => ReadLocation::Section { archive: self.path.clone(), offset, len: entry.size, preload: Vec::new() }
+		} )
+	}
+
+	fn read_dir( &self, dirname: &str ) -> Result<Vec<DirEntry>, Error> {
+		let dir = trim_dir( dirname );
+		let normalized = normalize_path( dir );
+		let prefix = if normalized.is_empty() { String::new() } else { format!( "{normalized}/" ) };
+		let depth = prefix.matches( '/' ).count();
+
+		// like vpks, directories are the common prefixes of the paths
+		let mut entries = BTreeMap::new();
+		for path in self.entries.keys() {
+			if path.starts_with( prefix.as_str() ) {
+				// the key is matched, so the key is split: raw names may have `.` segments or other separators
+				let segments: Vec<&str> = path.split( '/' ).collect();
+				entries.entry( segments[depth].to_string() ).or_insert( segments.len() > depth + 1 );
+			}
+		}
+
+		if entries.is_empty() {
+			return Err( Error::new( ErrorKind::NotFound, format!( "Directory {dirname} was not found" ) ) );
+		}
+		Ok(
+			entries.into_iter()
+				.map( |( name, is_dir )| DirEntry { path: join_path( dir, name.as_str() ), name, is_dir, layer: self.uuid } )
+				.collect()
+		)
+	}
+
+	fn meta( &self ) -> LayerMeta {
+		LayerMeta {
+			origin: None,
+			filename: self.path.to_str().unwrap().to_string(),
+			size: std::fs::metadata( &self.path ).ok().map( |meta| meta.len() )
+		}
+	}
+
+	fn uuid( &self ) -> &Uuid {
+		&self.uuid
+	}
+	fn is_archive( &self ) -> bool {
+		true
+	}
+}
+
+
+struct ZipLayeredFile {
+	path: String,
+	/// the data as stored in the archive, streams share the handle of the archive
+	data: SectionStream,
+	deflated: bool,
+	size: u64,
+	layer: Uuid
+}
+
+impl ILayeredFile for ZipLayeredFile {
+	fn size( &self ) -> u64 {
+		self.size
+	}
+
+	fn stream( &self ) -> Result<LayeredStream, Error> {
+		Ok( match self.deflated {
+			true => Box::new( InflateStream::new( self.data.clone(), self.size ) ),
+			false => Box::new( self.data.clone() )
+		} )
+	}
+
+	fn layer( &self ) -> Uuid {
+		self.layer
+	}
+
+	fn path( &self ) -> String {
+		self.path.clone()
+	}
+}
+
+/**
+ * A stream over a deflated entry, seeking backwards starts decompressing from the beginning again.
+ */
+pub(crate) struct InflateStream {
+	/// the compressed data, to start over from
+	data: SectionStream,
+	decoder: Decoder<SectionStream>,
+	size: u64,
+	pos: u64
+}
+
+impl InflateStream {
+	pub(crate) fn new( data: SectionStream, size: u64 ) -> Self {
+		InflateStream { decoder: Decoder::new( data.clone() ), data, size, pos: 0 }
+	}
+}
+
+impl Read for InflateStream {
+	fn read( &mut self, buf: &mut [u8] ) -> Result<usize, Error> {
+		let remaining = self.size.saturating_sub( self.pos );
+		let count = ( buf.len() as u64 ).min( remaining ) as usize;
+		let read = self.decoder.read( &mut buf[ .. count ] )?;
+		self.pos += read as u64;
+		Ok( read )
+	}
+}
+
+impl Seek for InflateStream {
+	fn seek( &mut self, from: SeekFrom ) -> Result<u64, Error> {
+		let target = stream::seek_position( self.pos, self.size, from )?;
+		if target < self.pos {
+			self.decoder = Decoder::new( self.data.clone() );
+			self.pos = 0;
+		}
+		let skip = target - self.pos;
+		std::io::copy( &mut self.by_ref().take( skip ), &mut std::io::sink() )?;
+		// like files, seeking past the end is fine and reads nothing
+		self.pos = target;
+		Ok( self.pos )
+	}
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use std::io::{ErrorKind, Read, Seek, SeekFrom};
+
+	use crate::layered::{Layer, LayeredFS, LayeredFSError};
+	use crate::layered::layers::zip::ZipLayer;
+	use crate::layered::tests::scratch_dir;
+
+	const VMT: &str = r#""LightmappedGeneric" { "$basetexture" "brick/wall01" "$surfaceprop" "brick" "$basetexture2" "brick/wall01" }"#;
+	/// `VMT`, raw deflated
+	const VMT_DEFLATED: [u8; 74] = [
+		83, 242, 201, 76, 207, 40, 201, 77, 44, 40, 72, 77, 113, 79, 205, 75, 45, 202, 76, 86, 82, 168, 86, 80, 82, 73, 74, 44, 78, 45, 73,
+		173, 40, 41, 45, 74, 85, 82, 80, 74, 2, 138, 103, 235, 151, 39, 230, 228, 24, 24, 2, 185, 42, 197, 165, 69, 105, 137, 201, 169, 5,
+		69, 249, 5, 48, 89, 37, 84, 77, 70, 24, 186, 106, 1
+	];
+
+	/**
+	 * Builds a zip archive out of ( name, method, data, uncompressed size ) entries, without checksums.
+	 */
+	pub(crate) fn build_zip( entries: &[( &str, u16, &[u8], u32 )] ) -> Vec<u8> {
+		let mut zip = Vec::new();
+		let mut directory = Vec::new();
+		for ( name, method, data, size ) in entries {
+			let offset = zip.len() as u32;
+			let common = [
+				&method.to_le_bytes()[..], &[0; 8], &( data.len() as u32 ).to_le_bytes(), &size.to_le_bytes(),
+				&( name.len() as u16 ).to_le_bytes(), &[0; 2]
+			].concat();
+			// local header, with an extra field the central directory doesn't have
+			zip.extend( [ &0x04034b50u32.to_le_bytes()[..], &[ 20, 0, 0, 0 ], &common[ .. 20 ], &4u16.to_le_bytes(), name.as_bytes(), &[ 0xAA; 4 ], data ].concat() );
+			directory.extend( [ &0x02014b50u32.to_le_bytes()[..], &[ 20, 0, 20, 0, 0, 0 ], &common, &[0; 6], &[0; 4], &offset.to_le_bytes(), name.as_bytes() ].concat() );
+		}
+		let count = ( entries.len() as u16 ).to_le_bytes();
+		let end = [
+			&0x06054b50u32.to_le_bytes()[..], &[0; 4], &count, &count, &( directory.len() as u32 ).to_le_bytes(),
+			&( zip.len() as u32 ).to_le_bytes(), &7u16.to_le_bytes(), b"comment"
+		].concat();
+		[ zip, directory, end ].concat()
+	}
+
+	#[test]
+	fn read_zip_entries() {
+		let root = scratch_dir( "zip" );
+		let path = root.join( "pak01.pk3" );
+		std::fs::write( &path, build_zip( &[
+			( "maps/", 0, b"", 0 ),
+			( "maps/map01.bsp", 0, b"VBSP map01", 10 ),
+			( "maps/./workshop\\map02.bsp", 0, b"VBSP map02", 10 ),
+			( "Materials/Brick/wall01.vmt", 8, &VMT_DEFLATED, VMT.len() as u32 )
+		] ) ).unwrap();
+
+		let zip = ZipLayer::new( path.clone() ).unwrap();
+		assert!( zip.contains( "materials/brick/WALL01.vmt" ) );
+		assert!( !zip.contains( "maps" ) );
+		assert_eq!( "VBSP map01", zip.get_file( "maps/map01.bsp" ).unwrap().read_string().unwrap() );
+		assert_eq!( vec![ "maps", "materials" ], zip.read_dir( "" ).unwrap().into_iter().map( |entry| entry.name ).collect::<Vec<String>>() );
+		assert_eq!( vec![ "map01.bsp", "workshop" ], zip.read_dir( "maps" ).unwrap().into_iter().map( |entry| entry.name ).collect::<Vec<String>>() );
+
+		let file = zip.get_file( "materials/brick/wall01.vmt" ).unwrap();
+		assert_eq!( VMT.len() as u64, file.size() );
+		assert_eq!( VMT, file.read_string().unwrap() );
+		let mut stream = file.stream().unwrap();
+		let mut buf = [0u8; 12];
+		stream.seek( SeekFrom::Start( 24 ) ).unwrap();
+		stream.read_exact( &mut buf ).unwrap();
+		assert_eq!( b"$basetexture", &buf );
+		stream.seek( SeekFrom::Start( 1 ) ).unwrap();
+		stream.read_exact( &mut buf ).unwrap();
+		assert_eq!( b"LightmappedG", &buf );
+
+		let mut fs = LayeredFS::new();
+		fs.add_layer( path.clone(), false ).unwrap();
+		assert_eq!( 1, fs.layer_count() );
+
+		std::fs::remove_dir_all( root ).unwrap();
+	}
+
+	#[test]
+	fn entries_stay_inside_the_archive() {
+		let root = scratch_dir( "zip_bounds" );
+		let path = root.join( "pak01.pk3" );
+		let zip = build_zip( &[ ( "maps/map01.bsp", 0, b"VBSP map01", 10 ) ] );
+		// the central directory entry, before the end record and its comment
+		let entry = zip.len() - ( 22 + "comment".len() ) - ( 46 + "maps/map01.bsp".len() );
+		assert_eq!( 0x02014b50u32.to_le_bytes(), zip[ entry .. entry + 4 ] );
+		let patched = |at: usize, value: u32| {
+			let mut zip = zip.clone();
+			zip[ at .. at + 4 ].copy_from_slice( &value.to_le_bytes() );
+			zip
+		};
+
+		// compressed size, local header offset, and an uncompressed size which doesn't match the stored data
+		for zip in [ patched( entry + 20, 1 << 30 ), patched( entry + 42, 1 << 30 ), patched( entry + 24, 11 ) ] {
+			std::fs::write( &path, zip ).unwrap();
+			assert!( matches!( ZipLayer::new( path.clone() ), Err( LayeredFSError::InvalidArchive(_) ) ) );
+		}
+
+		// the local header is bigger than the central directory says
+		let mut zip = zip.clone();
+		zip[ 28 ] = 0xFF;
+		std::fs::write( &path, zip ).unwrap();
+		let layer = ZipLayer::new( path.clone() ).unwrap();
+		assert_eq!( ErrorKind::InvalidData, layer.get_file( "maps/map01.bsp" ).err().unwrap().kind() );
+
+		std::fs::remove_dir_all( root ).unwrap();
+	}
+}
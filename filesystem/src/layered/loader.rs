@@ -0,0 +1,564 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::future::Future;
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::task::{Context, Poll, Waker};
+use std::thread::JoinHandle;
+
+use libflate::deflate::Decoder;
+use log::debug;
+
+use crate::layered::{Layer, LayeredFS, ReadLocation};
+use crate::layered::whiteout::{Hit, find};
+
+/// most requests a worker takes from the same archive in one go
+const MAX_BATCH: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+	/// prefetching, streaming of far away content
+	Background,
+	Normal,
+	/// texture streaming of visible content
+	High,
+	/// content needed to render the next frame
+	Immediate
+}
+
+/**
+ * Called on the worker thread once a file is loaded, it's not called for cancelled requests.
+ */
+pub type LoadCallback = Box<dyn FnOnce( Result<Vec<u8>, Error> ) + Send>;
+
+fn cancelled( path: &str ) -> Error {
+	Error::new( ErrorKind::Interrupted, format!( "Loading of {path} was cancelled" ) )
+}
+
+#[derive(Default)]
+struct Completion {
+	finished: bool,
+	result: Option<Result<Vec<u8>, Error>>,
+	callback: Option<LoadCallback>,
+	waker: Option<Waker>
+}
+
+struct RequestState {
+	path: String,
+	cancelled: AtomicBool,
+	completion: Mutex<Completion>,
+	done: Condvar
+}
+
+impl RequestState {
+	fn new( path: &str, callback: Option<LoadCallback> ) -> Arc<Self> {
+		Arc::new( RequestState {
+			path: path.to_string(),
+			cancelled: AtomicBool::new( false ),
+			completion: Mutex::new( Completion { callback, ..Default::default() } ),
+			done: Condvar::new()
+		} )
+	}
+
+	fn is_cancelled( &self ) -> bool {
+		self.cancelled.load( AtomicOrdering::Acquire )
+	}
+
+	/**
+	 * Stores the result and wakes up whoever is waiting for it, only the first call has an effect.
+	 */
+	fn complete( &self, result: Result<Vec<u8>, Error> ) {
+		let mut completion = self.completion.lock().unwrap();
+		if completion.finished {
+			return;
+		}
+		completion.finished = true;
+
+		let callback = completion.callback.take();
+		let result = match self.is_cancelled() {
+			true => Err( cancelled( self.path.as_str() ) ),
+			false => result
+		};
+		let callback = match callback {
+			Some( callback ) if !self.is_cancelled() => Some( ( callback, result ) ),
+			_ => {
+				completion.result = Some( result );
+				None
+			}
+		};
+		let waker = completion.waker.take();
+		drop( completion );
+
+		self.done.notify_all();
+		if let Some( waker ) = waker {
+			waker.wake();
+		}
+		// outside the lock, the callback may well look at its own handle
+		if let Some( ( callback, result ) ) = callback {
+			callback( result );
+		}
+	}
+
+	fn take_result( &self, completion: &mut Completion ) -> Result<Vec<u8>, Error> {
+		completion.result.take().unwrap_or_else( || Err( Error::new(
+			ErrorKind::Other,
+			format!( "The result of loading {} was already taken or handed to its callback", self.path )
+		) ) )
+	}
+}
+
+/**
+ * A queued load, it can be waited on, or awaited as a future.
+ */
+pub struct LoadHandle {
+	state: Arc<RequestState>
+}
+
+impl LoadHandle {
+	pub fn path( &self ) -> &str {
+		self.state.path.as_str()
+	}
+
+	/**
+	 * Cancels the load, waiting on it returns an `Interrupted` error and its callback isn't called.
+	 * Loads which already finished aren't affected.
+	 */
+	pub fn cancel( &self ) {
+		self.state.cancelled.store( true, AtomicOrdering::Release );
+		self.state.complete( Err( cancelled( self.path() ) ) );
+	}
+
+	pub fn is_finished( &self ) -> bool {
+		self.state.completion.lock().unwrap().finished
+	}
+
+	/**
+	 * Blocks until the load finishes, returning the contents of the file.
+	 */
+	pub fn wait( self ) -> Result<Vec<u8>, Error> {
+		let mut completion = self.state.completion.lock().unwrap();
+		while !completion.finished {
+			completion = self.state.done.wait( completion ).unwrap();
+		}
+		self.state.take_result( &mut completion )
+	}
+}
+
+impl Future for LoadHandle {
+	type Output = Result<Vec<u8>, Error>;
+
+	fn poll( self: Pin<&mut Self>, cx: &mut Context<'_> ) -> Poll<Self::Output> {
+		let mut completion = self.state.completion.lock().unwrap();
+		if completion.finished {
+			return Poll::Ready( self.state.take_result( &mut completion ) );
+		}
+		completion.waker = Some( cx.waker().clone() );
+		Poll::Pending
+	}
+}
+
+/**
+ * What a worker reads to serve a request.
+ */
+enum Source {
+	Location( ReadLocation ),
+	/// the layer can't locate its files, they are read through `get_file()`, which may have to download them first
+	Layer( Arc<dyn Layer> )
+}
+
+impl Source {
+	fn archive( &self ) -> Option<&PathBuf> {
+		match self {
+			Source::Location( location ) => location.archive(),
+			Source::Layer(_) => None
+		}
+	}
+}
+
+struct Request {
+	source: Source,
+	priority: Priority,
+	/// submission order, among requests with the same priority the oldest goes first
+	sequence: u64,
+	state: Arc<RequestState>
+}
+
+impl Request {
+	fn offset( &self ) -> u64 {
+		match self.source {
+			Source::Location( ReadLocation::Section { offset, .. } | ReadLocation::Deflated { offset, .. } ) => offset,
+			_ => 0
+		}
+	}
+}
+
+impl PartialEq for Request {
+	fn eq( &self, other: &Self ) -> bool {
+		self.cmp( other ) == Ordering::Equal
+	}
+}
+
+impl Eq for Request { }
+
+impl PartialOrd for Request {
+	fn partial_cmp( &self, other: &Self ) -> Option<Ordering> {
+		Some( self.cmp( other ) )
+	}
+}
+
+impl Ord for Request {
+	fn cmp( &self, other: &Self ) -> Ordering {
+		self.priority.cmp( &other.priority ).then_with( || other.sequence.cmp( &self.sequence ) )
+	}
+}
+
+#[derive(Default)]
+struct Queue {
+	requests: BinaryHeap<Request>,
+	next_sequence: u64,
+	shutdown: bool
+}
+
+impl Queue {
+	fn push( &mut self, source: Source, priority: Priority, state: Arc<RequestState> ) {
+		let sequence = self.next_sequence;
+		self.next_sequence += 1;
+		self.requests.push( Request { source, priority, sequence, state } );
+	}
+
+	/**
+	 * Takes the top-priority request, together with the other ones reading from the same archive,
+	 * sorted by their offset so the archive is read front to back.
+	 */
+	fn take_batch( &mut self ) -> Vec<Request> {
+		let Some( first ) = self.requests.pop() else {
+			return Vec::new();
+		};
+		let Some( archive ) = first.source.archive().cloned() else {
+			return vec![ first ];
+		};
+
+		let ( mut batch, rest ): ( Vec<Request>, Vec<Request> ) = std::mem::take( &mut self.requests )
+			.into_iter()
+			.partition( |request| request.source.archive() == Some( &archive ) );
+		self.requests = rest.into();
+
+		if batch.len() >= MAX_BATCH {
+			batch.sort_by( |a, b| b.cmp( a ) );
+			for request in batch.split_off( MAX_BATCH - 1 ) {
+				self.requests.push( request );
+			}
+		}
+		batch.push( first );
+		batch.sort_by_key( |request| request.offset() );
+		batch
+	}
+}
+
+struct Shared {
+	queue: Mutex<Queue>,
+	available: Condvar
+}
+
+/**
+ * Loads files of a `LayeredFS` on a pool of worker threads.
+ *
+ * Files are looked up on the calling thread, only reading their contents happens on the workers,
+ * so changes to the layer stack don't affect already queued requests.
+ * Files of layers which can't `locate()` them, like remote ones, are opened and read on the workers.
+ * Requests are served by priority, and the ones hitting the same archive are read together.
+ */
+pub struct AsyncLoader {
+	shared: Arc<Shared>,
+	workers: Vec<JoinHandle<()>>
+}
+
+impl AsyncLoader {
+	pub fn new( workers: usize ) -> Self {
+		let shared = Arc::new( Shared { queue: Mutex::new( Queue::default() ), available: Condvar::new() } );
+		let workers = ( 0 .. workers.max( 1 ) )
+			.map( |index| {
+				let shared = shared.clone();
+				std::thread::Builder::new()
+					.name( format!( "fs-loader-{index}" ) )
+					.spawn( move || work( &shared ) )
+					.expect( "Failed to spawn a filesystem loader thread" )
+			} )
+			.collect();
+
+		AsyncLoader { shared, workers }
+	}
+
+	/**
+	 * Queues the loading of a file, the returned handle can be waited on or awaited.
+	 */
+	pub fn load( &self, fs: &LayeredFS, filename: &str, priority: Priority ) -> LoadHandle {
+		self.submit( fs, filename, priority, None )
+	}
+
+	/**
+	 * Queues the loading of a file, the callback gets its contents on the worker thread.
+	 * The returned handle can only be used to cancel the load or to check if it finished.
+	 */
+	pub fn load_with( &self, fs: &LayeredFS, filename: &str, priority: Priority, callback: LoadCallback ) -> LoadHandle {
+		self.submit( fs, filename, priority, Some( callback ) )
+	}
+
+	/**
+	 * Returns how many requests are waiting for a worker.
+	 */
+	pub fn pending( &self ) -> usize {
+		self.shared.queue.lock().unwrap().requests.len()
+	}
+
+	fn submit( &self, fs: &LayeredFS, filename: &str, priority: Priority, callback: Option<LoadCallback> ) -> LoadHandle {
+		let state = RequestState::new( filename, callback );
+		let hit = find( fs.active_layers(), filename );
+		fs.record_access( filename, &hit, true );
+		let source = match hit {
+			Hit::Found( layer ) => match layer.locate( filename ) {
+				Err( err ) if err.kind() == ErrorKind::Unsupported => Ok( Source::Layer( layer.clone() ) ),
+				location => location.map( Source::Location )
+			},
+			_ => Err( Error::new( ErrorKind::NotFound, format!( "File {filename} was not found" ) ) )
+		};
+		match source {
+			Ok( source ) => {
+				self.shared.queue.lock().unwrap().push( source, priority, state.clone() );
+				self.shared.available.notify_one();
+			}
+			Err( err ) => state.complete( Err( err ) )
+		}
+		LoadHandle { state }
+	}
+}
+
+impl Drop for AsyncLoader {
+	fn drop( &mut self ) {
+		let pending = {
+			let mut queue = self.shared.queue.lock().unwrap();
+			queue.shutdown = true;
+			std::mem::take( &mut queue.requests )
+		};
+		self.shared.available.notify_all();
+
+		for request in pending {
+			request.state.complete( Err( Error::new( ErrorKind::Interrupted, "The loader was shut down" ) ) );
+		}
+		for worker in self.workers.drain( .. ) {
+			let _ = worker.join();
+		}
+	}
+}
+
+fn work( shared: &Shared ) {
+	loop {
+		let batch = {
+			let mut queue = shared.queue.lock().unwrap();
+			while queue.requests.is_empty() && !queue.shutdown {
+				queue = shared.available.wait( queue ).unwrap();
+			}
+			if queue.shutdown {
+				return;
+			}
+			queue.take_batch()
+		};
+		if batch.len() > 1 {
+			debug!( "Loading {} files from {:?} in one batch", batch.len(), batch[0].source.archive() );
+		}
+		read_batch( batch );
+	}
+}
+
+fn read_batch( batch: Vec<Request> ) {
+	let mut archive: Option<( PathBuf, File )> = None;
+
+	for request in batch {
+		// cancelled requests are already completed
+		if request.state.is_cancelled() {
+			continue;
+		}
+		let mut with_archive = |path: PathBuf, read: &mut dyn FnMut( &mut File ) -> Result<Vec<u8>, Error>| {
+			let file = match archive.take() {
+				Some( ( open, file ) ) if open == path => Ok( file ),
+				_ => File::open( &path )
+			};
+			file.and_then( |mut file| {
+				let result = read( &mut file );
+				archive = Some( ( path, file ) );
+				result
+			} )
+		};
+		let location = match request.source {
+			Source::Location( location ) => location,
+			Source::Layer( layer ) => {
+				request.state.complete( layer.get_file( request.state.path.as_str() ).and_then( |file| file.read() ) );
+				continue;
+			}
+		};
+		let result = match location {
+			ReadLocation::Bytes( data ) => Ok( data ),
+			ReadLocation::Section { archive: path, offset, len, preload } => {
+				let mut preload = Some( preload );
+				with_archive( path, &mut |file| read_section( file, offset, len, preload.take().unwrap_or_default() ) )
+			}
+			ReadLocation::Deflated { archive: path, offset, len, size } => {
+				with_archive( path, &mut |file| inflate_section( file, offset, len, size ) )
+			}
+		};
+		request.state.complete( result );
+	}
+}
+
+/**
+ * Reads `len` bytes at `offset`, after the preloaded ones.
+ * The location comes from the archive, so it's checked against the archive size before allocating anything.
+ */
+fn read_section( file: &mut File, offset: u64, len: u64, preload: Vec<u8> ) -> Result<Vec<u8>, Error> {
+	let archive_len = file.metadata()?.len();
+	if offset.checked_add( len ).map_or( true, |end| end > archive_len ) {
+		return Err( Error::new( ErrorKind::InvalidData, format!( "Section of {len} bytes at {offset} is past the end of the archive ({archive_len} bytes)" ) ) );
+	}
+
+	let mut data = preload;
+	let start = data.len();
+	data.resize( start + len as usize, 0 );
+	file.seek( SeekFrom::Start( offset ) )?;
+	file.read_exact( &mut data[ start .. ] )?;
+	Ok( data )
+}
+
+/**
+ * Inflates a deflated section, which must not inflate to more than `size` bytes.
+ */
+fn inflate_section( file: &mut File, offset: u64, len: u64, size: u64 ) -> Result<Vec<u8>, Error> {
+	let compressed = read_section( file, offset, len, Vec::new() )?;
+	// one more byte than allowed is enough to tell a deflate bomb apart
+	let mut data = Vec::new();
+	Decoder::new( compressed.as_slice() ).take( size.saturating_add( 1 ) ).read_to_end( &mut data )?;
+	if data.len() as u64 > size {
+		return Err( Error::new( ErrorKind::InvalidData, format!( "Deflated section at {offset} inflates to more than {size} bytes" ) ) );
+	}
+	Ok( data )
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs::{File, create_dir_all, remove_dir_all, write};
+	use std::io::{Error, ErrorKind};
+	use std::path::PathBuf;
+	use std::sync::Arc;
+	use std::sync::mpsc::channel;
+
+	use uuid::Uuid;
+
+	use crate::layered::{DirEntry, Layer, LayeredFile, LayeredFS, LayerMeta, ReadLocation};
+	use crate::layered::layers::memory::MemoryLayer;
+	use crate::layered::loader::{AsyncLoader, Priority, Queue, RequestState, Source, inflate_section, read_section};
+	use crate::layered::tests::scratch_dir;
+
+	/**
+	 * A layer which can't locate its files, like a remote one.
+	 */
+	struct OpaqueLayer( MemoryLayer );
+
+	impl Layer for OpaqueLayer {
+		fn resolve( &self, filename: &str ) -> PathBuf {
+			self.0.resolve( filename )
+		}
+
+		fn contains( &self, filename: &str ) -> bool {
+			self.0.contains( filename )
+		}
+
+		fn get_file( &self, filename: &str ) -> Result<LayeredFile, Error> {
+			self.0.get_file( filename )
+		}
+
+		fn read_dir( &self, dirname: &str ) -> Result<Vec<DirEntry>, Error> {
+			self.0.read_dir( dirname )
+		}
+
+		fn meta( &self ) -> LayerMeta {
+			self.0.meta()
+		}
+
+		fn uuid( &self ) -> &Uuid {
+			self.0.uuid()
+		}
+	}
+
+	#[test]
+	fn batches_by_priority_and_archive() {
+		let section = |archive: &str, offset: u64| Source::Location( ReadLocation::Section { archive: PathBuf::from( archive ), offset, len: 4, preload: Vec::new() } );
+		let mut queue = Queue::default();
+		queue.push( section( "pak01_000.vpk", 64 ), Priority::Background, RequestState::new( "a", None ) );
+		queue.push( section( "pak01_001.vpk", 0 ), Priority::Normal, RequestState::new( "b", None ) );
+		queue.push( section( "pak01_000.vpk", 8 ), Priority::High, RequestState::new( "c", None ) );
+		queue.push( Source::Location( ReadLocation::Bytes( vec![ 1 ] ) ), Priority::Normal, RequestState::new( "d", None ) );
+
+		let paths = |batch: Vec<super::Request>| batch.iter().map( |request| request.state.path.clone() ).collect::<Vec<String>>();
+		assert_eq!( vec![ "c", "a" ], paths( queue.take_batch() ) );
+		assert_eq!( vec![ "b" ], paths( queue.take_batch() ) );
+		assert_eq!( vec![ "d" ], paths( queue.take_batch() ) );
+		assert!( queue.take_batch().is_empty() );
+	}
+
+	#[test]
+	fn load_files() {
+		let root = scratch_dir( "loader" );
+		create_dir_all( root.join( "maps" ) ).unwrap();
+		write( root.join( "maps/map01.bsp" ), "map01" ).unwrap();
+		write( root.join( "maps/map02.bsp" ), "map02" ).unwrap();
+
+		let mut fs = LayeredFS::new();
+		fs.add_layer( root.clone(), false ).unwrap();
+		let loader = AsyncLoader::new( 2 );
+
+		let handle = loader.load( &fs, "maps/map01.bsp", Priority::Immediate );
+		let ( sender, receiver ) = channel();
+		loader.load_with( &fs, "maps/map02.bsp", Priority::Background, Box::new( move |result| sender.send( result.unwrap() ).unwrap() ) );
+		assert_eq!( b"map01".to_vec(), handle.wait().unwrap() );
+		assert_eq!( b"map02".to_vec(), receiver.recv().unwrap() );
+
+		let missing = loader.load( &fs, "maps/map03.bsp", Priority::Normal );
+		assert_eq!( std::io::ErrorKind::NotFound, missing.wait().unwrap_err().kind() );
+
+		remove_dir_all( root ).unwrap();
+	}
+
+	#[test]
+	fn sections_stay_inside_the_archive() {
+		let root = scratch_dir( "loader_sections" );
+		// a stored deflate block of 16 bytes
+		let mut archive = vec![ 0x01, 16, 0, !16, !0 ];
+		archive.extend_from_slice( b"0123456789abcdef" );
+		write( root.join( "pak01.pk3" ), &archive ).unwrap();
+		let mut file = File::open( root.join( "pak01.pk3" ) ).unwrap();
+
+		assert_eq!( b"0123456789abcdef".to_vec(), inflate_section( &mut file, 0, 21, 16 ).unwrap() );
+		assert_eq!( b"01".to_vec(), read_section( &mut file, 5, 2, Vec::new() ).unwrap() );
+
+		// locations past the end, or more inflated data than declared, are refused
+		assert_eq!( ErrorKind::InvalidData, read_section( &mut file, 5, u64::MAX, Vec::new() ).unwrap_err().kind() );
+		assert_eq!( ErrorKind::InvalidData, read_section( &mut file, 20, 2, Vec::new() ).unwrap_err().kind() );
+		assert_eq!( ErrorKind::InvalidData, inflate_section( &mut file, 0, 21, 15 ).unwrap_err().kind() );
+
+		remove_dir_all( root ).unwrap();
+	}
+
+	#[test]
+	fn load_files_without_location() {
+		let mut fs = LayeredFS::new();
+		fs.insert_layer( Arc::new( OpaqueLayer( MemoryLayer::new( "remote" ).with( "maps/map01.bsp", "map01" ) ) ), false, &[] );
+		assert_eq!( std::io::ErrorKind::Unsupported, fs.locate( "maps/map01.bsp" ).unwrap_err().kind() );
+
+		let loader = AsyncLoader::new( 1 );
+		assert_eq!( b"map01".to_vec(), loader.load( &fs, "maps/map01.bsp", Priority::Normal ).wait().unwrap() );
+		assert_eq!( std::io::ErrorKind::NotFound, loader.load( &fs, "maps/map02.bsp", Priority::Normal ).wait().unwrap_err().kind() );
+	}
+}
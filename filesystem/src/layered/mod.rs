@@ -1,114 +1,423 @@
-use std::fmt::{Debug, Formatter};
-use std::io::{Error, ErrorKind};
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{Debug, Display, Formatter};
+use std::io::{Error, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
+use log::debug;
 use uuid::Uuid;
 
 use crate::layered::layers::folder::FolderLayerProvider;
 use crate::layered::layers::vpk::VpkLayerProvider;
+use crate::layered::layers::zip::ZipLayerProvider;
+use crate::layered::stream::LayeredStream;
+use crate::layered::trace::AccessTrace;
+use crate::layered::whiteout::{Hit, find, has_whiteout, is_whiteout};
 
+pub mod conflicts;
+pub mod glob;
+pub mod hash;
 pub mod layers;
+pub mod loader;
+pub mod mount;
+pub mod path_id;
+pub mod pure;
+pub mod resource;
+pub mod stack;
+pub mod stream;
+pub mod trace;
+pub mod whiteout;
+pub mod write;
 
-pub type LayeredFile<'a> = Box<dyn ILayeredFile<'a> + 'a>;
+/**
+ * Returns the directory path without leading or trailing slashes, the root is an empty string.
+ */
+pub(crate) fn trim_dir( dirname: &str ) -> &str {
+	dirname.trim_matches( |chr| chr == '/' || chr == '\\' )
+}
+
+/**
+ * Normalizes a path as all layers look files up: lowercase, with `/` as the only separator
+ * and without duplicate, leading or trailing slashes or `.` segments.
+ */
+pub fn normalize_path( path: &str ) -> String {
+	path.split( |chr| chr == '/' || chr == '\\' )
+		.filter( |segment| !segment.is_empty() && *segment != "." )
+		.map( |segment| segment.to_lowercase() )
+		.collect::<Vec<String>>()
+		.join( "/" )
+}
+
+/**
+ * Sanitizes a path coming from content, scripts or the network: the result is normalized like
+ * `normalize_path()`, with its `..` segments applied, and never leaves the root of a layer.
+ * A leading slash refers to the root of the layer, not of the host.
+ */
+pub fn sanitize_path( path: &str ) -> Result<String, LayeredFSError> {
+	let first = path.split( |chr| chr == '/' || chr == '\\' ).next().unwrap_or_default();
+	let drive = first.len() == 2 && first.ends_with( ':' ) && first.starts_with( |chr: char| chr.is_ascii_alphabetic() );
+	if drive || path.starts_with( "\\\\" ) {
+		return Err( LayeredFSError::AbsolutePath( path.to_string() ) );
+	}
+
+	let mut parts: Vec<&str> = Vec::new();
+	let normalized = normalize_path( path );
+	for part in normalized.split( '/' ).filter( |part| !part.is_empty() ) {
+		match part {
+			".." => if parts.pop().is_none() {
+				return Err( LayeredFSError::EscapesRoot( path.to_string() ) );
+			},
+			part => parts.push( part )
+		}
+	}
+	Ok( parts.join( "/" ) )
+}
+
+/**
+ * Joins a directory and an entry name, as returned in `DirEntry.path`.
+ */
+pub(crate) fn join_path( dirname: &str, name: &str ) -> String {
+	match trim_dir( dirname ) {
+		"" => name.to_string(),
+		dir => format!( "{dir}/{name}" )
+	}
+}
+
+pub type LayeredFile = Box<dyn ILayeredFile>;
 
 #[derive(Debug)]
 pub enum LayeredFSError {
 	NoExtension,
-	Unsupported(String)
+	NotFound(PathBuf),
+	Unsupported(String),
+	/// the path goes outside of the layer root, with `..` segments or symlinks
+	EscapesRoot(String),
+	/// the path is absolute on the host, like `C:\Windows`
+	AbsolutePath(String),
+	/// the archive couldn't be parsed
+	InvalidArchive(String),
+	/// no layer in the stack has this UUID
+	UnknownLayer(Uuid)
+}
+
+impl Display for LayeredFSError {
+	fn fmt( &self, f: &mut Formatter<'_> ) -> std::fmt::Result {
+		match self {
+			LayeredFSError::NoExtension => write!( f, "path has no extension to pick a layer provider with" ),
+			LayeredFSError::NotFound( path ) => write!( f, "{path:?} was not found" ),
+			LayeredFSError::Unsupported( ext ) => write!( f, "no layer provider supports `{ext}` files" ),
+			LayeredFSError::EscapesRoot( path ) => write!( f, "path `{path}` goes outside of the layer root" ),
+			LayeredFSError::AbsolutePath( path ) => write!( f, "path `{path}` is absolute" ),
+			LayeredFSError::InvalidArchive( reason ) => write!( f, "invalid archive {reason}" ),
+			LayeredFSError::UnknownLayer( uuid ) => write!( f, "layer {uuid} is not in the stack" )
+		}
+	}
+}
+
+impl std::error::Error for LayeredFSError { }
+
+impl From<LayeredFSError> for Error {
+	fn from( err: LayeredFSError ) -> Self {
+		let kind = match err {
+			LayeredFSError::NotFound(_) | LayeredFSError::UnknownLayer(_) => ErrorKind::NotFound,
+			LayeredFSError::EscapesRoot(_) | LayeredFSError::AbsolutePath(_) => ErrorKind::PermissionDenied,
+			LayeredFSError::NoExtension | LayeredFSError::Unsupported(_) => ErrorKind::Unsupported,
+			LayeredFSError::InvalidArchive(_) => ErrorKind::InvalidData
+		};
+		Error::new( kind, err )
+	}
 }
 
 pub trait LayerProvider: Sync + Send {
 	fn supports( &self, path: &PathBuf ) -> bool;
-	fn create<'a>( &self, path: PathBuf ) -> Result<Arc<dyn Layer + 'a>, LayeredFSError>;
+	fn create( &self, path: PathBuf ) -> Result<Arc<dyn Layer>, LayeredFSError>;
 }
 
-pub trait ILayeredFile<'a> {
+/**
+ * A file opened from a layer, it owns what it needs to read the file or shares it with its layer,
+ * so it stays usable while layers are added or removed, and from any thread.
+ */
+pub trait ILayeredFile: Send + Sync {
 	fn size( &self ) -> u64;
-	fn read( &self ) -> Result<Vec<u8>, Error>;
-	fn read_string( &self ) -> Result<String, Error>;
-	fn layer( &self ) -> &'a Uuid;
+	/**
+	 * Opens a new stream over the contents of the file, each stream has its own position.
+	 */
+	fn stream( &self ) -> Result<LayeredStream, Error>;
+	fn layer( &self ) -> Uuid;
 	fn path( &self ) -> String;
+
+	fn read( &self ) -> Result<Vec<u8>, Error> {
+		let mut buf = Vec::with_capacity( self.size() as usize );
+		self.stream()?.read_to_end( &mut buf )?;
+		Ok( buf )
+	}
+
+	fn read_string( &self ) -> Result<String, Error> {
+		let mut string = String::with_capacity( self.size() as usize );
+		self.stream()?.read_to_string( &mut string )?;
+		Ok( string )
+	}
 }
 
+#[derive(Debug, Clone)]
 pub struct LayerMeta {
 	pub origin: Option<String>,
 	pub filename: String,
 	pub size: Option<u64>
 }
 
-pub trait Layer<'a> {
+/**
+ * An entry of a directory listing, `path` is relative to the root of the layer, with `/` as separator.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+	pub name: String,
+	pub path: String,
+	pub is_dir: bool,
+	/// the layer this entry comes from, for merged listings it's the top-priority one
+	pub layer: Uuid
+}
+
+/**
+ * Where the contents of a file are stored, so they can be read without going through its layer.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadLocation {
+	/// `len` bytes at `offset` of a file on disk, preceded by `preload`, which the layer keeps in memory
+	Section { archive: PathBuf, offset: u64, len: u64, preload: Vec<u8> },
+	/// `len` bytes of deflate data at `offset` of a file on disk, which inflate to `size` bytes
+	Deflated { archive: PathBuf, offset: u64, len: u64, size: u64 },
+	/// contents which are already in memory
+	Bytes( Vec<u8> )
+}
+
+impl ReadLocation {
+	pub fn archive( &self ) -> Option<&PathBuf> {
+		match self {
+			ReadLocation::Section { archive, .. } | ReadLocation::Deflated { archive, .. } => Some( archive ),
+			ReadLocation::Bytes(_) => None
+		}
+	}
+}
+
+/**
+ * Layers are owned by `Arc`s, shared by the filesystem, the files opened from them and any thread using them.
+ */
+pub trait Layer: Send + Sync {
 	fn resolve( &self, filename: &str ) -> PathBuf;
 	fn contains( &self, filename: &str ) -> bool;
-	fn get_file( &'a self, filename: &str ) -> Result<LayeredFile<'a>, Error>;
+	fn get_file( &self, filename: &str ) -> Result<LayeredFile, Error>;
+	/**
+	 * Lists the entries of a directory, errors with `NotFound` if this layer doesn't have it.
+	 */
+	fn read_dir( &self, dirname: &str ) -> Result<Vec<DirEntry>, Error>;
 	fn meta( &self ) -> LayerMeta;
 	fn uuid( &self ) -> &Uuid;
+	/**
+	 * Describes where the contents of a file are, for the `AsyncLoader` workers,
+	 * layers which can't are only readable with `get_file()`.
+	 */
+	fn locate( &self, filename: &str ) -> Result<ReadLocation, Error> {
+		Err( Error::new( ErrorKind::Unsupported, format!( "Layer {} can't locate {filename}", self.meta().filename ) ) )
+	}
+	/**
+	 * Returns whether this layer hides a path of the lower layers, by default with whiteout markers.
+	 */
+	fn hides( &self, filename: &str ) -> bool {
+		has_whiteout( self, filename )
+	}
+	/**
+	 * Returns whether the files come from a packed archive, as shipped, instead of loose files.
+	 */
+	fn is_archive( &self ) -> bool {
+		false
+	}
+
+	// Write operations, layers are read-only unless they override these.
+	fn is_writable( &self ) -> bool {
+		false
+	}
+	/**
+	 * Creates or overwrites a file, creating its parent directories if needed.
+	 */
+	fn write( &self, filename: &str, _data: &[u8] ) -> Result<(), Error> {
+		Err( read_only( &self.meta(), filename ) )
+	}
+	/**
+	 * Creates an empty file, errors with `AlreadyExists` if it's already there.
+	 */
+	fn create( &self, filename: &str ) -> Result<(), Error> {
+		Err( read_only( &self.meta(), filename ) )
+	}
+	/**
+	 * Removes a file or an empty directory.
+	 */
+	fn remove( &self, filename: &str ) -> Result<(), Error> {
+		Err( read_only( &self.meta(), filename ) )
+	}
+	fn rename( &self, from: &str, _to: &str ) -> Result<(), Error> {
+		Err( read_only( &self.meta(), from ) )
+	}
 }
 
-pub struct LayeredFS<'a> {
-	providers: Vec< Box<dyn LayerProvider + 'a> >,
-	pub layers: Vec< Arc<dyn Layer<'a>> >
+pub(crate) fn read_only( meta: &LayerMeta, filename: &str ) -> Error {
+	Error::new( ErrorKind::PermissionDenied, format!( "Cannot write {filename}: layer {} is read-only", meta.filename ) )
 }
 
-impl Debug for LayeredFS<'_> {
+/**
+ * Error for files which can't be named in an E document, or written to one.
+ */
+pub(crate) fn not_utf8( path: &Path ) -> Error {
+	Error::new( ErrorKind::InvalidInput, format!( "Path {path:?} is not valid UTF-8" ) )
+}
+
+pub struct LayeredFS {
+	providers: Vec< Box<dyn LayerProvider> >,
+	/// in priority order, see `stack` for managing them
+	layers: Vec< Arc<dyn Layer> >,
+	/// layers which are temporarily skipped by all lookups
+	disabled: HashSet<Uuid>,
+	/// search-path groups each layer belongs to, see `path_id`
+	path_ids: HashMap<Uuid, Vec<String>>,
+	/// layer which gets all writes, see `write`
+	write_layer: Option<Uuid>,
+	/// accesses recorded while tracing, see `trace`
+	trace: Mutex<Option<AccessTrace>>
+}
+
+impl Debug for LayeredFS {
 	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
 		write!( f, "LayeredFS(providerCount={}, layerCount={})", self.providers.len(), self.layer_count() )
 	}
 }
 
-impl<'a> LayeredFS<'a> {
+impl LayeredFS {
 	pub fn new() -> Self {
 		LayeredFS {
 			providers: vec![
 				Box::new( FolderLayerProvider { } ),
-				Box::new( VpkLayerProvider { } )
+				Box::new( VpkLayerProvider { } ),
+				Box::new( ZipLayerProvider { } )
 			],
-			layers: Vec::new()
+			layers: Vec::new(),
+			disabled: HashSet::new(),
+			path_ids: HashMap::new(),
+			write_layer: None,
+			trace: Mutex::new( None )
 		}
 	}
 
 	pub fn contains( &self, filename: &str ) -> bool {
-		for layer in &self.layers {
-			if layer.contains( filename ) {
-				return true;
-			}
-		}
-		false
+		let hit = find( self.active_layers(), filename );
+		self.record_access( filename, &hit, false );
+		matches!( hit, Hit::Found(_) )
 	}
 
-	pub fn get_file( &'a self, filename: &str ) -> Result<LayeredFile<'a>, Error> {
-		for layer in &self.layers {
-			if layer.contains( filename ) {
-				return layer.get_file( filename );
+	pub fn get_file( &self, filename: &str ) -> Result<LayeredFile, Error> {
+		let hit = find( self.active_layers(), filename );
+		self.record_access( filename, &hit, true );
+		match hit {
+			Hit::Found( layer ) => layer.get_file( filename ),
+			Hit::Hidden( layer ) => {
+				debug!( "File {filename} is hidden by a whiteout in layer {}", layer.meta().filename );
+				Err( Error::new(ErrorKind::NotFound, format!("File {filename} was not found, it's hidden by layer {}", layer.meta().filename ) ) )
 			}
+			Hit::Missing => Err( Error::new(ErrorKind::NotFound, format!("File {filename} was not found") ) )
 		}
-		Err( Error::new(ErrorKind::NotFound, format!("File {filename} was not found") ) )
 	}
 
 	pub fn resolve( &self, filename: &str ) -> Option<PathBuf> {
-		for layer in &self.layers {
-			if layer.contains( filename ) {
-				return Some( layer.resolve( filename ) );
-			}
+		match find( self.active_layers(), filename ) {
+			Hit::Found( layer ) => Some( layer.resolve( filename ) ),
+			_ => None
 		}
-		None
 	}
 
-	pub fn add_layer( &'a mut self, path: PathBuf, prepend: bool ) -> Result<(), LayeredFSError> {
-		for provider in &self.providers {
-			if provider.supports( &path ) {
-				let layer = provider.create( path )?;
-				if prepend {
-					self.layers.insert( 0, layer )
-				} else {
-					self.layers.push( layer )
+	/**
+	 * Returns where the contents of a file are, in the top-priority layer which has it.
+	 */
+	pub fn locate( &self, filename: &str ) -> Result<ReadLocation, Error> {
+		let hit = find( self.active_layers(), filename );
+		// locating a file is how the loader opens it
+		self.record_access( filename, &hit, true );
+		match hit {
+			Hit::Found( layer ) => layer.locate( filename ),
+			_ => Err( Error::new(ErrorKind::NotFound, format!("File {filename} was not found") ) )
+		}
+	}
+
+	/**
+	 * Lists a directory across all layers, if more layers have an entry with the same name,
+	 * ignoring case, the one from the top-priority layer is returned.
+	 * Whiteout markers aren't listed, and hide the entries of lower layers.
+	 */
+	pub fn read_dir( &self, dirname: &str ) -> Result<Vec<DirEntry>, Error> {
+		let mut entries = BTreeMap::new();
+		let mut hidden = HashSet::new();
+		let mut found = false;
+
+		for layer in self.active_layers() {
+			match layer.read_dir( dirname ) {
+				Ok( listing ) => {
+					found = true;
+					let mut whiteouts = Vec::new();
+					for entry in listing {
+						let name = entry.name.to_lowercase();
+						if is_whiteout( name.as_str() ) {
+							whiteouts.push( name[ whiteout::WHITEOUT_PREFIX.len() .. ].to_string() );
+						} else if !hidden.contains( &name ) {
+							entries.entry( name ).or_insert( entry );
+						}
+					}
+					hidden.extend( whiteouts );
 				}
-				return Ok(())
+				Err( err ) if err.kind() == ErrorKind::NotFound => { }
+				Err( err ) => return Err( err )
+			}
+			// the directory itself, or one of its parents, is hidden for the layers below
+			if !trim_dir( dirname ).is_empty() && layer.hides( dirname ) {
+				break;
+			}
+		}
+
+		if !found {
+			return Err( Error::new( ErrorKind::NotFound, format!( "Directory {dirname} was not found" ) ) );
+		}
+		Ok( entries.into_values().collect() )
+	}
+
+	pub fn add_layer( &mut self, path: PathBuf, prepend: bool ) -> Result<(), LayeredFSError> {
+		self.add_layer_with_ids( path, prepend, &[] )
+	}
+
+	/**
+	 * Same as `add_layer()`, but also tags the new layer with the given path IDs.
+	 */
+	pub fn add_layer_with_ids( &mut self, path: PathBuf, prepend: bool, path_ids: &[&str] ) -> Result<(), LayeredFSError> {
+		let Some( provider ) = self.providers.iter().find( |provider| provider.supports( &path ) ) else {
+			return match path.extension() {
+				None => Err( LayeredFSError::NoExtension ),
+				Some( ext ) => Err( LayeredFSError::Unsupported { 0: ext.to_str().unwrap().to_string() } )
 			}
+		};
+
+		let layer = provider.create( path )?;
+		self.insert_layer( layer, prepend, path_ids );
+		Ok(())
+	}
+
+	/**
+	 * Adds an already created layer, tagging it with the given path IDs.
+	 */
+	pub fn insert_layer( &mut self, layer: Arc<dyn Layer>, prepend: bool, path_ids: &[&str] ) {
+		let uuid = *layer.uuid();
+		for path_id in path_ids {
+			self.tag_layer( &uuid, path_id );
 		}
-		match path.extension() {
-			None => Err( LayeredFSError::NoExtension ),
-			Some( ext ) => Err( LayeredFSError::Unsupported { 0: ext.to_str().unwrap().to_string() } )
+		if prepend {
+			self.layers.insert( 0, layer )
+		} else {
+			self.layers.push( layer )
 		}
 	}
 
@@ -120,7 +429,7 @@ impl<'a> LayeredFS<'a> {
 		self.layers.len()
 	}
 
-	pub fn find_layer( &self, uuid: &Uuid ) -> Option<Arc<dyn Layer<'a> +'a>> {
+	pub fn find_layer( &self, uuid: &Uuid ) -> Option<Arc<dyn Layer>> {
 		for layer in &self.layers {
 			if layer.uuid() == uuid {
 				return Some(layer.clone())
@@ -129,3 +438,81 @@ impl<'a> LayeredFS<'a> {
 		None
 	}
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use std::fs::{create_dir_all, remove_dir_all};
+	use std::io::ErrorKind;
+	use std::path::PathBuf;
+	use std::sync::{Arc, RwLock};
+	use std::thread;
+
+	use crate::layered::{Layer, LayeredFile, LayeredFS};
+	use crate::layered::layers::memory::MemoryLayer;
+
+	/**
+	 * Creates an empty folder for a test, named after it and the test process so parallel runs don't share it.
+	 * Anything left there by an earlier, failed run is removed first.
+	 */
+	pub(crate) fn scratch_dir( name: &str ) -> PathBuf {
+		let root = std::env::temp_dir().join( format!( "borealis_{name}_test_{}", std::process::id() ) );
+		if root.exists() {
+			remove_dir_all( &root ).unwrap();
+		}
+		create_dir_all( &root ).unwrap();
+		root
+	}
+
+	fn assert_send_sync<T: Send + Sync>() { }
+
+	#[test]
+	fn shared_between_threads() {
+		assert_send_sync::<LayeredFS>();
+		assert_send_sync::<LayeredFile>();
+
+		let fs = Arc::new( RwLock::new( LayeredFS::new() ) );
+		fs.write().unwrap().insert_layer( Arc::new( MemoryLayer::new( "game" ).with( "scripts/titles.txt", "game titles" ) ), false, &[] );
+		let file = fs.read().unwrap().get_file( "scripts/titles.txt" ).unwrap();
+
+		// layers can be added and removed while files of the filesystem are open
+		fs.write().unwrap().insert_layer( Arc::new( MemoryLayer::new( "mod" ).with( "scripts/titles.txt", "mod titles" ) ), true, &[] );
+		fs.write().unwrap().remove_layer( &file.layer() ).unwrap();
+		assert_eq!( "game titles", file.read_string().unwrap() );
+
+		let reader = {
+			let fs = fs.clone();
+			thread::spawn( move || fs.read().unwrap().get_file( "scripts/titles.txt" ).unwrap().read_string().unwrap() )
+		};
+		assert_eq!( "mod titles", reader.join().unwrap() );
+		assert_eq!( "game titles", thread::spawn( move || file.read_string().unwrap() ).join().unwrap() );
+	}
+
+	#[test]
+	fn read_dir_merges_layers() {
+		let game = Arc::new(
+			MemoryLayer::new( "game" )
+				.with( "maps/dm_lockdown.bsp", "game lockdown" )
+				.with( "maps/dm_overwatch.bsp", "overwatch" )
+				.with( "maps/graphs/dm_lockdown.ain", "nodes" )
+		);
+		let custom = Arc::new(
+			MemoryLayer::new( "custom" )
+				.with( "maps/dm_lockdown.bsp", "custom lockdown" )
+				.with( "maps/dm_custom.bsp", "custom" )
+		);
+		let mut fs = LayeredFS::new();
+		fs.insert_layer( game.clone(), false, &[] );
+		fs.insert_layer( custom.clone(), true, &[] );
+
+		let entries = fs.read_dir( "maps" ).unwrap();
+		let names: Vec<&str> = entries.iter().map( |entry| entry.name.as_str() ).collect();
+		assert_eq!( vec![ "dm_custom.bsp", "dm_lockdown.bsp", "dm_overwatch.bsp", "graphs" ], names );
+		assert!( entries[3].is_dir );
+
+		// a file present in more than one layer is listed once, from the top-priority layer
+		assert_eq!( custom.uuid(), &entries[1].layer );
+		assert_eq!( game.uuid(), &entries[2].layer );
+
+		assert_eq!( ErrorKind::NotFound, fs.read_dir( "sound" ).unwrap_err().kind() );
+	}
+}
@@ -0,0 +1,265 @@
+use std::fs::read_to_string;
+use std::io::{Error, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use log::{info, warn};
+use tier0::commandline::CommandLine;
+use tier0::format::e;
+use tier0::format::e::E;
+
+use crate::layered::{LayeredFS, LayeredFSError, not_utf8, path_id};
+use crate::layered::layers::folder::FolderLayer;
+use crate::layered::resource::ResourceId;
+
+/// name of the base game folder, inside the game root
+pub const BASE_GAME: &str = "game";
+/// default name of the platform folder, inside the game root
+pub const PLATFORM: &str = "platform";
+/// name of the game info file, inside the game or mod folder
+pub const GAME_INFO: &str = "gameinfo.e";
+
+/**
+ * The contents of a `gameinfo.e` file.
+ *
+ * ```text
+ * name: Borealis Game
+ * archives:
+ *	pak01_dir.vpk
+ *	pak02_dir.vpk
+ * platform: platform
+ * ```
+ */
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct GameInfo {
+	pub name: String,
+	/// archives to mount, relative to the folder of the game info, in priority order
+	pub archives: Vec<String>,
+	/// folder with engine-wide content, relative to the game root
+	pub platform: Option<String>
+}
+
+impl GameInfo {
+	pub fn load( path: &Path ) -> Result<GameInfo, Error> {
+		let name = path.to_str().ok_or_else( || not_utf8( path ) )?;
+		let data = read_to_string( path )?;
+		Ok( GameInfo::from_e( &e::loads( data.as_str(), name )? ) )
+	}
+
+	pub fn from_e( info: &E ) -> GameInfo {
+		let string = |key: &str| info.get( key ).and_then( |value| value.as_str() ).map( |value| value.to_string() );
+
+		GameInfo {
+			name: string( "name" ).unwrap_or_default(),
+			archives: match info.get( "archives" ) {
+				Some( E::List { values } ) => values.iter()
+					.filter_map( |value| value.as_str() )
+					.map( |value| value.to_string() )
+					.collect(),
+				Some( E::String { val } ) => vec![ val.clone() ],
+				_ => Vec::new()
+			},
+			platform: string( "platform" )
+		}
+	}
+}
+
+/**
+ * Builds the layer stack of a game from the game folder layout:
+ *  - `$ROOT/$MOD`, and its archives, tagged `MOD` and `GAME`, if a mod is selected with `-game`
+ *  - `$ROOT/game`, and its archives, tagged `GAME`
+ *  - `$ROOT/platform`, tagged `PLATFORM`
+ *
+ * Loose files of a folder override the ones in its archives.
+ * The top-most folder is mounted writable and also tagged `DEFAULT_WRITE_PATH`, and each mount is also tagged with the
+ * path ID of its namespace ( `mod0name`, `game` and `platform` ) for `ResourceId` lookups.
+ */
+#[derive(Debug, Clone)]
+pub struct MountManager {
+	root: PathBuf,
+	mod_name: Option<String>
+}
+
+impl MountManager {
+	pub fn new( root: PathBuf ) -> Self {
+		MountManager { root, mod_name: None }
+	}
+
+	/**
+	 * Creates a mount manager which mounts the mod selected with `-game`, if any.
+	 */
+	pub fn from_command_line( root: PathBuf ) -> Self {
+		let mod_name = CommandLine::get().option( "-game" ).cloned();
+		MountManager::new( root ).with_mod( mod_name.as_deref() )
+	}
+
+	pub fn with_mod( mut self, mod_name: Option<&str> ) -> Self {
+		// `-game game` is the same as no mod at all
+		self.mod_name = mod_name
+			.filter( |name| *name != BASE_GAME )
+			.map( |name| name.to_string() );
+		self
+	}
+
+	pub fn mod_name( &self ) -> Option<&str> {
+		self.mod_name.as_deref()
+	}
+
+	pub fn game_dir( &self ) -> PathBuf {
+		self.root.join( BASE_GAME )
+	}
+
+	pub fn mod_dir( &self ) -> Option<PathBuf> {
+		self.mod_name.as_ref().map( |name| self.root.join( name ) )
+	}
+
+	/**
+	 * Mounts the game, after any layer which is already in the filesystem.
+	 * Returns the game info of the base game.
+	 *
+	 * The mod must be a folder directly inside the game root, as it gets mounted writable.
+	 */
+	pub fn mount( &self, fs: &mut LayeredFS ) -> Result<GameInfo, Error> {
+		if let Some( name ) = self.mod_name.as_deref() {
+			let mut components = Path::new( name ).components();
+			if !matches!( ( components.next(), components.next() ), ( Some( Component::Normal(_) ), None ) ) {
+				return Err( Error::new( ErrorKind::InvalidInput, format!( "mod `{name}` is not a folder inside the game root" ) ) );
+			}
+		}
+
+		let game_dir = self.game_dir();
+		let game_info = GameInfo::load( &game_dir.join( GAME_INFO ) )?;
+
+		if let Some( mod_dir ) = self.mod_dir() {
+			if !mod_dir.is_dir() {
+				return Err( LayeredFSError::NotFound( mod_dir ).into() );
+			}
+			// mods don't need their own game info, but may use it to add archives
+			let mod_info = match mod_dir.join( GAME_INFO ).exists() {
+				true => GameInfo::load( &mod_dir.join( GAME_INFO ) )?,
+				false => GameInfo::default()
+			};
+
+			let mod_name = self.mod_name.as_deref().unwrap();
+			let namespace = ResourceId::namespace_path_id( mod_name );
+			info!( "Mounting mod `{}`", mod_name );
+			fs.insert_layer( Arc::new( FolderLayer::writable( mod_dir.clone() ) ), false, &[ namespace.as_str(), path_id::MOD, path_id::GAME, path_id::DEFAULT_WRITE_PATH ] );
+			self.mount_archives( fs, &mod_dir, &mod_info, &[ namespace.as_str(), path_id::MOD, path_id::GAME ] )?;
+		}
+
+		info!( "Mounting game `{}`", game_info.name );
+		let namespace = ResourceId::namespace_path_id( BASE_GAME );
+		match self.mod_name {
+			Some(_) => fs.add_layer_with_ids( game_dir.clone(), false, &[ namespace.as_str(), path_id::GAME ] )?,
+			None => fs.insert_layer( Arc::new( FolderLayer::writable( game_dir.clone() ) ), false, &[ namespace.as_str(), path_id::GAME, path_id::DEFAULT_WRITE_PATH ] )
+		}
+		self.mount_archives( fs, &game_dir, &game_info, &[ namespace.as_str(), path_id::GAME ] )?;
+
+		let platform_dir = self.root.join( game_info.platform.as_deref().unwrap_or( PLATFORM ) );
+		if platform_dir.is_dir() {
+			fs.add_layer_with_ids( platform_dir, false, &[ ResourceId::namespace_path_id( PLATFORM ).as_str(), path_id::PLATFORM ] )?;
+		} else if game_info.platform.is_some() {
+			return Err( LayeredFSError::NotFound( platform_dir ).into() );
+		}
+
+		Ok( game_info )
+	}
+
+	fn mount_archives( &self, fs: &mut LayeredFS, dir: &Path, info: &GameInfo, path_ids: &[&str] ) -> Result<(), LayeredFSError> {
+		for archive in &info.archives {
+			let path = dir.join( archive );
+			if !path.exists() {
+				warn!( "Archive {:?} listed in the game info doesn't exist, skipping it", path );
+				continue;
+			}
+			fs.add_layer_with_ids( path, false, path_ids )?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs::{create_dir_all, remove_dir_all, write};
+	use std::io::ErrorKind;
+
+	use crate::layered::LayeredFS;
+	use crate::layered::layers::zip::tests::build_zip;
+	use crate::layered::mount::MountManager;
+	use crate::layered::path_id;
+	use crate::layered::resource::{ResourceId, ResourceLookup};
+	use crate::layered::tests::scratch_dir;
+
+	#[test]
+	fn mount_mod_over_game() {
+		let root = scratch_dir( "mount" );
+		create_dir_all( root.join( "game/scripts/webui" ) ).unwrap();
+		create_dir_all( root.join( "mod0name/scripts/webui" ) ).unwrap();
+		create_dir_all( root.join( "platform" ) ).unwrap();
+		write( root.join( "game/gameinfo.e" ), "name: Borealis Game\n" ).unwrap();
+		write( root.join( "game/scripts/webui/main_menu.ts" ), "game" ).unwrap();
+		write( root.join( "mod0name/scripts/webui/main_menu.ts" ), "mod" ).unwrap();
+
+		let mut fs = LayeredFS::new();
+		let info = MountManager::new( root.clone() ).with_mod( Some( "mod0name" ) ).mount( &mut fs ).unwrap();
+
+		assert_eq!( "Borealis Game", info.name );
+		assert_eq!( 3, fs.layer_count() );
+		assert_eq!( Some( root.join( "mod0name/scripts/webui/main_menu.ts" ) ), fs.resolve( "scripts/webui/main_menu.ts" ) );
+		assert!( fs.resolve_in( path_id::MOD, "gameinfo.e" ).is_none() );
+		assert_eq!( 1, fs.layers_in( path_id::DEFAULT_WRITE_PATH ).count() );
+		assert_eq!( 1, fs.layers_in( path_id::PLATFORM ).count() );
+
+		// writes go to the mod folder, lower layers stay untouched
+		fs.write( "cfg/config.cfg", b"volume 0.5" ).unwrap();
+		assert_eq!( Some( root.join( "mod0name/cfg/config.cfg" ) ), fs.resolve( "cfg/config.cfg" ) );
+		fs.remove( "cfg/config.cfg" ).unwrap();
+		assert!( !fs.contains( "cfg/config.cfg" ) );
+		let platform = fs.layers_in( path_id::PLATFORM ).next().unwrap().clone();
+		assert_eq!( std::io::ErrorKind::PermissionDenied, fs.set_write_layer( platform.uuid() ).unwrap_err().kind() );
+
+		let id: ResourceId = "game:scripts/webui/main_menu.ts".parse().unwrap();
+		assert_eq!( Some( root.join( "game/scripts/webui/main_menu.ts" ) ), fs.resolve_resource( &id, ResourceLookup::Namespace ) );
+		let id: ResourceId = "mod0name:gameinfo.e".parse().unwrap();
+		assert!( !fs.contains_resource( &id, ResourceLookup::Namespace ) );
+		assert_eq!( Some( root.join( "game/gameinfo.e" ) ), fs.resolve_resource( &id, ResourceLookup::Fallback ) );
+
+		remove_dir_all( root ).unwrap();
+	}
+
+	#[test]
+	fn mods_stay_inside_the_root() {
+		for name in [ "/etc", "../..", "mods/../..", "mods/mod0name", "." ] {
+			let mut fs = LayeredFS::new();
+			let err = MountManager::new( std::env::temp_dir() ).with_mod( Some( name ) ).mount( &mut fs ).unwrap_err();
+			assert_eq!( ErrorKind::InvalidInput, err.kind(), "{name}" );
+			assert_eq!( 0, fs.layer_count() );
+		}
+	}
+
+	#[test]
+	fn mount_listed_archives() {
+		let root = scratch_dir( "mount_archives" );
+		create_dir_all( root.join( "game/scripts" ) ).unwrap();
+		write( root.join( "game/gameinfo.e" ), "name: Borealis Game\narchives:\n\tpak01.pk3\n\tpak02_dir.vpk\n" ).unwrap();
+		write( root.join( "game/pak01.pk3" ), build_zip( &[
+			( "scripts/weapons.txt", 0, b"packed weapons", 14 ),
+			( "scripts/items.txt", 0, b"packed items", 12 )
+		] ) ).unwrap();
+		write( root.join( "game/scripts/weapons.txt" ), "loose weapons" ).unwrap();
+
+		let mut fs = LayeredFS::new();
+		let info = MountManager::new( root.clone() ).mount( &mut fs ).unwrap();
+
+		// the missing archive is skipped
+		assert_eq!( vec![ "pak01.pk3".to_string(), "pak02_dir.vpk".to_string() ], info.archives );
+		assert_eq!( 2, fs.layer_count() );
+		assert_eq!( 2, fs.layers_in( path_id::GAME ).count() );
+
+		// loose files override the ones in the archives
+		assert_eq!( "loose weapons", fs.get_file( "scripts/weapons.txt" ).unwrap().read_string().unwrap() );
+		assert_eq!( "packed items", fs.get_file_in( path_id::GAME, "scripts/items.txt" ).unwrap().read_string().unwrap() );
+
+		remove_dir_all( root ).unwrap();
+	}
+}
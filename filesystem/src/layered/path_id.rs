@@ -0,0 +1,131 @@
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use uuid::Uuid;
+
+use crate::layered::{Layer, LayeredFile, LayeredFS};
+use crate::layered::whiteout::{Hit, find};
+
+// Search-path groups used by the engine, layers may be tagged with any other ID too.
+/// game content, including mods
+pub const GAME: &str = "GAME";
+/// content of the active mod only
+pub const MOD: &str = "MOD";
+/// engine-wide content shared by every game
+pub const PLATFORM: &str = "PLATFORM";
+/// where configs, saves and screenshots get written to
+pub const DEFAULT_WRITE_PATH: &str = "DEFAULT_WRITE_PATH";
+
+/**
+ * Path IDs are case-insensitive, like in Source.
+ */
+fn normalize( path_id: &str ) -> String {
+	path_id.to_ascii_uppercase()
+}
+
+impl LayeredFS {
+	/**
+	 * Adds a layer to a search-path group.
+	 */
+	pub fn tag_layer( &mut self, uuid: &Uuid, path_id: &str ) {
+		let path_id = normalize( path_id );
+		let ids = self.path_ids.entry( *uuid ).or_default();
+		if !ids.contains( &path_id ) {
+			ids.push( path_id );
+		}
+	}
+
+	/**
+	 * Removes a layer from a search-path group.
+	 */
+	pub fn untag_layer( &mut self, uuid: &Uuid, path_id: &str ) {
+		let path_id = normalize( path_id );
+		if let Some( ids ) = self.path_ids.get_mut( uuid ) {
+			ids.retain( |id| *id != path_id );
+		}
+	}
+
+	/**
+	 * Returns the search-path groups a layer belongs to.
+	 */
+	pub fn layer_path_ids( &self, uuid: &Uuid ) -> &[String] {
+		self.path_ids.get( uuid ).map_or( &[], |ids| ids.as_slice() )
+	}
+
+	/**
+	 * Iterates over the enabled layers of a search-path group, in priority order.
+	 */
+	pub fn layers_in( &self, path_id: &str ) -> impl Iterator<Item = &Arc<dyn Layer>> {
+		let path_id = normalize( path_id );
+		self.active_layers()
+			.filter( move |layer| self.layer_path_ids( layer.uuid() ).contains( &path_id ) )
+	}
+
+	/**
+	 * Same as `contains()`, but only searches the layers of a search-path group.
+	 */
+	pub fn contains_in( &self, path_id: &str, filename: &str ) -> bool {
+		let hit = find( self.layers_in( path_id ), filename );
+		self.record_access( filename, &hit, false );
+		matches!( hit, Hit::Found(_) )
+	}
+
+	/**
+	 * Same as `get_file()`, but only searches the layers of a search-path group.
+	 */
+	pub fn get_file_in( &self, path_id: &str, filename: &str ) -> Result<LayeredFile, Error> {
+		let hit = find( self.layers_in( path_id ), filename );
+		self.record_access( filename, &hit, true );
+		match hit {
+			Hit::Found( layer ) => layer.get_file( filename ),
+			_ => Err( Error::new( ErrorKind::NotFound, format!( "File {filename} was not found in {path_id}" ) ) )
+		}
+	}
+
+	/**
+	 * Same as `resolve()`, but only searches the layers of a search-path group.
+	 */
+	pub fn resolve_in( &self, path_id: &str, filename: &str ) -> Option<PathBuf> {
+		match find( self.layers_in( path_id ), filename ) {
+			Hit::Found( layer ) => Some( layer.resolve( filename ) ),
+			_ => None
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::ErrorKind;
+	use std::sync::Arc;
+
+	use crate::layered::{Layer, LayeredFS};
+	use crate::layered::layers::memory::MemoryLayer;
+	use crate::layered::path_id::{GAME, MOD};
+
+	#[test]
+	fn groups_keep_layer_order() {
+		let platform = Arc::new( MemoryLayer::new( "platform" ).with( "resource/ui.res", "platform ui" ) );
+		let game = Arc::new( MemoryLayer::new( "game" ).with( "resource/ui.res", "game ui" ) );
+		let custom = Arc::new( MemoryLayer::new( "custom" ).with( "resource/ui.res", "custom ui" ) );
+		let mut fs = LayeredFS::new();
+		fs.insert_layer( platform.clone(), false, &[] );
+		fs.insert_layer( game.clone(), true, &[ GAME, MOD ] );
+		fs.insert_layer( custom.clone(), true, &[ "game" ] );
+
+		let order: Vec<_> = fs.layers_in( GAME ).map( |layer| *layer.uuid() ).collect();
+		assert_eq!( vec![ *custom.uuid(), *game.uuid() ], order );
+		assert_eq!( "custom ui", fs.get_file_in( GAME, "resource/ui.res" ).unwrap().read_string().unwrap() );
+		assert_eq!( "game ui", fs.get_file_in( MOD, "resource/ui.res" ).unwrap().read_string().unwrap() );
+
+		// untagging only removes the layer from that group
+		fs.untag_layer( custom.uuid(), "Game" );
+		assert_eq!( "game ui", fs.get_file_in( GAME, "resource/ui.res" ).unwrap().read_string().unwrap() );
+		assert_eq!( "custom ui", fs.get_file( "resource/ui.res" ).unwrap().read_string().unwrap() );
+
+		fs.untag_layer( game.uuid(), GAME );
+		assert!( !fs.contains_in( GAME, "resource/ui.res" ) );
+		assert_eq!( ErrorKind::NotFound, fs.get_file_in( GAME, "resource/ui.res" ).err().unwrap().kind() );
+		assert_eq!( &[ MOD.to_string() ], fs.layer_path_ids( game.uuid() ) );
+	}
+}
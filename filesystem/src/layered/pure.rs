@@ -0,0 +1,386 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use tier0::format::e;
+use tier0::format::e::{E, KeyValue};
+
+use crate::layered::{LayeredFS, normalize_path, not_utf8};
+use crate::layered::glob::GlobPattern;
+use crate::layered::hash::hash_file;
+use crate::layered::whiteout::{Hit, find};
+
+/**
+ * Where the files matched by a whitelist rule may be loaded from.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PureSource {
+	/// only from a packed archive, with the same contents as the server
+	Archive,
+	/// from any layer, loose files too, with the same contents as the server
+	Any
+}
+
+impl PureSource {
+	fn parse( source: &str ) -> Option<PureSource> {
+		match source.to_lowercase().as_str() {
+			"archive" => Some( PureSource::Archive ),
+			"any" => Some( PureSource::Any ),
+			_ => None
+		}
+	}
+
+	fn as_str( &self ) -> &'static str {
+		match self {
+			PureSource::Archive => "archive",
+			PureSource::Any => "any"
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PureRule {
+	/// glob pattern, see `GlobPattern`
+	pub pattern: String,
+	pub source: PureSource
+}
+
+/**
+ * The files a pure server checks, like `sv_pure` whitelists: files matched by no rule aren't checked,
+ * when more rules match a file, the last one wins.
+ *
+ * ```text
+ * rules:
+ *	.Rule:
+ *		path: materials/brick/wall*.vmt
+ *		source: any
+ *	.Rule:
+ *		path: models/weapons/v_*.mdl
+ *		source: archive
+ * ```
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PureWhitelist {
+	pub rules: Vec<PureRule>
+}
+
+impl PureWhitelist {
+	pub fn load( path: &Path ) -> Result<PureWhitelist, Error> {
+		let name = path.to_str().ok_or_else( || not_utf8( path ) )?;
+		let data = std::fs::read_to_string( path )?;
+		PureWhitelist::from_e( &e::loads( data.as_str(), name )? )
+	}
+
+	pub fn save( &self, path: &Path ) -> Result<(), Error> {
+		std::fs::write( path, e::dumps( &self.to_e() ) )
+	}
+
+	/**
+	 * Returns the rule which applies to a file, if any.
+	 */
+	pub fn rule_for( &self, filename: &str ) -> Option<&PureRule> {
+		self.rules.iter()
+			.rev()
+			.find( |rule| GlobPattern::new( rule.pattern.as_str() ).matches( filename ) )
+	}
+
+	/**
+	 * Parses a whitelist, errors with `InvalidData` on rules which can't be read, or with an unknown source,
+	 * as a typo would weaken them.
+	 */
+	pub fn from_e( whitelist: &E ) -> Result<PureWhitelist, Error> {
+		let invalid = |reason: String| Error::new( ErrorKind::InvalidData, reason );
+		let entries = match whitelist.get( "rules" ) {
+			None => return Ok( PureWhitelist::default() ),
+			Some( E::List { values } ) if !values.is_empty() => values,
+			// an empty whitelist would check nothing, rather than what was meant
+			Some(_) => return Err( invalid( "whitelist has `rules`, but no `.Rule` could be read from it".to_string() ) )
+		};
+
+		let mut rules = Vec::new();
+		for rule in entries {
+			let Some( pattern ) = rule.get( "path" ).and_then( |path| path.as_str() ) else {
+				return Err( invalid( format!( "whitelist rule without a path: {rule}" ) ) );
+			};
+			let source = rule.get( "source" ).and_then( |source| source.as_str() ).unwrap_or_default();
+			let Some( source ) = PureSource::parse( source ) else {
+				return Err( invalid( format!( "whitelist rule for `{pattern}` has unknown source `{source}`" ) ) );
+			};
+			rules.push( PureRule { pattern: pattern.to_string(), source } );
+		}
+		Ok( PureWhitelist { rules } )
+	}
+
+	pub fn to_e( &self ) -> E {
+		let rules = self.rules.iter()
+			.map( |rule| E::Object {
+				class: "Rule".to_string(),
+				fields: vec![
+					KeyValue::new( "path", E::String { val: rule.pattern.clone() } ),
+					KeyValue::new( "source", E::String { val: rule.source.as_str().to_string() } )
+				]
+			} )
+			.collect();
+		E::Map { values: vec![ KeyValue::new( "rules", E::List { values: rules } ) ] }
+	}
+}
+
+/**
+ * A whitelisted file as a client loads it.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReportedFile {
+	/// normalized path
+	pub path: String,
+	/// SHA-256 of the contents, lowercase hex
+	pub sha256: String,
+	pub size: u64,
+	/// `LayerMeta.filename` of the layer it's loaded from
+	pub layer: String,
+	/// whether that layer is an archive
+	pub archive: bool
+}
+
+/**
+ * The hashes of all whitelisted files, as sent by a client to a pure server, sorted by path.
+ *
+ * ```text
+ * files:
+ *	.File:
+ *		path: materials/brick/wall01.vmt
+ *		sha256: 5d41402abc4b2a76b9719d911017c592...
+ *		size: 5
+ *		layer: /games/borealis/game/pak01_dir.vpk
+ *		archive: true
+ * ```
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PureReport {
+	pub files: Vec<ReportedFile>
+}
+
+impl PureReport {
+	pub fn load( path: &Path ) -> Result<PureReport, Error> {
+		let name = path.to_str().ok_or_else( || not_utf8( path ) )?;
+		let data = std::fs::read_to_string( path )?;
+		Ok( PureReport::from_e( &e::loads( data.as_str(), name )? ) )
+	}
+
+	pub fn save( &self, path: &Path ) -> Result<(), Error> {
+		std::fs::write( path, e::dumps( &self.to_e() ) )
+	}
+
+	pub fn get( &self, filename: &str ) -> Option<&ReportedFile> {
+		let path = normalize_path( filename );
+		self.files.binary_search_by( |file| file.path.cmp( &path ) )
+			.ok()
+			.map( |index| &self.files[ index ] )
+	}
+
+	pub fn from_e( report: &E ) -> PureReport {
+		let string = |value: &E, key: &str| value.get( key ).and_then( |value| value.as_str() ).unwrap_or_default().to_string();
+
+		let mut files: Vec<ReportedFile> = report.get( "files" ).and_then( |files| files.as_list() ).map_or( Vec::new(), |files| {
+			files.iter()
+				.map( |file| ReportedFile {
+					path: normalize_path( string( file, "path" ).as_str() ),
+					sha256: string( file, "sha256" ).to_lowercase(),
+					size: string( file, "size" ).parse().unwrap_or( 0 ),
+					layer: string( file, "layer" ),
+					archive: string( file, "archive" ) == "true"
+				} )
+				.collect()
+		} );
+		files.sort_by( |a, b| a.path.cmp( &b.path ) );
+		PureReport { files }
+	}
+
+	pub fn to_e( &self ) -> E {
+		let string = |value: &str| E::String { val: value.to_string() };
+		let files = self.files.iter()
+			.map( |file| E::Object {
+				class: "File".to_string(),
+				fields: vec![
+					KeyValue::new( "path", string( file.path.as_str() ) ),
+					KeyValue::new( "sha256", string( file.sha256.as_str() ) ),
+					KeyValue::new( "size", E::Integer { val: file.size as i64 } ),
+					KeyValue::new( "layer", string( file.layer.as_str() ) ),
+					KeyValue::new( "archive", string( if file.archive { "true" } else { "false" } ) )
+				]
+			} )
+			.collect();
+		E::Map { values: vec![ KeyValue::new( "files", E::List { values: files } ) ] }
+	}
+}
+
+/**
+ * A whitelisted file a client loads in a way the server doesn't allow.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+	/// must come from an archive, but the client loads it from another layer
+	NotFromArchive { path: String, layer: String },
+	/// the contents differ from the server's copy
+	Modified { path: String, expected: String, reported: String },
+	/// the server doesn't have the file, so it can't be verified
+	Unknown { path: String },
+	/// the server has the file, but the client didn't report it
+	Missing { path: String }
+}
+
+impl Violation {
+	pub fn path( &self ) -> &str {
+		match self {
+			Violation::NotFromArchive { path, .. } | Violation::Modified { path, .. } | Violation::Unknown { path } | Violation::Missing { path } => path.as_str()
+		}
+	}
+}
+
+impl Display for Violation {
+	fn fmt( &self, f: &mut Formatter<'_> ) -> std::fmt::Result {
+		match self {
+			Violation::NotFromArchive { path, layer } => write!( f, "{path} must come from an archive, but is loaded from {layer}" ),
+			Violation::Modified { path, expected, reported } => write!( f, "{path} has hash {reported}, the server has {expected}" ),
+			Violation::Unknown { path } => write!( f, "{path} is whitelisted but the server doesn't have it" ),
+			Violation::Missing { path } => write!( f, "{path} is whitelisted but the client didn't report it" )
+		}
+	}
+}
+
+impl LayeredFS {
+	/**
+	 * Hashes every whitelisted file as this filesystem resolves it, what a client sends to a pure server.
+	 */
+	pub fn pure_report( &self, whitelist: &PureWhitelist ) -> Result<PureReport, Error> {
+		let mut files = BTreeMap::new();
+		for rule in &whitelist.rules {
+			for entry in self.glob( rule.pattern.as_str() )?.into_iter().filter( |entry| !entry.is_dir ) {
+				let path = normalize_path( entry.path.as_str() );
+				if files.contains_key( &path ) {
+					continue;
+				}
+				let Hit::Found( layer ) = find( self.active_layers(), path.as_str() ) else {
+					continue;
+				};
+				let file = layer.get_file( path.as_str() )?;
+				files.insert( path.clone(), ReportedFile {
+					sha256: hash_file( file.as_ref() )?,
+					size: file.size(),
+					layer: layer.meta().filename,
+					archive: layer.is_archive(),
+					path
+				} );
+			}
+		}
+		Ok( PureReport { files: files.into_values().collect() } )
+	}
+
+	/**
+	 * Checks a client's report against the files of this filesystem, as a pure server,
+	 * returns the violations by path, none means the client may play.
+	 */
+	pub fn check_pure( &self, whitelist: &PureWhitelist, report: &PureReport ) -> Result<Vec<Violation>, Error> {
+		let server = self.pure_report( whitelist )?;
+		let mut violations = Vec::new();
+		for file in &report.files {
+			let Some( rule ) = whitelist.rule_for( file.path.as_str() ) else {
+				continue;
+			};
+			if rule.source == PureSource::Archive && !file.archive {
+				violations.push( Violation::NotFromArchive { path: file.path.clone(), layer: file.layer.clone() } );
+			}
+			match server.get( file.path.as_str() ) {
+				Some( expected ) if expected.sha256 != file.sha256 => violations.push( Violation::Modified {
+					path: file.path.clone(),
+					expected: expected.sha256.clone(),
+					reported: file.sha256.clone()
+				} ),
+				Some(_) => { }
+				None => violations.push( Violation::Unknown { path: file.path.clone() } )
+			}
+		}
+		// a client could leave out the files it modified
+		for file in &server.files {
+			if report.get( file.path.as_str() ).is_none() {
+				violations.push( Violation::Missing { path: file.path.clone() } );
+			}
+		}
+		violations.sort_by( |a, b| a.path().cmp( b.path() ) );
+		Ok( violations )
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+
+	use crate::layered::LayeredFS;
+	use crate::layered::layers::memory::MemoryLayer;
+	use crate::layered::layers::zip::tests::build_zip;
+	use crate::layered::pure::{PureReport, PureWhitelist, Violation};
+	use crate::layered::tests::scratch_dir;
+
+	#[test]
+	fn check_clients_against_the_server() {
+		let root = scratch_dir( "pure" );
+		let archive = root.join( "pak01.pk3" );
+		std::fs::write( &archive, build_zip( &[
+			( "materials/weapons/rifle.vmt", 0, b"rifle", 5 ),
+			( "materials/brick/wall01.vmt", 0, b"wall01", 6 ),
+			( "sound/theme.wav", 0, b"theme", 5 )
+		] ) ).unwrap();
+		let whitelist_path = root.join( "whitelist.e" );
+		std::fs::write( &whitelist_path, "rules:\n\t.Rule:\n\t\tpath: materials/**\n\t\tsource: any\n\t.Rule:\n\t\tpath: materials/weapons/*.vmt\n\t\tsource: archive\n" ).unwrap();
+		let whitelist = PureWhitelist::load( &whitelist_path ).unwrap();
+		assert_eq!( 2, whitelist.rules.len() );
+
+		let mut server = LayeredFS::new();
+		server.add_layer( archive.clone(), false ).unwrap();
+
+		// an unmodified client
+		let mut client = LayeredFS::new();
+		client.add_layer( archive.clone(), false ).unwrap();
+		let report = client.pure_report( &whitelist ).unwrap();
+		assert_eq!( vec![ "materials/brick/wall01.vmt", "materials/weapons/rifle.vmt" ], report.files.iter().map( |file| file.path.as_str() ).collect::<Vec<&str>>() );
+		assert!( server.check_pure( &whitelist, &report ).unwrap().is_empty() );
+
+		// a client which leaves a file out of its report
+		let mut partial = report.clone();
+		partial.files.retain( |file| file.path != "materials/brick/wall01.vmt" );
+		assert_eq!(
+			vec![ Violation::Missing { path: "materials/brick/wall01.vmt".to_string() } ],
+			server.check_pure( &whitelist, &partial ).unwrap()
+		);
+
+		// loose files over the archive, sound isn't whitelisted
+		client.insert_layer( Arc::new(
+			MemoryLayer::new( "custom" )
+				.with( "materials/weapons/rifle.vmt", "rifle" )
+				.with( "materials/brick/wall01.vmt", "see-through wall" )
+				.with( "materials/brick/wall02.vmt", "wall02" )
+				.with( "sound/theme.wav", "louder theme" )
+		), true, &[] );
+		let path = root.join( "report.e" );
+		client.pure_report( &whitelist ).unwrap().save( &path ).unwrap();
+		let report = PureReport::load( &path ).unwrap();
+		assert!( !report.get( "Materials\\Weapons\\Rifle.vmt" ).unwrap().archive );
+
+		let violations = server.check_pure( &whitelist, &report ).unwrap();
+		assert_eq!( vec![ "materials/brick/wall01.vmt", "materials/brick/wall02.vmt", "materials/weapons/rifle.vmt" ], violations.iter().map( |violation| violation.path() ).collect::<Vec<&str>>() );
+		assert!( matches!( violations[0], Violation::Modified { .. } ) );
+		assert!( matches!( violations[1], Violation::Unknown { .. } ) );
+		// same contents, but not from an archive
+		assert_eq!( Violation::NotFromArchive { path: "materials/weapons/rifle.vmt".to_string(), layer: "memory:custom".to_string() }, violations[2] );
+		assert_eq!( whitelist, PureWhitelist::from_e( &whitelist.to_e() ).unwrap() );
+
+		// rules which can't be read must not mean an empty whitelist
+		for data in [ "rules:\n", "rules:\n\tpath: materials/*\n\tsource: archive\n", "rules:\n\t.Rule:\n\t\tsource: archive\n" ] {
+			let rules = tier0::format::e::loads( data, "whitelist.e" ).unwrap();
+			assert_eq!( std::io::ErrorKind::InvalidData, PureWhitelist::from_e( &rules ).unwrap_err().kind() );
+		}
+		assert!( PureWhitelist::from_e( &tier0::format::e::loads( "name: empty\n", "whitelist.e" ).unwrap() ).unwrap().rules.is_empty() );
+
+		std::fs::remove_dir_all( root ).unwrap();
+	}
+}
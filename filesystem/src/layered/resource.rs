@@ -0,0 +1,168 @@
+use std::fmt::{Display, Formatter};
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::layered::{LayeredFile, LayeredFS};
+
+/**
+ * Identifies a resource, optionally inside a specific mount: `mod0name:scripts/webui/main_menu.ts`.
+ *
+ * The namespace is the name of a mount, its layers are tagged with `ResourceId::namespace_path_id()`
+ * ( see `MountManager` ), it's case-insensitive and kept lowercase.
+ * The path always uses `/`, without duplicate, leading or trailing slashes and without `.` or `..` segments.
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ResourceId {
+	namespace: Option<String>,
+	path: String
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResourceIdError {
+	Empty,
+	InvalidNamespace(String),
+	/// the path has more `..` segments than directories
+	EscapesRoot(String)
+}
+
+impl Display for ResourceIdError {
+	fn fmt( &self, f: &mut Formatter<'_> ) -> std::fmt::Result {
+		match self {
+			ResourceIdError::Empty => write!( f, "resource id has an empty path" ),
+			ResourceIdError::InvalidNamespace( namespace ) => write!( f, "invalid resource namespace `{namespace}`" ),
+			ResourceIdError::EscapesRoot( path ) => write!( f, "resource path `{path}` goes outside of its root" )
+		}
+	}
+}
+
+impl std::error::Error for ResourceIdError { }
+
+/**
+ * Where lookups of a namespaced `ResourceId` go.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceLookup {
+	/// only in the mount named by the namespace
+	Namespace,
+	/// in the mount named by the namespace first, then in the whole stack
+	Fallback
+}
+
+impl ResourceId {
+	pub fn new( namespace: Option<&str>, path: &str ) -> Result<Self, ResourceIdError> {
+		let namespace = match namespace {
+			Some( namespace ) => {
+				let valid = !namespace.is_empty() && namespace.chars().all( |chr| chr.is_ascii_alphanumeric() || matches!( chr, '_' | '-' | '.' ) );
+				if !valid {
+					return Err( ResourceIdError::InvalidNamespace( namespace.to_string() ) );
+				}
+				Some( namespace.to_ascii_lowercase() )
+			}
+			None => None
+		};
+
+		let mut parts: Vec<&str> = Vec::new();
+		for part in path.split( |chr| chr == '/' || chr == '\\' ) {
+			match part {
+				"" | "." => { }
+				".." => {
+					if parts.pop().is_none() {
+						return Err( ResourceIdError::EscapesRoot( path.to_string() ) );
+					}
+				}
+				part => parts.push( part )
+			}
+		}
+		if parts.is_empty() {
+			return Err( ResourceIdError::Empty );
+		}
+
+		Ok( ResourceId { namespace, path: parts.join( "/" ) } )
+	}
+
+	/**
+	 * Returns the path ID the layers of a mount are tagged with, for `game` it is `game:`,
+	 * so that mount names can't clash with the search-path groups.
+	 */
+	pub fn namespace_path_id( namespace: &str ) -> String {
+		format!( "{}:", namespace )
+	}
+
+	pub fn namespace( &self ) -> Option<&str> {
+		self.namespace.as_deref()
+	}
+
+	pub fn path( &self ) -> &str {
+		self.path.as_str()
+	}
+}
+
+impl FromStr for ResourceId {
+	type Err = ResourceIdError;
+
+	fn from_str( id: &str ) -> Result<Self, Self::Err> {
+		match id.split_once( ':' ) {
+			Some( ( namespace, path ) ) => ResourceId::new( Some( namespace ), path ),
+			None => ResourceId::new( None, id )
+		}
+	}
+}
+
+impl Display for ResourceId {
+	fn fmt( &self, f: &mut Formatter<'_> ) -> std::fmt::Result {
+		match &self.namespace {
+			Some( namespace ) => write!( f, "{}:{}", namespace, self.path ),
+			None => f.write_str( self.path.as_str() )
+		}
+	}
+}
+
+impl LayeredFS {
+	/**
+	 * Looks up a resource, ids without a namespace are always searched in the whole stack.
+	 */
+	pub fn get_resource( &self, id: &ResourceId, lookup: ResourceLookup ) -> Result<LayeredFile, Error> {
+		if let Some( namespace ) = id.namespace() {
+			let namespace = ResourceId::namespace_path_id( namespace );
+			if lookup == ResourceLookup::Namespace || self.contains_in( namespace.as_str(), id.path() ) {
+				return self.get_file_in( namespace.as_str(), id.path() );
+			}
+		}
+		self.get_file( id.path() ).map_err( |err| Error::new( ErrorKind::NotFound, format!( "Resource {id} was not found: {err}" ) ) )
+	}
+
+	pub fn contains_resource( &self, id: &ResourceId, lookup: ResourceLookup ) -> bool {
+		match ( id.namespace().map( ResourceId::namespace_path_id ), lookup ) {
+			( Some( namespace ), ResourceLookup::Namespace ) => self.contains_in( namespace.as_str(), id.path() ),
+			( Some( namespace ), ResourceLookup::Fallback ) => self.contains_in( namespace.as_str(), id.path() ) || self.contains( id.path() ),
+			( None, _ ) => self.contains( id.path() )
+		}
+	}
+
+	pub fn resolve_resource( &self, id: &ResourceId, lookup: ResourceLookup ) -> Option<PathBuf> {
+		match ( id.namespace().map( ResourceId::namespace_path_id ), lookup ) {
+			( Some( namespace ), ResourceLookup::Namespace ) => self.resolve_in( namespace.as_str(), id.path() ),
+			( Some( namespace ), ResourceLookup::Fallback ) => self.resolve_in( namespace.as_str(), id.path() ).or_else( || self.resolve( id.path() ) ),
+			( None, _ ) => self.resolve( id.path() )
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::layered::resource::{ResourceId, ResourceIdError};
+
+	#[test]
+	fn parse_resource_ids() {
+		let id: ResourceId = "Mod0Name:scripts\\webui//./main_menu.ts".parse().unwrap();
+		assert_eq!( Some( "mod0name" ), id.namespace() );
+		assert_eq!( "scripts/webui/main_menu.ts", id.path() );
+		assert_eq!( "mod0name:scripts/webui/main_menu.ts", id.to_string() );
+
+		assert_eq!( "materials/wall.vmt", "/materials/brick/../wall.vmt".parse::<ResourceId>().unwrap().to_string() );
+		assert_eq!( Err( ResourceIdError::EscapesRoot( "../cfg".to_string() ) ), "game:../cfg".parse::<ResourceId>() );
+		assert_eq!( Err( ResourceIdError::InvalidNamespace( "my mod".to_string() ) ), "my mod:cfg".parse::<ResourceId>() );
+		assert_eq!( Err( ResourceIdError::Empty ), "game:".parse::<ResourceId>() );
+	}
+}
@@ -0,0 +1,347 @@
+use std::fs::write;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use log::warn;
+use tier0::format::e;
+use tier0::format::e::{E, KeyValue};
+use uuid::Uuid;
+
+use crate::layered::{Layer, LayerMeta, LayeredFS, LayeredFSError, not_utf8};
+use crate::layered::layers::folder::FolderLayer;
+
+/**
+ * A layer of the stack, as listed by `LayeredFS::list_layers()`.
+ */
+#[derive(Debug, Clone)]
+pub struct LayerInfo {
+	pub uuid: Uuid,
+	/// position in the stack, 0 is the top-priority layer
+	pub index: usize,
+	pub meta: LayerMeta,
+	pub enabled: bool,
+	pub writable: bool,
+	pub path_ids: Vec<String>
+}
+
+/**
+ * A saved layer stack, to restore a mount configuration as it was.
+ *
+ * ```text
+ * layers:
+ *	.Layer:
+ *		path_ids:
+ *			MOD
+ *			GAME
+ *		path: /games/borealis/mod0name
+ *		enabled: true
+ *		writable: true
+ *		write: true
+ *	.Layer:
+ *		path: /games/borealis/game/pak01_dir.vpk
+ *		enabled: false
+ *		writable: false
+ * ```
+ */
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct StackConfig {
+	/// in priority order
+	pub layers: Vec<LayerConfig>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerConfig {
+	/// folder or archive the layer gets created from
+	pub path: PathBuf,
+	pub enabled: bool,
+	/// folders only, mounts the folder writable
+	pub writable: bool,
+	/// whether this is the layer set with `set_write_layer()`
+	pub write: bool,
+	pub path_ids: Vec<String>
+}
+
+impl StackConfig {
+	pub fn load( path: &Path ) -> Result<StackConfig, Error> {
+		let name = path.to_str().ok_or_else( || not_utf8( path ) )?;
+		let data = std::fs::read_to_string( path )?;
+		Ok( StackConfig::from_e( &e::loads( data.as_str(), name )? ) )
+	}
+
+	pub fn save( &self, path: &Path ) -> Result<(), Error> {
+		write( path, e::dumps( &self.to_e()? ) )
+	}
+
+	pub fn from_e( stack: &E ) -> StackConfig {
+		let Some( layers ) = stack.get( "layers" ).and_then( |layers| layers.as_list() ) else {
+			return StackConfig::default();
+		};
+
+		let mut config = StackConfig::default();
+		for layer in layers {
+			let Some( path ) = layer.get( "path" ).and_then( |path| path.as_str() ) else {
+				warn!( "Skipping layer without a path in the saved stack: {layer}" );
+				continue;
+			};
+			let flag = |key: &str, default: bool| layer.get( key ).and_then( |value| value.as_str() ).map_or( default, |value| value == "true" );
+
+			config.layers.push( LayerConfig {
+				path: PathBuf::from( path ),
+				enabled: flag( "enabled", true ),
+				writable: flag( "writable", false ),
+				write: flag( "write", false ),
+				path_ids: match layer.get( "path_ids" ) {
+					Some( E::List { values } ) => values.iter()
+						.filter_map( |value| value.as_str() )
+						.map( |value| value.to_string() )
+						.collect(),
+					Some( E::String { val } ) => vec![ val.clone() ],
+					_ => Vec::new()
+				}
+			} );
+		}
+		config
+	}
+
+	/**
+	 * Errors with `InvalidInput` if the path of a layer isn't valid UTF-8, as E files can't store it.
+	 */
+	pub fn to_e( &self ) -> Result<E, Error> {
+		let flag = |value: bool| E::String { val: value.to_string() };
+		let layers = self.layers.iter()
+			.map( |layer| {
+				let path = layer.path.to_str().ok_or_else( || not_utf8( &layer.path ) )?;
+				// a list would also take the objects after it, so it goes before the scalar fields
+				let mut fields = vec![
+					KeyValue::new( "path_ids", E::List { values: layer.path_ids.iter().map( |id| E::String { val: id.clone() } ).collect() } ),
+					KeyValue::new( "path", E::String { val: path.to_string() } ),
+					KeyValue::new( "enabled", flag( layer.enabled ) ),
+					KeyValue::new( "writable", flag( layer.writable ) )
+				];
+				if layer.write {
+					fields.push( KeyValue::new( "write", flag( true ) ) );
+				}
+				Ok( E::Object { class: "Layer".to_string(), fields } )
+			} )
+			.collect::<Result<Vec<E>, Error>>()?;
+
+		Ok( E::Map { values: vec![ KeyValue::new( "layers", E::List { values: layers } ) ] } )
+	}
+}
+
+impl LayeredFS {
+	/**
+	 * All layers in priority order, including the disabled ones.
+	 */
+	pub fn layers( &self ) -> &[Arc<dyn Layer>] {
+		self.layers.as_slice()
+	}
+
+	/**
+	 * The layers used for lookups, in priority order.
+	 */
+	pub(crate) fn active_layers( &self ) -> impl Iterator<Item = &Arc<dyn Layer>> {
+		self.layers.iter()
+			.filter( |layer| !self.disabled.contains( layer.uuid() ) )
+	}
+
+	pub fn list_layers( &self ) -> Vec<LayerInfo> {
+		self.layers.iter()
+			.enumerate()
+			.map( |( index, layer )| LayerInfo {
+				uuid: *layer.uuid(),
+				index,
+				meta: layer.meta(),
+				enabled: self.is_layer_enabled( layer.uuid() ),
+				writable: layer.is_writable(),
+				path_ids: self.layer_path_ids( layer.uuid() ).to_vec()
+			} )
+			.collect()
+	}
+
+	pub fn layer_index( &self, uuid: &Uuid ) -> Option<usize> {
+		self.layers.iter().position( |layer| layer.uuid() == uuid )
+	}
+
+	fn expect_layer_index( &self, uuid: &Uuid ) -> Result<usize, LayeredFSError> {
+		self.layer_index( uuid ).ok_or( LayeredFSError::UnknownLayer( *uuid ) )
+	}
+
+	/**
+	 * Removes a layer from the stack, together with its path IDs, files opened from it stay readable.
+	 */
+	pub fn remove_layer( &mut self, uuid: &Uuid ) -> Result<Arc<dyn Layer>, LayeredFSError> {
+		let index = self.expect_layer_index( uuid )?;
+		self.path_ids.remove( uuid );
+		self.disabled.remove( uuid );
+		if self.write_layer == Some( *uuid ) {
+			self.write_layer = None;
+		}
+		Ok( self.layers.remove( index ) )
+	}
+
+	/**
+	 * Moves a layer to another position, 0 being the top priority, indices past the end move it to the bottom.
+	 */
+	pub fn move_layer( &mut self, uuid: &Uuid, index: usize ) -> Result<(), LayeredFSError> {
+		let layer = self.layers.remove( self.expect_layer_index( uuid )? );
+		self.layers.insert( index.min( self.layers.len() ), layer );
+		Ok(())
+	}
+
+	/**
+	 * Reverses the priority order of all layers.
+	 */
+	pub fn reverse_layers( &mut self ) {
+		self.layers.reverse()
+	}
+
+	/**
+	 * Disables a layer, it stays in the stack at the same position, but is skipped by all lookups and writes.
+	 */
+	pub fn set_layer_enabled( &mut self, uuid: &Uuid, enabled: bool ) -> Result<(), LayeredFSError> {
+		self.expect_layer_index( uuid )?;
+		if enabled {
+			self.disabled.remove( uuid );
+		} else {
+			self.disabled.insert( *uuid );
+		}
+		Ok(())
+	}
+
+	pub fn is_layer_enabled( &self, uuid: &Uuid ) -> bool {
+		!self.disabled.contains( uuid )
+	}
+
+	/**
+	 * Describes the layer stack, for saving it.
+	 * Layers which aren't backed by a folder or archive, like memory layers, can't be restored and are left out.
+	 */
+	pub fn stack_config( &self ) -> StackConfig {
+		let mut config = StackConfig::default();
+		for info in self.list_layers() {
+			let path = PathBuf::from( &info.meta.filename );
+			if !path.exists() {
+				warn!( "Layer {} isn't backed by a folder or archive, it won't be saved", info.meta.filename );
+				continue;
+			}
+			config.layers.push( LayerConfig {
+				path,
+				enabled: info.enabled,
+				writable: info.writable,
+				write: self.write_layer == Some( info.uuid ),
+				path_ids: info.path_ids
+			} );
+		}
+		config
+	}
+
+	/**
+	 * Mounts a saved stack after the layers which are already in the filesystem,
+	 * on an empty filesystem this restores the stack as it was saved.
+	 */
+	pub fn restore_stack( &mut self, config: &StackConfig ) -> Result<(), LayeredFSError> {
+		for layer in &config.layers {
+			let path_ids: Vec<&str> = layer.path_ids.iter().map( |id| id.as_str() ).collect();
+			if layer.writable && layer.path.is_dir() {
+				self.insert_layer( Arc::new( FolderLayer::writable( layer.path.clone() ) ), false, &path_ids );
+			} else {
+				self.add_layer_with_ids( layer.path.clone(), false, &path_ids )?;
+			}
+
+			let uuid = *self.layers.last().unwrap().uuid();
+			if !layer.enabled {
+				self.disabled.insert( uuid );
+			}
+			if layer.write {
+				if let Err( err ) = self.set_write_layer( &uuid ) {
+					warn!( "Layer {:?} can't be the write layer again: {err}", layer.path );
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs::{create_dir_all, remove_dir_all, write};
+	use std::io::ErrorKind;
+	use std::path::PathBuf;
+	use std::sync::Arc;
+
+	use crate::layered::{Layer, LayeredFS};
+	use crate::layered::layers::folder::FolderLayer;
+	use crate::layered::layers::memory::MemoryLayer;
+	use crate::layered::stack::StackConfig;
+	use crate::layered::tests::scratch_dir;
+
+	#[test]
+	fn manage_and_restore_the_stack() {
+		let root = scratch_dir( "stack" );
+		create_dir_all( root.join( "mod/cfg" ) ).unwrap();
+		create_dir_all( root.join( "game/cfg" ) ).unwrap();
+		write( root.join( "mod/cfg/config.cfg" ), "mod" ).unwrap();
+		write( root.join( "game/cfg/config.cfg" ), "game" ).unwrap();
+
+		let mut fs = LayeredFS::new();
+		let game = Arc::new( FolderLayer::new( root.join( "game" ) ) );
+		let game_uuid = *game.uuid();
+		fs.insert_layer( game, false, &[ "GAME" ] );
+		let mod_layer = Arc::new( FolderLayer::writable( root.join( "mod" ) ) );
+		let mod_uuid = *mod_layer.uuid();
+		fs.insert_layer( mod_layer, false, &[ "MOD", "GAME" ] );
+		fs.insert_layer( Arc::new( MemoryLayer::new( "generated" ) ), false, &[] );
+		fs.set_write_layer( &mod_uuid ).unwrap();
+
+		assert_eq!( Some( root.join( "game/cfg/config.cfg" ) ), fs.resolve( "cfg/config.cfg" ) );
+		fs.move_layer( &mod_uuid, 0 ).unwrap();
+		assert_eq!( Some( root.join( "mod/cfg/config.cfg" ) ), fs.resolve( "cfg/config.cfg" ) );
+		fs.set_layer_enabled( &mod_uuid, false ).unwrap();
+		assert_eq!( Some( root.join( "game/cfg/config.cfg" ) ), fs.resolve( "cfg/config.cfg" ) );
+		assert!( fs.write( "cfg/other.cfg", b"" ).is_err() );
+		fs.set_layer_enabled( &mod_uuid, true ).unwrap();
+
+		let layers = fs.list_layers();
+		assert_eq!( vec![ mod_uuid, game_uuid ], layers.iter().take( 2 ).map( |info| info.uuid ).collect::<Vec<_>>() );
+		assert_eq!( vec![ "MOD", "GAME" ], layers[0].path_ids );
+
+		// memory layers can't be saved, everything else is restored as it was
+		fs.set_layer_enabled( &game_uuid, false ).unwrap();
+		let config = fs.stack_config();
+		assert_eq!( 2, config.layers.len() );
+		config.save( &root.join( "stack.e" ) ).unwrap();
+		let loaded = StackConfig::load( &root.join( "stack.e" ) ).unwrap();
+		assert_eq!( config, loaded );
+		// paths read back as they were, whatever they look like
+		for path in [ "/games/C# mods/game", "null", "./game", ".Layer", "mods: custom", "" ] {
+			let mut odd = config.clone();
+			odd.layers[0].path = PathBuf::from( path );
+			odd.save( &root.join( "odd.e" ) ).unwrap();
+			assert_eq!( odd, StackConfig::load( &root.join( "odd.e" ) ).unwrap() );
+		}
+		assert_eq!( ErrorKind::NotFound, StackConfig::load( &root.join( "missing.e" ) ).unwrap_err().kind() );
+		#[cfg(unix)]
+		{
+			use std::os::unix::ffi::OsStrExt;
+
+			let mut invalid = config.clone();
+			invalid.layers[0].path = root.join( std::ffi::OsStr::from_bytes( b"game\xFF" ) );
+			assert_eq!( ErrorKind::InvalidInput, invalid.save( &root.join( "invalid.e" ) ).unwrap_err().kind() );
+		}
+
+		let mut restored = LayeredFS::new();
+		restored.restore_stack( &loaded ).unwrap();
+		let layers = restored.list_layers();
+		assert_eq!( vec![ true, false ], layers.iter().map( |info| info.enabled ).collect::<Vec<_>>() );
+		assert_eq!( Some( layers[0].uuid ), restored.write_layer().map( |layer| *layer.uuid() ) );
+		assert_eq!( 1, restored.layers_in( "MOD" ).count() );
+
+		restored.remove_layer( &layers[0].uuid ).unwrap();
+		assert!( restored.write_layer().is_none() );
+		assert!( restored.move_layer( &layers[0].uuid, 0 ).is_err() );
+
+		remove_dir_all( root ).unwrap();
+	}
+}
@@ -0,0 +1,112 @@
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom};
+use std::sync::{Arc, Mutex};
+
+/**
+ * A readable and seekable stream over the contents of a layered file, it can be moved to another thread.
+ */
+pub trait FileStream: Read + Seek + Send { }
+
+impl<T: Read + Seek + Send> FileStream for T { }
+
+pub type LayeredStream = Box<dyn FileStream>;
+
+/**
+ * Computes the position a seek lands on, for a stream of `len` bytes currently at `pos`.
+ */
+pub(crate) fn seek_position( pos: u64, len: u64, from: SeekFrom ) -> Result<u64, Error> {
+	let target = match from {
+		SeekFrom::Start( offset ) => return Ok( offset ),
+		SeekFrom::End( offset ) => len as i64 + offset,
+		SeekFrom::Current( offset ) => pos as i64 + offset
+	};
+	if target < 0 {
+		return Err( Error::new( ErrorKind::InvalidInput, "Cannot seek before the start of the stream" ) );
+	}
+	Ok( target as u64 )
+}
+
+/**
+ * A stream over the `[start, start + len)` range of a file, which may be shared with other streams:
+ * every read seeks the shared handle to its own position first, so the archive is only opened once.
+ */
+#[derive(Clone)]
+pub struct SectionStream {
+	file: Arc<Mutex<File>>,
+	start: u64,
+	len: u64,
+	pos: u64
+}
+
+impl SectionStream {
+	pub fn new( file: Arc<Mutex<File>>, start: u64, len: u64 ) -> Self {
+		SectionStream { file, start, len, pos: 0 }
+	}
+
+	pub fn len( &self ) -> u64 {
+		self.len
+	}
+}
+
+impl Read for SectionStream {
+	fn read( &mut self, buf: &mut [u8] ) -> Result<usize, Error> {
+		let remaining = self.len.saturating_sub( self.pos );
+		let count = ( buf.len() as u64 ).min( remaining ) as usize;
+		if count == 0 {
+			return Ok( 0 );
+		}
+
+		let mut file = self.file.lock().map_err( |_| Error::new( ErrorKind::Other, "File handle was poisoned" ) )?;
+		file.seek( SeekFrom::Start( self.start + self.pos ) )?;
+		let read = file.read( &mut buf[ .. count ] )?;
+		if read == 0 {
+			return Err( Error::new( ErrorKind::UnexpectedEof, "File is shorter than the section it contains" ) );
+		}
+		self.pos += read as u64;
+		Ok( read )
+	}
+}
+
+impl Seek for SectionStream {
+	fn seek( &mut self, from: SeekFrom ) -> Result<u64, Error> {
+		self.pos = seek_position( self.pos, self.len, from )?;
+		Ok( self.pos )
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs::{File, remove_dir_all, write};
+	use std::io::{Read, Seek, SeekFrom};
+	use std::sync::{Arc, Mutex};
+
+	use crate::layered::stream::SectionStream;
+	use crate::layered::tests::scratch_dir;
+
+	#[test]
+	fn section_streams() {
+		let root = scratch_dir( "section_stream" );
+		let path = root.join( "section.bin" );
+		write( &path, b"headerHello, world!trailer" ).unwrap();
+		let file = Arc::new( Mutex::new( File::open( &path ).unwrap() ) );
+
+		let mut first = SectionStream::new( file.clone(), 6, 13 );
+		let mut second = SectionStream::new( file, 6, 13 );
+		let mut buf = [0u8; 5];
+		first.read_exact( &mut buf ).unwrap();
+		assert_eq!( b"Hello", &buf );
+
+		// streams sharing a handle don't move each other
+		second.seek( SeekFrom::End( -6 ) ).unwrap();
+		let mut string = String::new();
+		second.read_to_string( &mut string ).unwrap();
+		assert_eq!( "world!", string );
+
+		string.clear();
+		first.read_to_string( &mut string ).unwrap();
+		assert_eq!( ", world!", string );
+		assert!( first.seek( SeekFrom::Current( -100 ) ).is_err() );
+
+		remove_dir_all( root ).unwrap();
+	}
+}
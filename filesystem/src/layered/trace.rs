@@ -0,0 +1,241 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::write;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+use tier0::format::e;
+use tier0::format::e::{E, KeyValue};
+
+use crate::layered::{LayeredFS, normalize_path, not_utf8};
+use crate::layered::whiteout::Hit;
+
+/**
+ * A file which was looked up during a trace.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessedFile {
+	/// normalized path
+	pub path: String,
+	/// `LayerMeta.filename` of the layer it resolved to, the first time it was looked up
+	pub layer: String,
+	/// where the layer has the file, see `Layer::resolve()`
+	pub source: PathBuf,
+	/// how many times it was opened with `get_file()`
+	pub opens: u64,
+	/// how many times it was checked with `contains()`
+	pub checks: u64
+}
+
+/**
+ * The files touched while tracing, for building content packs with only what's used.
+ *
+ * ```text
+ * misses:
+ *	sound/missing.wav
+ * files:
+ *	.File:
+ *		path: materials/brick/wall01.vmt
+ *		layer: /games/borealis/game/pak01_dir.vpk
+ *		source: /games/borealis/game/pak01_dir.vpk!/materials/brick/wall01.vmt
+ *		opens: 2
+ *		checks: 1
+ * ```
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct AccessManifest {
+	/// sorted by path
+	pub files: Vec<AccessedFile>,
+	/// paths which were looked up but no layer had, or a whiteout hid
+	pub misses: Vec<String>
+}
+
+impl AccessManifest {
+	pub fn load( path: &Path ) -> Result<AccessManifest, Error> {
+		let name = path.to_str().ok_or_else( || not_utf8( path ) )?;
+		let data = std::fs::read_to_string( path )?;
+		Ok( AccessManifest::from_e( &e::loads( data.as_str(), name )? ) )
+	}
+
+	pub fn save( &self, path: &Path ) -> Result<(), Error> {
+		write( path, e::dumps( &self.to_e() ) )
+	}
+
+	/**
+	 * The files which were resolved to a layer, grouped by that layer.
+	 */
+	pub fn by_layer( &self ) -> BTreeMap<&str, Vec<&AccessedFile>> {
+		let mut layers: BTreeMap<&str, Vec<&AccessedFile>> = BTreeMap::new();
+		for file in &self.files {
+			layers.entry( file.layer.as_str() ).or_default().push( file );
+		}
+		layers
+	}
+
+	pub fn from_e( manifest: &E ) -> AccessManifest {
+		let string = |value: &E, key: &str| value.get( key ).and_then( |value| value.as_str() ).unwrap_or_default().to_string();
+		let count = |value: &E, key: &str| value.get( key ).and_then( |value| value.as_str() ).and_then( |value| value.parse().ok() ).unwrap_or( 0 );
+
+		AccessManifest {
+			files: manifest.get( "files" ).and_then( |files| files.as_list() ).map_or( Vec::new(), |files| {
+				files.iter()
+					.map( |file| AccessedFile {
+						path: string( file, "path" ),
+						layer: string( file, "layer" ),
+						source: PathBuf::from( string( file, "source" ) ),
+						opens: count( file, "opens" ),
+						checks: count( file, "checks" )
+					} )
+					.collect()
+			} ),
+			misses: match manifest.get( "misses" ) {
+				Some( E::List { values } ) => values.iter()
+					.filter_map( |value| value.as_str() )
+					.map( |value| value.to_string() )
+					.collect(),
+				Some( E::String { val } ) => vec![ val.clone() ],
+				_ => Vec::new()
+			}
+		}
+	}
+
+	pub fn to_e( &self ) -> E {
+		let string = |value: &str| E::String { val: value.to_string() };
+		let files = self.files.iter()
+			.map( |file| E::Object {
+				class: "File".to_string(),
+				fields: vec![
+					KeyValue::new( "path", string( file.path.as_str() ) ),
+					KeyValue::new( "layer", string( file.layer.as_str() ) ),
+					KeyValue::new( "source", string( file.source.to_str().unwrap() ) ),
+					KeyValue::new( "opens", E::Integer { val: file.opens as i64 } ),
+					KeyValue::new( "checks", E::Integer { val: file.checks as i64 } )
+				]
+			} )
+			.collect();
+
+		// the objects of a list would take the keys after them, so the files go last
+		E::Map { values: vec![
+			KeyValue::new( "misses", E::List { values: self.misses.iter().map( |miss| string( miss.as_str() ) ).collect() } ),
+			KeyValue::new( "files", E::List { values: files } )
+		] }
+	}
+}
+
+/**
+ * What was recorded so far while tracing.
+ */
+#[derive(Default)]
+pub(crate) struct AccessTrace {
+	files: BTreeMap<String, AccessedFile>,
+	misses: BTreeSet<String>
+}
+
+impl AccessTrace {
+	fn manifest( &self ) -> AccessManifest {
+		AccessManifest {
+			files: self.files.values().cloned().collect(),
+			misses: self.misses.iter().cloned().collect()
+		}
+	}
+}
+
+impl LayeredFS {
+	/**
+	 * Starts recording every `get_file()` and `contains()`, with the layer each file resolves to,
+	 * restarting a trace which is already running.
+	 */
+	pub fn start_trace( &self ) {
+		*self.trace.lock().unwrap() = Some( AccessTrace::default() );
+	}
+
+	/**
+	 * Stops recording, returns what was recorded if tracing was running.
+	 */
+	pub fn stop_trace( &self ) -> Option<AccessManifest> {
+		self.trace.lock().unwrap().take().map( |trace| trace.manifest() )
+	}
+
+	pub fn is_tracing( &self ) -> bool {
+		self.trace.lock().unwrap().is_some()
+	}
+
+	/**
+	 * Returns what was recorded so far, tracing keeps running.
+	 */
+	pub fn trace_manifest( &self ) -> Option<AccessManifest> {
+		self.trace.lock().unwrap().as_ref().map( |trace| trace.manifest() )
+	}
+
+	/**
+	 * Records a lookup if tracing, `open` tells `get_file()` apart from `contains()`.
+	 */
+	pub(crate) fn record_access( &self, filename: &str, hit: &Hit, open: bool ) {
+		let mut trace = self.trace.lock().unwrap();
+		let Some( trace ) = trace.as_mut() else {
+			return;
+		};
+
+		let path = normalize_path( filename );
+		let Hit::Found( layer ) = hit else {
+			trace.misses.insert( path );
+			return;
+		};
+		let file = trace.files.entry( path.clone() ).or_insert_with( || AccessedFile {
+			source: layer.resolve( path.as_str() ),
+			layer: layer.meta().filename,
+			path,
+			opens: 0,
+			checks: 0
+		} );
+		match open {
+			true => file.opens += 1,
+			false => file.checks += 1
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+
+	use crate::layered::LayeredFS;
+	use crate::layered::layers::memory::MemoryLayer;
+	use crate::layered::tests::scratch_dir;
+	use crate::layered::trace::AccessManifest;
+
+	#[test]
+	fn trace_accesses() {
+		let mut fs = LayeredFS::new();
+		fs.insert_layer( Arc::new( MemoryLayer::new( "mod" ).with( "scripts/titles.txt", "mod titles" ) ), false, &[ "MOD", "GAME" ] );
+		fs.insert_layer( Arc::new(
+			MemoryLayer::new( "game" )
+				.with( "scripts/titles.txt", "game titles" )
+				.with( "materials/brick/wall01.vmt", "wall01" )
+				.with( "materials/brick/wall02.vmt", "wall02" )
+		), false, &[ "GAME" ] );
+
+		assert!( fs.get_file( "materials/brick/wall01.vmt" ).is_ok() );
+		fs.start_trace();
+		assert!( fs.get_file( "Scripts\\Titles.txt" ).is_ok() );
+		assert!( fs.contains_in( "GAME", "materials/brick/wall01.vmt" ) );
+		assert!( fs.get_file( "materials/brick/wall01.vmt" ).is_ok() );
+		assert!( !fs.contains( "sound/missing.wav" ) );
+		// the async loader opens files by locating them
+		assert!( fs.locate( "materials/brick/wall02.vmt" ).is_ok() );
+
+		let manifest = fs.stop_trace().unwrap();
+		assert!( !fs.is_tracing() );
+		assert_eq!( vec![ "materials/brick/wall01.vmt", "materials/brick/wall02.vmt", "scripts/titles.txt" ], manifest.files.iter().map( |file| file.path.as_str() ).collect::<Vec<&str>>() );
+		assert_eq!( ( 1, 1 ), ( manifest.files[0].opens, manifest.files[0].checks ) );
+		assert_eq!( ( 1, 0 ), ( manifest.files[1].opens, manifest.files[1].checks ) );
+		assert_eq!( "memory:mod", manifest.files[2].layer );
+		assert_eq!( vec![ "sound/missing.wav" ], manifest.misses );
+		assert_eq!( 2, manifest.by_layer()[ "memory:game" ].len() );
+
+		let root = scratch_dir( "trace" );
+		let path = root.join( "trace.e" );
+		manifest.save( &path ).unwrap();
+		assert_eq!( manifest, AccessManifest::load( &path ).unwrap() );
+		std::fs::remove_dir_all( root ).unwrap();
+	}
+}
@@ -0,0 +1,143 @@
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use uuid::Uuid;
+
+use crate::layered::{Layer, LayeredFS, join_path, normalize_path};
+
+/**
+ * Prefix of whiteout markers: an empty `materials/.wh.wall01.vmt` file hides `materials/wall01.vmt`
+ * in all lower layers, and `.wh.materials` hides the whole `materials` directory.
+ * A layer which has both a file and its marker still provides the file.
+ */
+pub const WHITEOUT_PREFIX: &str = ".wh.";
+
+/**
+ * Returns the path of the marker which hides a file or directory.
+ */
+pub fn whiteout_path( filename: &str ) -> String {
+	let path = normalize_path( filename );
+	match path.rsplit_once( '/' ) {
+		Some( ( dir, name ) ) => join_path( dir, format!( "{WHITEOUT_PREFIX}{name}" ).as_str() ),
+		None => format!( "{WHITEOUT_PREFIX}{path}" )
+	}
+}
+
+pub fn is_whiteout( name: &str ) -> bool {
+	name.starts_with( WHITEOUT_PREFIX )
+}
+
+/**
+ * Returns whether a layer has a marker for a path or any of its parent directories.
+ */
+pub(crate) fn has_whiteout<L: Layer + ?Sized>( layer: &L, filename: &str ) -> bool {
+	let path = normalize_path( filename );
+	let mut end = 0;
+	for segment in path.split( '/' ) {
+		end += segment.len();
+		if layer.contains( whiteout_path( &path[ .. end ] ).as_str() ) {
+			return true;
+		}
+		end += 1;
+	}
+	false
+}
+
+/**
+ * How a path resolves in a layer stack.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+	/// the top-priority layer which has the file
+	Found { layer: Uuid, layer_name: String, path: PathBuf },
+	/// the file is hidden by a whiteout in a layer above the ones which have it
+	Hidden { layer: Uuid, layer_name: String },
+	Missing
+}
+
+impl Display for Resolution {
+	fn fmt( &self, f: &mut Formatter<'_> ) -> std::fmt::Result {
+		match self {
+			Resolution::Found { layer_name, path, .. } => write!( f, "{} (from layer {layer_name})", path.display() ),
+			Resolution::Hidden { layer_name, .. } => write!( f, "hidden by a whiteout in layer {layer_name}" ),
+			Resolution::Missing => write!( f, "not found" )
+		}
+	}
+}
+
+pub(crate) enum Hit<'l> {
+	Found( &'l Arc<dyn Layer> ),
+	Hidden( &'l Arc<dyn Layer> ),
+	Missing
+}
+
+/**
+ * Finds the layer which provides a file, stopping at the first one which hides it.
+ */
+pub(crate) fn find<'l>( layers: impl Iterator<Item = &'l Arc<dyn Layer>>, filename: &str ) -> Hit<'l> {
+	for layer in layers {
+		if layer.contains( filename ) {
+			return Hit::Found( layer );
+		}
+		if layer.hides( filename ) {
+			return Hit::Hidden( layer );
+		}
+	}
+	Hit::Missing
+}
+
+impl LayeredFS {
+	/**
+	 * Explains how a path resolves, for debugging overrides and whiteouts.
+	 */
+	pub fn resolution( &self, filename: &str ) -> Resolution {
+		match find( self.active_layers(), filename ) {
+			Hit::Found( layer ) => Resolution::Found { layer: *layer.uuid(), layer_name: layer.meta().filename, path: layer.resolve( filename ) },
+			Hit::Hidden( layer ) => Resolution::Hidden { layer: *layer.uuid(), layer_name: layer.meta().filename },
+			Hit::Missing => Resolution::Missing
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+
+	use crate::layered::LayeredFS;
+	use crate::layered::layers::memory::MemoryLayer;
+	use crate::layered::whiteout::{Resolution, whiteout_path};
+
+	#[test]
+	fn whiteouts_hide_lower_layers() {
+		assert_eq!( "materials/brick/.wh.wall01.vmt", whiteout_path( "Materials\\Brick\\wall01.vmt" ) );
+
+		let mut fs = LayeredFS::new();
+		let mod_layer = MemoryLayer::new( "mod" )
+			.with( whiteout_path( "materials/brick/wall01.vmt" ).as_str(), "" )
+			.with( whiteout_path( "sound" ).as_str(), "" )
+			.with( "scripts/.wh.titles.txt", "" )
+			.with( "scripts/titles.txt", "mod titles" );
+		fs.insert_layer( Arc::new( mod_layer ), false, &[] );
+		fs.insert_layer( Arc::new(
+			MemoryLayer::new( "game" )
+				.with( "materials/brick/wall01.vmt", "wall01" )
+				.with( "materials/brick/wall02.vmt", "wall02" )
+				.with( "sound/music/theme.wav", "theme" )
+				.with( "scripts/titles.txt", "game titles" )
+		), false, &[] );
+
+		assert!( !fs.contains( "materials/brick/wall01.vmt" ) );
+		assert!( fs.resolve( "materials/brick/wall01.vmt" ).is_none() );
+		assert!( fs.get_file( "materials/brick/wall01.vmt" ).is_err() );
+		assert!( !fs.contains( "sound/music/theme.wav" ) );
+		assert!( matches!( fs.resolution( "sound/music/theme.wav" ), Resolution::Hidden { layer_name, .. } if layer_name == "memory:mod" ) );
+		// the layer with the marker may still have the file itself
+		assert_eq!( "mod titles", fs.get_file( "scripts/titles.txt" ).unwrap().read_string().unwrap() );
+
+		let names = |dir: &str| fs.read_dir( dir ).unwrap().into_iter().map( |entry| entry.name ).collect::<Vec<String>>();
+		assert_eq!( vec![ "wall02.vmt" ], names( "materials/brick" ) );
+		assert_eq!( vec![ "materials", "scripts" ], names( "" ) );
+		assert!( fs.read_dir( "sound" ).is_err() );
+	}
+}
@@ -0,0 +1,121 @@
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+
+use uuid::Uuid;
+
+use crate::layered::{Layer, LayeredFS, path_id};
+
+impl LayeredFS {
+	/**
+	 * Sets the layer which gets all writes, it must be writable.
+	 */
+	pub fn set_write_layer( &mut self, uuid: &Uuid ) -> Result<(), Error> {
+		match self.find_layer( uuid ) {
+			None => Err( Error::new( ErrorKind::NotFound, format!( "Layer {uuid} was not found" ) ) ),
+			Some( layer ) if !layer.is_writable() => Err( Error::new(
+				ErrorKind::PermissionDenied,
+				format!( "Layer {} is read-only and can't be the write layer", layer.meta().filename )
+			) ),
+			Some(_) => {
+				self.write_layer = Some( *uuid );
+				Ok(())
+			}
+		}
+	}
+
+	/**
+	 * Returns the layer which gets all writes: the one set with `set_write_layer()`,
+	 * or else the top-priority writable layer in `DEFAULT_WRITE_PATH`, disabled layers get no writes.
+	 */
+	pub fn write_layer( &self ) -> Option<Arc<dyn Layer>> {
+		if let Some( uuid ) = &self.write_layer {
+			if let Some( layer ) = self.find_layer( uuid ).filter( |_| self.is_layer_enabled( uuid ) ) {
+				return Some( layer );
+			}
+		}
+		self.layers_in( path_id::DEFAULT_WRITE_PATH )
+			.find( |layer| layer.is_writable() )
+			.cloned()
+	}
+
+	pub(crate) fn expect_write_layer( &self ) -> Result<Arc<dyn Layer>, Error> {
+		self.write_layer()
+			.ok_or_else( || Error::new( ErrorKind::PermissionDenied, "There is no writable layer to write to" ) )
+	}
+
+	/**
+	 * Creates or overwrites a file in the write layer.
+	 */
+	pub fn write( &self, filename: &str, data: &[u8] ) -> Result<(), Error> {
+		self.expect_write_layer()?.write( filename, data )
+	}
+
+	/**
+	 * Creates an empty file in the write layer, errors with `AlreadyExists` if it's already there.
+	 */
+	pub fn create( &self, filename: &str ) -> Result<(), Error> {
+		self.expect_write_layer()?.create( filename )
+	}
+
+	/**
+	 * Removes a file or an empty directory from the write layer, files in other layers are left alone.
+	 */
+	pub fn remove( &self, filename: &str ) -> Result<(), Error> {
+		self.expect_write_layer()?.remove( filename )
+	}
+
+	/**
+	 * Renames a file of the write layer.
+	 */
+	pub fn rename( &self, from: &str, to: &str ) -> Result<(), Error> {
+		self.expect_write_layer()?.rename( from, to )
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::ErrorKind;
+	use std::sync::Arc;
+
+	use crate::layered::{Layer, LayeredFS, path_id};
+	use crate::layered::layers::folder::FolderLayer;
+	use crate::layered::layers::memory::MemoryLayer;
+	use crate::layered::tests::scratch_dir;
+
+	#[test]
+	fn writes_go_to_the_write_layer() {
+		let root = scratch_dir( "write" );
+		std::fs::create_dir_all( root.join( "game" ) ).unwrap();
+		let game = Arc::new( FolderLayer::new( root.join( "game" ) ) );
+		let mut fs = LayeredFS::new();
+		fs.insert_layer( game.clone(), false, &[ path_id::GAME ] );
+
+		// read-only layers never get writes
+		assert_eq!( ErrorKind::PermissionDenied, fs.write( "cfg/config.cfg", b"volume 1" ).unwrap_err().kind() );
+		assert_eq!( ErrorKind::PermissionDenied, fs.set_write_layer( game.uuid() ).unwrap_err().kind() );
+		assert!( !root.join( "game/cfg" ).exists() );
+
+		// without an explicit write layer, the top-priority writable one in DEFAULT_WRITE_PATH is used
+		let saves = Arc::new( MemoryLayer::new( "saves" ) );
+		let custom = Arc::new( MemoryLayer::new( "custom" ) );
+		fs.insert_layer( saves.clone(), false, &[ path_id::DEFAULT_WRITE_PATH ] );
+		fs.insert_layer( custom.clone(), false, &[ path_id::DEFAULT_WRITE_PATH ] );
+		fs.write( "cfg/config.cfg", b"volume 1" ).unwrap();
+		assert!( saves.contains( "cfg/config.cfg" ) );
+		assert!( !custom.contains( "cfg/config.cfg" ) );
+
+		fs.set_write_layer( custom.uuid() ).unwrap();
+		fs.write( "cfg/config.cfg", b"volume 0.5" ).unwrap();
+		assert!( custom.contains( "cfg/config.cfg" ) );
+
+		// paths can't leave the write layer
+		let writable = Arc::new( FolderLayer::writable( root.join( "game" ) ) );
+		fs.insert_layer( writable.clone(), true, &[] );
+		fs.set_write_layer( writable.uuid() ).unwrap();
+		assert_eq!( ErrorKind::PermissionDenied, fs.write( "../escaped.cfg", b"volume 1" ).unwrap_err().kind() );
+		assert_eq!( ErrorKind::PermissionDenied, fs.write( "cfg/../../escaped.cfg", b"volume 1" ).unwrap_err().kind() );
+		assert!( !root.join( "escaped.cfg" ).exists() );
+
+		std::fs::remove_dir_all( root ).unwrap();
+	}
+}
@@ -3,9 +3,5 @@
 #![feature(thin_box)]
 #![feature(once_cell)]
 
-mod file;
+pub mod file;
 pub mod layered;
-
-pub mod filesystem {
-
-}
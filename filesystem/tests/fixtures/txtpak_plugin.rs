@@ -0,0 +1,92 @@
+/*
+ * A layer provider plugin for `.txtpak` archives, text files with a `path=contents` line per file,
+ * built as a cdylib by the plugin tests, see `include/borealis_layer_plugin.h`.
+ * It only depends on std, like a third-party plugin would only have the header.
+ */
+use std::collections::BTreeMap;
+use std::ffi::{c_char, c_void, CStr};
+
+type DirCallback = unsafe extern "C" fn( ctx: *mut c_void, name: *const c_char, is_dir: i32 );
+
+#[repr(C)]
+pub struct BorealisLayerProvider {
+	abi_version: u32,
+	name: *const c_char,
+	supports: unsafe extern "C" fn( path: *const c_char ) -> i32,
+	open: unsafe extern "C" fn( path: *const c_char ) -> *mut c_void,
+	close: unsafe extern "C" fn( archive: *mut c_void ),
+	file_size: unsafe extern "C" fn( archive: *mut c_void, filename: *const c_char ) -> i64,
+	read: unsafe extern "C" fn( archive: *mut c_void, filename: *const c_char, offset: u64, buf: *mut u8, len: u64 ) -> i64,
+	read_dir: unsafe extern "C" fn( archive: *mut c_void, dirname: *const c_char, ctx: *mut c_void, entry: DirCallback ) -> i32
+}
+
+unsafe impl Sync for BorealisLayerProvider { }
+
+type TxtPak = BTreeMap<String, Vec<u8>>;
+
+unsafe fn string<'a>( ptr: *const c_char ) -> &'a str {
+	CStr::from_ptr( ptr ).to_str().unwrap_or_default()
+}
+
+unsafe extern "C" fn supports( path: *const c_char ) -> i32 {
+	string( path ).ends_with( ".txtpak" ) as i32
+}
+
+unsafe extern "C" fn open( path: *const c_char ) -> *mut c_void {
+	let Ok( data ) = std::fs::read_to_string( string( path ) ) else {
+		return std::ptr::null_mut();
+	};
+	let pak: TxtPak = data.lines()
+		.filter_map( |line| line.split_once( '=' ) )
+		.map( |( path, contents )| ( path.to_string(), contents.as_bytes().to_vec() ) )
+		.collect();
+	Box::into_raw( Box::new( pak ) ) as *mut c_void
+}
+
+unsafe extern "C" fn close( archive: *mut c_void ) {
+	drop( Box::from_raw( archive as *mut TxtPak ) )
+}
+
+unsafe extern "C" fn file_size( archive: *mut c_void, filename: *const c_char ) -> i64 {
+	( *( archive as *mut TxtPak ) ).get( string( filename ) ).map_or( -1, |data| data.len() as i64 )
+}
+
+unsafe extern "C" fn read( archive: *mut c_void, filename: *const c_char, offset: u64, buf: *mut u8, len: u64 ) -> i64 {
+	let Some( data ) = ( *( archive as *mut TxtPak ) ).get( string( filename ) ) else {
+		return -1;
+	};
+	let rest = &data[ ( offset as usize ).min( data.len() ) .. ];
+	let read = rest.len().min( len as usize );
+	std::ptr::copy_nonoverlapping( rest.as_ptr(), buf, read );
+	read as i64
+}
+
+unsafe extern "C" fn read_dir( archive: *mut c_void, dirname: *const c_char, ctx: *mut c_void, entry: DirCallback ) -> i32 {
+	let prefix = match string( dirname ) {
+		"" => String::new(),
+		dir => format!( "{dir}/" )
+	};
+	let mut names = BTreeMap::new();
+	for path in ( *( archive as *mut TxtPak ) ).keys() {
+		if let Some( rest ) = path.strip_prefix( prefix.as_str() ) {
+			let name = rest.split( '/' ).next().unwrap();
+			names.insert( format!( "{name}\0" ), rest.contains( '/' ) as i32 );
+		}
+	}
+	for ( name, is_dir ) in &names {
+		entry( ctx, name.as_ptr() as *const c_char, *is_dir );
+	}
+	!names.is_empty() as i32
+}
+
+static PROVIDERS: [BorealisLayerProvider; 1] = [ BorealisLayerProvider {
+	abi_version: 1,
+	name: c"txtpak".as_ptr(),
+	supports, open, close, file_size, read, read_dir
+} ];
+
+#[no_mangle]
+pub unsafe extern "C" fn borealis_layer_providers( count: *mut usize ) -> *const BorealisLayerProvider {
+	*count = PROVIDERS.len();
+	PROVIDERS.as_ptr()
+}
@@ -7,7 +7,7 @@ use std::mem::size_of;
 use std::path::{Path, PathBuf};
 
 use thiserror::Error;
-use zerocopy::{FromBytes, AsBytes};
+use zerocopy::{FromBytes, FromZeroes, AsBytes};
 use crate::exts::ReadNullStringExt;
 
 #[derive(Error, Debug)]
@@ -29,7 +29,7 @@ pub enum VpkError {
 }
 
 #[repr(C, packed)]
-#[derive(FromBytes, AsBytes)]
+#[derive(FromZeroes, FromBytes, AsBytes)]
 pub struct VpkHeaderV1 {
 	signature: u32, // 0x55aa1234
 	version: u32,   // [1|2]
@@ -39,7 +39,7 @@ pub struct VpkHeaderV1 {
 }
 
 #[repr(C, packed)]
-#[derive(FromBytes, AsBytes)]
+#[derive(FromZeroes, FromBytes, AsBytes)]
 pub struct VpkHeaderV2 {
 	v1: VpkHeaderV1,
 
@@ -54,7 +54,7 @@ pub struct VpkHeaderV2 {
 }
 
 #[repr(C, packed)]
-#[derive(Debug, FromBytes, AsBytes)]
+#[derive(Debug, FromZeroes, FromBytes, AsBytes)]
 struct VpkDirEntry {
 	/// A 32bit CRC of the file's data.
 	crc: u32,
@@ -90,7 +90,13 @@ pub struct Vpk {
 impl Vpk {
 	pub fn load<P: AsRef<Path>>( path: P ) -> Result<Self, VpkError> {
 		let mut file = File::open( path.as_ref() )?;
+		Vpk::load_from( &mut file, path )
+	}
 
+	/**
+	 * Reads the directory of a vpk from any reader, `path` is where the vpk is, for finding its archives.
+	 */
+	pub fn load_from<R: Read, P: AsRef<Path>>( file: &mut R, path: P ) -> Result<Self, VpkError> {
 		let mut header_bytes = [ 0; size_of::<VpkHeaderV2>() ];
 		file.read( &mut header_bytes[ .. size_of::<VpkHeaderV1>() ] )?;
 
@@ -108,13 +114,13 @@ impl Vpk {
 		};
 
 		match v1.version {
-			1 => vpk.load_v1( &mut file, v1 )?,
+			1 => vpk.load_v1( file, v1 )?,
 			2 => {
 				file.read( &mut header_bytes[ size_of::<VpkHeaderV1>() .. ] )?;
 
 				let v2 = VpkHeaderV2::read_from_prefix( header_bytes.as_slice() ).ok_or( VpkError::Unknown )?;
 
-				vpk.load_v2( &mut file, v2 )?
+				vpk.load_v2( file, v2 )?
 			},
 			it => return Err( VpkError::UnsupportedVersion( it ) )
 		}
@@ -122,21 +128,21 @@ impl Vpk {
 		Ok( vpk )
 	}
 
-	fn load_v1( &mut self, file: &mut File, v1: VpkHeaderV1 ) -> Result<(), VpkError> {
+	fn load_v1<R: Read>( &mut self, file: &mut R, v1: VpkHeaderV1 ) -> Result<(), VpkError> {
 		self.load_tree( file )?;
 		v1;
 
 		Ok(())
 	}
 
-	fn load_v2( &mut self, file: &mut File, v2: VpkHeaderV2 ) -> Result<(), VpkError> {
+	fn load_v2<R: Read>( &mut self, file: &mut R, v2: VpkHeaderV2 ) -> Result<(), VpkError> {
 		self.load_tree( file )?;
 		v2;
 
 		Ok(())
 	}
 
-	fn load_tree( &mut self, file: &mut File ) -> Result<(), VpkError> {
+	fn load_tree<R: Read>( &mut self, file: &mut R ) -> Result<(), VpkError> {
 		while let Ok( extension ) = file.read_null_string() {
 			if extension.is_empty() {
 				break;
@@ -175,27 +181,68 @@ impl Vpk {
 
 #[cfg(test)]
 mod tests {
+	use std::io::Cursor;
+	use std::path::PathBuf;
+
 	use crate::{Vpk, VpkError};
 
+	/**
+	 * Builds the directory of a vpk with the given header, for files stored in the first archive.
+	 */
+	fn build_vpk( header: &[u32], files: &[( &str, &str, &str )] ) -> Vec<u8> {
+		let mut tree = Vec::new();
+		for ( extension, folder, filename ) in files {
+			for part in [ extension, folder, filename ] {
+				tree.extend( part.as_bytes() );
+				tree.push( 0 );
+			}
+			// crc, preload bytes, archive index, offset, length and terminator
+			tree.extend( 0u32.to_le_bytes() );
+			tree.extend( 0u16.to_le_bytes() );
+			tree.extend( 0u16.to_le_bytes() );
+			tree.extend( 0u32.to_le_bytes() );
+			tree.extend( 16u32.to_le_bytes() );
+			tree.extend( 0xffffu16.to_le_bytes() );
+			// end of the filenames and folders of this extension
+			tree.extend( [ 0, 0 ] );
+		}
+		// end of the extensions
+		tree.push( 0 );
+
+		let mut vpk: Vec<u8> = header.iter().flat_map( |field| field.to_le_bytes() ).collect();
+		vpk[ 8 .. 12 ].copy_from_slice( &( tree.len() as u32 ).to_le_bytes() );
+		vpk.extend( tree );
+		vpk
+	}
+
 	#[test]
-    fn open_vpk_v1() -> Result<(), VpkError> {
-		let vpk = Vpk::load( "C:/Program Files (x86)/Steam/steamapps/common/Portal 2/portal2_dlc4/pak01_dir.vpk" )?;
+	fn open_vpk_v1() -> Result<(), VpkError> {
+		let data = build_vpk( &[ 0x55aa1234, 1, 0 ], &[ ( "vmt", "materials/brick", "wall01" ), ( "txt", "scripts", "titles" ) ] );
+		let vpk = Vpk::load_from( &mut Cursor::new( data ), "portal2_dlc4/pak01_dir.vpk" )?;
 
-		for (path, file) in &vpk.files {
-			println!( "{:?} -> {:?}", path, file.entry )
-		}
+		assert_eq!( 1, vpk.version );
+		assert_eq!( PathBuf::from( "portal2_dlc4" ), vpk.base_path );
+		assert_eq!( 2, vpk.files.len() );
+		assert_eq!( 16, { vpk.files[ &PathBuf::from( "materials/brick/wall01.vmt" ) ].entry.entry_length } );
+		assert!( vpk.files.contains_key( &PathBuf::from( "scripts/titles.txt" ) ) );
 
 		Ok(())
 	}
 
 	#[test]
-    fn open_vpk_v2() -> Result<(), VpkError> {
-		let vpk = Vpk::load( "D:/SteamLibrary/steamapps/common/Black Mesa/hl2/hl2_materials_dir.vpk" )?;
+	fn open_vpk_v2() -> Result<(), VpkError> {
+		let data = build_vpk( &[ 0x55aa1234, 2, 0, 0, 0, 48, 0 ], &[ ( "vtf", "materials/brick", "wall01" ) ] );
+		let vpk = Vpk::load_from( &mut Cursor::new( data ), "hl2/hl2_materials_dir.vpk" )?;
 
-		for (path, file) in &vpk.files {
-			println!( "{:?} -> {:?}", path, file.entry )
-		}
+		assert_eq!( 2, vpk.version );
+		assert!( vpk.files.contains_key( &PathBuf::from( "materials/brick/wall01.vtf" ) ) );
 
 		Ok(())
 	}
+
+	#[test]
+	fn reject_invalid_signature() {
+		let data = build_vpk( &[ 0xdeadbeef, 1, 0 ], &[] );
+		assert!( matches!( Vpk::load_from( &mut Cursor::new( data ), "pak01_dir.vpk" ), Err( VpkError::InvalidSignature( 0xdeadbeef ) ) ) );
+	}
 }
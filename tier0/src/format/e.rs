@@ -1,5 +1,6 @@
 use std::fmt::{Display, Formatter};
 use std::fs::read_to_string;
+use std::io::ErrorKind;
 use std::path::Path;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -8,6 +9,20 @@ pub struct KeyValue {
 	value: E,
 }
 
+impl KeyValue {
+	pub fn new( key: &str, value: E ) -> Self {
+		KeyValue { key: key.to_string(), value }
+	}
+
+	pub fn key( &self ) -> &str {
+		&self.key
+	}
+
+	pub fn value( &self ) -> &E {
+		&self.value
+	}
+}
+
 impl Display for KeyValue {
 	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
 		f.write_fmt( format_args!( "{}: {}", self.key, self.value ) )
@@ -25,6 +40,34 @@ pub enum E {
 	Object { class: String, fields: Vec<KeyValue> },
 }
 
+impl E {
+	/**
+	 * Returns the value of a key of a map or the field of an object.
+	 */
+	pub fn get( &self, key: &str ) -> Option<&E> {
+		match self {
+			E::Map { values: fields } | E::Object { class: _, fields } => fields.iter()
+				.find( |field| field.key == key )
+				.map( |field| &field.value ),
+			_ => None
+		}
+	}
+
+	pub fn as_str( &self ) -> Option<&str> {
+		match self {
+			E::String { val } => Some( val.as_str() ),
+			_ => None
+		}
+	}
+
+	pub fn as_list( &self ) -> Option<&Vec<E>> {
+		match self {
+			E::List { values } => Some( values ),
+			_ => None
+		}
+	}
+}
+
 #[allow(unused_variables)]
 impl Display for E {
 	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -86,19 +129,43 @@ pub enum TokType {
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokValue {
 	String { value: String },
+	/// a string which was written in quotes, so it's never anything but a string
+	Quoted { value: String },
 	Int { value: i64 },
 	Float { value: f64 },
 	None
 }
 
 #[derive(Debug, Clone)]
-#[allow(dead_code)]
 pub struct Loc {
 	file: String,
 	line: usize,
 	char: usize
 }
 
+/**
+ * A document which isn't valid E, with where the problem was found.
+ */
+#[derive(Debug, Clone)]
+pub struct ParseError {
+	loc: Loc,
+	message: String
+}
+
+impl Display for ParseError {
+	fn fmt( &self, f: &mut Formatter<'_> ) -> std::fmt::Result {
+		write!( f, "{}:{}:{}: {}", self.loc.file, self.loc.line, self.loc.char, self.message )
+	}
+}
+
+impl std::error::Error for ParseError { }
+
+impl From<ParseError> for std::io::Error {
+	fn from( err: ParseError ) -> Self {
+		std::io::Error::new( ErrorKind::InvalidData, err )
+	}
+}
+
 #[derive(Debug, Clone)]
 pub struct Token {
 	typ: TokType,
@@ -119,7 +186,7 @@ impl Token {
 
 	fn string_value(&self) -> String {
 		match &self.value {
-			TokValue::String { value } => value.to_string(),
+			TokValue::String { value } | TokValue::Quoted { value } => value.to_string(),
 			TokValue::Int { value } => value.to_string(),
 			TokValue::Float { value } => value.to_string(),
 			TokValue::None => "none".to_string()
@@ -160,36 +227,93 @@ impl Tokenizer {
 		self.data[ self.index as usize ]
 	}
 
+	/**
+	 * Once a line has its key, the colons of its value which aren't followed by a space or the end of the line
+	 * are part of the value, so values like `C:\Games` and `memory:name` stay one word.
+	 * The first colon of a line always ends a key, `key:value` is still a key and its value.
+	 */
+	fn is_inner_colon( &self, offset: usize ) -> bool {
+		let has_key = self.tokens.iter()
+			.rev()
+			.take_while( |token| token.typ != TokType::Newline )
+			.any( |token| token.typ == TokType::Colon );
+		has_key && self.data[ self.index + offset ] == ':' && self.data.get( self.index + offset + 1 ).is_some_and( |next| !next.is_whitespace() )
+	}
+
 	fn add( &mut self, typ: TokType, value: TokValue ) -> () {
 		self.tokens.push(
 			Token {
 				typ,
 				value,
 				padding: self.padding,
-				loc: Loc {
-					file: self.file.clone(),
-					line: self.line,
-					char: self.char
-				}
+				loc: self.loc()
 			}
 		)
 	}
 
-	fn tokenize( &mut self ) -> Vec<Token> {
+	fn loc( &self ) -> Loc {
+		Loc {
+			file: self.file.clone(),
+			line: self.line,
+			char: self.char
+		}
+	}
+
+	/**
+	 * Reads a quoted string, where `\"`, `\\`, `\n`, `\t`, `\r` and `\0` are escapes, up to its closing quote.
+	 */
+	fn quoted( &mut self ) -> Result<String, ParseError> {
+		let error = |tokenizer: &Tokenizer, message: &str| ParseError { loc: tokenizer.loc(), message: message.to_string() };
+		let mut string = String::new();
+		self.index += 1;
+		self.char += 1;
+		loop {
+			let chr = match self.data.get( self.index ) {
+				None | Some( '\n' ) => return Err( error( self, "string is missing its closing quote" ) ),
+				Some( '"' ) => break,
+				Some( '\\' ) => {
+					self.index += 1;
+					self.char += 1;
+					match self.data.get( self.index ) {
+						Some( 'n' ) => '\n',
+						Some( 't' ) => '\t',
+						Some( 'r' ) => '\r',
+						Some( '0' ) => '\0',
+						Some( chr @ ( '"' | '\\' ) ) => *chr,
+						_ => return Err( error( self, "unknown escape in string" ) )
+					}
+				}
+				Some( chr ) => *chr
+			};
+			string.push( chr );
+			self.index += 1;
+			self.char += 1;
+		}
+		self.index += 1;
+		self.char += 1;
+		Ok( string )
+	}
+
+	fn tokenize( &mut self ) -> Result<Vec<Token>, ParseError> {
 		let delimiters: Vec<char> = vec![':', '\n', '\0', '#' ];
 		while self.index < self.data.len() {
 			match self.get_char() {
 			    '#' => {
 				    let mut string = String::new();
-				    while self.get_char() != '\n' {
+				    while self.index < self.data.len() && self.get_char() != '\n' {
 					    string.push( self.get_char() );
 					    self.index += 1;
 				    }
 				    // self.add( TokType::Comment, TokValue::String { value: string.clone() } );
 			    }
+				'"' => {
+					let loc = self.loc();
+					let value = TokValue::Quoted { value: self.quoted()? };
+					self.tokens.push( Token { typ: TokType::Word, value, padding: self.padding, loc } );
+				}
 				'\t' => {
 					let mut count = 1usize;
-					while self.get_char_o( 1 ) == '\t' {
+					while self.data.get( self.index + 1 ) == Some( &'\t' ) {
 						count += 1;
 						self.index += 1;
 					}
@@ -223,7 +347,7 @@ impl Tokenizer {
 					let mut string = String::new();
 					string.push( self.get_char() );
 
-					while self.data.len() - 1 > self.index && !delimiters.contains( &self.get_char_o( 1 ) ) {
+					while self.data.len() - 1 > self.index && ( !delimiters.contains( &self.get_char_o( 1 ) ) || self.is_inner_colon( 1 ) ) {
 						string.push( self.get_char_o(1) );
 						self.index += 1;
 					}
@@ -245,7 +369,7 @@ impl Tokenizer {
 			}
 		} );
 
-		self.tokens.clone()
+		Ok( self.tokens.clone() )
 	}
 }
 
@@ -359,19 +483,21 @@ impl Parser {
 		Some( self.consume().string_value() )
 	}
 
-	fn value( &mut self ) -> E {
+	fn value( &mut self ) -> Result<E, ParseError> {
 		if self.peek(0).typ == TokType::Class {
 			return self.object()
 		}
-		match self.consume().value {
+		Ok( match self.consume().value {
 			TokValue::Int { value } => E::Integer { val: value.clone() },
 			TokValue::Float { value } => E::Float { val: value.clone() },
-			TokValue::String { value } => E::String { val: value.clone() },
+			// `null` is how `Display` and `dumps()` write `E::None`, a quoted `"null"` is a string
+			TokValue::String { value } if value == "null" => E::None,
+			TokValue::String { value } | TokValue::Quoted { value } => E::String { val: value.clone() },
 			TokValue::None => E::None
-		}
+		} )
 	}
 
-	fn key_value(&mut self ) -> KeyValue {
+	fn key_value(&mut self ) -> Result<KeyValue, ParseError> {
 		if let Some( key ) = self.key() {
 			// there's a key
 			if self.peek( 0 ).typ == TokType::Colon {
@@ -380,24 +506,25 @@ impl Parser {
 				// its either a map or a list
 				if vec![ TokType::Value, TokType::Class ].contains( &self.peek(0).typ ) {
 					// its a list
-					return KeyValue { key: key, value: self.list() }
+					return Ok( KeyValue { key: key, value: self.list()? } )
 				}
 
 				// its a map
-				return KeyValue { key: key, value: self.map() }
+				return Ok( KeyValue { key: key, value: self.map()? } )
 
 			} else if self.peek( 0 ).typ == TokType::Value {
 				// its a key-value pair
-				return KeyValue { key: key, value: self.value() }
+				return Ok( KeyValue { key: key, value: self.value()? } )
 			} else if self.peek( 0 ).typ == TokType::Class {
 				// its a key-value of an object
-				return KeyValue { key: key, value: self.object() }
+				return Ok( KeyValue { key: key, value: self.object()? } )
 			}
+			return Err( ParseError { loc: self.peek(0).loc.clone(), message: format!( "key `{key}` has no value" ) } )
 		}
-		panic!( "What the fuck just happened?? \n\t- {:?}\n\t- {:?}", self.peek(0), self.peek(1) )
+		Err( ParseError { loc: self.peek(0).loc.clone(), message: format!( "expected a key, found {:?}", self.peek(0).typ ) } )
 	}
 
-	fn object( &mut self ) -> E {
+	fn object( &mut self ) -> Result<E, ParseError> {
 		// .ClassName:
 		//      field: value
 
@@ -408,24 +535,24 @@ impl Parser {
 			if self.peek(0).typ != TokType::Key {
 				break
 			}
-			fields.push( self.key_value() );
+			fields.push( self.key_value()? );
 		}
 
-		E::Object { class: class, fields: fields }
+		Ok( E::Object { class: class, fields: fields } )
 	}
 
-	fn list( &mut self ) -> E {
+	fn list( &mut self ) -> Result<E, ParseError> {
 		let mut items = vec![];
 		while self.index < self.tokens.len() {
 			if !vec![ TokType::Value, TokType::Class ].contains( &self.peek(0).typ ) {
 				break
 			}
-			items.push( self.value() );
+			items.push( self.value()? );
 		}
-		E::List { values: items }
+		Ok( E::List { values: items } )
 	}
 
-	fn map( &mut self ) -> E {
+	fn map( &mut self ) -> Result<E, ParseError> {
 		let mut items = vec![];
 		self.consume_if_is(TokType::Colon); // remove the newline
 
@@ -434,12 +561,12 @@ impl Parser {
 			if self.peek(0).typ != TokType::Key || self.peek(0).padding != first_key.padding {
 				break
 			}
-			items.push( self.key_value() );
+			items.push( self.key_value()? );
 		}
-		E::Map { values: items }
+		Ok( E::Map { values: items } )
 	}
 
-	fn objectify( &mut self ) -> E {
+	fn objectify( &mut self ) -> Result<E, ParseError> {
 		if self.peek(0).typ == TokType::Word {
 			// root is list
 			return self.list()
@@ -449,7 +576,7 @@ impl Parser {
 	}
 }
 
-pub fn tokenize( string: &str, file: &str ) -> Vec<Token> {
+pub fn tokenize( string: &str, file: &str ) -> Result<Vec<Token>, ParseError> {
 	Tokenizer::new(string, file).tokenize()
 }
 
@@ -457,32 +584,219 @@ pub fn lex(tok_list: Vec<Token> ) -> Vec<Token> {
 	Lexer::new(tok_list).parse()
 }
 
-pub fn parse(tok_list: Vec<Token> ) -> E {
+pub fn parse(tok_list: Vec<Token> ) -> Result<E, ParseError> {
 	Parser::new(tok_list).objectify()
 }
 
-pub fn load( path: &Path ) -> E {
-	parse(
-		lex(
-			tokenize(
-				read_to_string( path ).unwrap().as_str(),
-				path.to_str().unwrap()
-			)
-		)
-	)
+pub fn load( path: &Path ) -> Result<E, std::io::Error> {
+	let data = read_to_string( path )?;
+	Ok( loads( data.as_str(), path.to_string_lossy().as_ref() )? )
+}
+
+pub fn loads( data: &str, file: &str ) -> Result<E, ParseError> {
+	parse( lex( tokenize(data, file )? ) )
+}
+
+/**
+ * Writes a value in the E syntax read by `loads()`, the root should be a map.
+ * Strings are written bare when they read back the same, and quoted otherwise. Keys with empty lists or maps
+ * are left out, as the syntax can't tell those apart from the keys which follow them.
+ */
+pub fn dumps( value: &E ) -> String {
+	let mut out = String::new();
+	match value {
+		E::Map { values } => dump_fields( &mut out, values, 0 ),
+		E::Object { .. } => dump_value( &mut out, value, 0 ),
+		E::List { values } => for value in values {
+			dump_value( &mut out, value, 0 )
+		},
+		scalar => {
+			out.push_str( scalar_string( scalar, false ).as_str() );
+			out.push( '\n' );
+		}
+	}
+	out
+}
+
+/**
+ * Writes a scalar, `keyed` is whether it follows a key on its line, where colons inside a word don't end it.
+ */
+fn scalar_string( value: &E, keyed: bool ) -> String {
+	match value {
+		E::None => "null".to_string(),
+		E::Integer { val } => val.to_string(),
+		E::Float { val } => val.to_string(),
+		E::String { val } => {
+			// bare, it would be read back as `E::None`, a class, a key, or lose its comment, padding or line breaks
+			let bare = !val.is_empty()
+				&& val != "null"
+				&& !val.starts_with( [ '.', '"' ] )
+				&& val.trim() == val
+				&& !val.contains( [ '#', '\n', '\r', '\t', '\0' ] )
+				&& val.match_indices( ':' ).all( |( idx, _ )| keyed && idx > 0 && val[ idx + 1 .. ].starts_with( |next: char| !next.is_whitespace() ) );
+			if bare {
+				return val.clone();
+			}
+
+			let mut quoted = String::from( '"' );
+			for chr in val.chars() {
+				match chr {
+					'"' => quoted.push_str( "\\\"" ),
+					'\\' => quoted.push_str( "\\\\" ),
+					'\n' => quoted.push_str( "\\n" ),
+					'\t' => quoted.push_str( "\\t" ),
+					'\r' => quoted.push_str( "\\r" ),
+					'\0' => quoted.push_str( "\\0" ),
+					chr => quoted.push( chr )
+				}
+			}
+			quoted.push( '"' );
+			quoted
+		}
+		_ => unreachable!()
+	}
+}
+
+fn dump_fields( out: &mut String, fields: &[KeyValue], padding: usize ) {
+	for field in fields {
+		let empty = match &field.value {
+			E::List { values } => values.is_empty(),
+			E::Map { values } => values.is_empty(),
+			_ => false
+		};
+		if empty {
+			continue;
+		}
+		out.push_str( "\t".repeat( padding ).as_str() );
+		out.push_str( field.key.as_str() );
+		out.push( ':' );
+		match &field.value {
+			E::List { values } => {
+				out.push( '\n' );
+				for value in values {
+					dump_value( out, value, padding + 1 );
+				}
+			}
+			E::Map { values } => {
+				out.push( '\n' );
+				dump_fields( out, values, padding + 1 );
+			}
+			E::Object { class, fields } => {
+				out.push_str( format!( " .{class}:\n" ).as_str() );
+				dump_fields( out, fields, padding + 1 );
+			}
+			scalar => {
+				out.push( ' ' );
+				out.push_str( scalar_string( scalar, true ).as_str() );
+				out.push( '\n' );
+			}
+		}
+	}
 }
 
-pub fn loads( data: &str, file: &str ) -> E {
-	parse( lex( tokenize(data, file ) ) )
+/**
+ * Writes an item of a list, on its own line.
+ */
+fn dump_value( out: &mut String, value: &E, padding: usize ) {
+	out.push_str( "\t".repeat( padding ).as_str() );
+	match value {
+		E::Object { class, fields } => {
+			out.push_str( format!( ".{class}:\n" ).as_str() );
+			dump_fields( out, fields, padding + 1 );
+		}
+		// lists can only hold scalars and objects
+		E::List { .. } | E::Map { .. } => out.push_str( "null\n" ),
+		scalar => {
+			out.push_str( scalar_string( scalar, false ).as_str() );
+			out.push( '\n' );
+		}
+	}
 }
 
 pub(crate) fn main() {
-	let tokens = tokenize( read_to_string( Path::new("test.e") ).unwrap().as_str(), "test.e" );
+	let tokens = match tokenize( read_to_string( Path::new("test.e") ).unwrap().as_str(), "test.e" ) {
+		Ok( tokens ) => tokens,
+		Err( err ) => return println!( "Error: {}", err )
+	};
 	println!( "Tokens: {:?}", tokens );
 
 	let parsed_tokens = lex( tokens );
 	println!( "Tokens: {:?}", parsed_tokens );
 
-	let object = parse( parsed_tokens );
-	println!( "Object: {}", object )
+	match parse( parsed_tokens ) {
+		Ok( object ) => println!( "Object: {}", object ),
+		Err( err ) => println!( "Error: {}", err )
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::format::e::{dumps, loads, tokenize, E, KeyValue, TokType, TokValue};
+
+	fn words( data: &str ) -> Vec<String> {
+		tokenize( data, "test.e" ).unwrap().into_iter()
+			.filter( |token| token.typ == TokType::Word )
+			.map( |token| match token.value {
+				TokValue::String { value } => value,
+				value => panic!( "word without a string: {value:?}" )
+			} )
+			.collect()
+	}
+
+	#[test]
+	fn colons_in_values() {
+		assert_eq!( vec![ "path", "C:\\Games\\Borealis" ], words( "path: C:\\Games\\Borealis\n" ) );
+		assert_eq!( vec![ "layer", "memory:generated" ], words( "layer: memory:generated\n" ) );
+		// the first colon of a line still ends the key, with or without a space after it
+		assert_eq!( vec![ "fov", "90" ], words( "fov:90\n" ) );
+		assert_eq!( vec![ "Layer", "path", "a:b" ], words( ".Layer:\n\tpath: a:b\n" ) );
+
+		let parsed = loads( "fov:90\nroot: C:\\Games\nlayer: .Layer:\n\tname: memory:game\n", "test.e" ).unwrap();
+		assert_eq!( Some( "90" ), parsed.get( "fov" ).and_then( |value| value.as_str() ) );
+		assert_eq!( Some( "C:\\Games" ), parsed.get( "root" ).and_then( |value| value.as_str() ) );
+		assert_eq!( Some( "memory:game" ), parsed.get( "layer" ).and_then( |value| value.get( "name" ) ).and_then( |value| value.as_str() ) );
+	}
+
+	#[test]
+	fn dumps_reads_back() {
+		let value = E::Map { values: vec![
+			KeyValue::new( "name", E::String { val: "Borealis".to_string() } ),
+			KeyValue::new( "icon", E::None ),
+			KeyValue::new( "archives", E::List { values: vec![ E::String { val: "pak01_dir.vpk".to_string() }, E::None ] } )
+		] };
+		let dumped = dumps( &value );
+		assert_eq!( "name: Borealis\nicon: null\narchives:\n\tpak01_dir.vpk\n\tnull\n", dumped );
+		assert_eq!( value, loads( dumped.as_str(), "test.e" ).unwrap() );
+		assert_eq!( "null", E::None.to_string() );
+	}
+
+	#[test]
+	fn dumps_quotes_strings() {
+		let strings = [
+			"/games/C# mods/game", "null", "./game", ".Layer", "a: b", "a:", ":a", "C:\\Games", "memory:game",
+			"", " padded ", "tab\there", "two\nlines", "\"quoted\"", "back\\slash\\", "plain"
+		];
+		let string = |val: &&str| E::String { val: val.to_string() };
+		let value = E::Map { values: vec![
+			KeyValue::new( "paths", E::List { values: strings.iter().map( string ).collect() } ),
+			KeyValue::new( "layer", E::Object { class: "Layer".to_string(), fields: strings.iter().map( |val| KeyValue::new( "path", string( val ) ) ).collect() } )
+		] };
+
+		let dumped = dumps( &value );
+		assert_eq!( value, loads( dumped.as_str(), "test.e" ).unwrap(), "{dumped}" );
+		// strings stay bare where they can
+		assert!( dumped.contains( "\tplain\n" ) && dumped.contains( "\tpath: C:\\Games\n" ) && dumped.contains( "\tpath: memory:game\n" ) );
+		assert!( dumped.contains( "\t\"null\"\n" ) && dumped.contains( "\t\"C:\\\\Games\"\n" ) );
+	}
+
+	#[test]
+	fn invalid_documents_are_errors() {
+		for data in [ "path: .\n", "path: \"unterminated\n", "path: \"\\x\"\n", "name: game\npath: ./game\n" ] {
+			let err = loads( data, "test.e" ).unwrap_err();
+			assert!( err.to_string().starts_with( "test.e:" ), "{err}" );
+		}
+		// documents may end anywhere
+		assert_eq!( Some( "b" ), loads( "a: b # comment", "test.e" ).unwrap().get( "a" ).and_then( |value| value.as_str() ) );
+		assert!( loads( "a: b\n\t", "test.e" ).is_ok() );
+	}
 }
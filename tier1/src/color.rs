@@ -1,4 +1,9 @@
+use std::fmt::{Display, Formatter};
 use std::ops::Index;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error;
 
 #[derive(Clone, PartialEq, Eq, Copy, Debug)]
 pub struct Color {
@@ -8,6 +13,243 @@ pub struct Color {
 	alpha: u8
 }
 
+/**
+ * How `Color::blend()` combines a color over another.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BlendMode {
+	/// standard "over" alpha compositing
+	Normal,
+	Additive,
+	Multiply,
+	Screen
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ParseColorError( String );
+
+impl Display for ParseColorError {
+	fn fmt( &self, f: &mut Formatter<'_> ) -> std::fmt::Result {
+		write!( f, "invalid color `{}`, expected `#RRGGBB`, `#RRGGBBAA` or `r g b [a]`", self.0 )
+	}
+}
+
+impl std::error::Error for ParseColorError { }
+
+impl Color {
+	pub const WHITE: Color = Color::new( 0xFF, 0xFF, 0xFF, 0xFF );
+	pub const BLACK: Color = Color::new( 0x00, 0x00, 0x00, 0xFF );
+	pub const TRANSPARENT: Color = Color::new( 0x00, 0x00, 0x00, 0x00 );
+
+	pub const fn new( red: u8, green: u8, blue: u8, alpha: u8 ) -> Self {
+		Color { red, green, blue, alpha }
+	}
+
+	/**
+	 * Creates an opaque color.
+	 */
+	pub const fn rgb( red: u8, green: u8, blue: u8 ) -> Self {
+		Color::new( red, green, blue, 0xFF )
+	}
+
+	/**
+	 * Creates a color from components in the `0.0 ..= 1.0` range, values outside of it are clamped.
+	 */
+	pub fn from_rgba_f32( red: f32, green: f32, blue: f32, alpha: f32 ) -> Self {
+		Color::new( to_u8( red ), to_u8( green ), to_u8( blue ), to_u8( alpha ) )
+	}
+
+	/**
+	 * Returns the components in the `0.0 ..= 1.0` range.
+	 */
+	pub fn to_rgba_f32( &self ) -> [f32; 4] {
+		[ to_f32( self.red ), to_f32( self.green ), to_f32( self.blue ), to_f32( self.alpha ) ]
+	}
+
+	pub fn red( &self ) -> u8 {
+		self.red
+	}
+
+	pub fn green( &self ) -> u8 {
+		self.green
+	}
+
+	pub fn blue( &self ) -> u8 {
+		self.blue
+	}
+
+	pub fn alpha( &self ) -> u8 {
+		self.alpha
+	}
+
+	pub fn with_alpha( &self, alpha: u8 ) -> Self {
+		Color { alpha, ..*self }
+	}
+
+	/**
+	 * Creates a color from hue ( in degrees ), saturation and value, all but hue in the `0.0 ..= 1.0` range.
+	 */
+	pub fn from_hsv( hue: f32, saturation: f32, value: f32, alpha: f32 ) -> Self {
+		let chroma = value * saturation;
+		let ( red, green, blue ) = from_hue( hue, chroma );
+		let min = value - chroma;
+		Color::from_rgba_f32( red + min, green + min, blue + min, alpha )
+	}
+
+	/**
+	 * Returns hue ( in degrees ), saturation and value of this color.
+	 */
+	pub fn to_hsv( &self ) -> ( f32, f32, f32 ) {
+		let [ red, green, blue, _ ] = self.to_rgba_f32();
+		let max = red.max( green ).max( blue );
+		let chroma = max - red.min( green ).min( blue );
+
+		let saturation = if max == 0.0 { 0.0 } else { chroma / max };
+		( to_hue( red, green, blue, max, chroma ), saturation, max )
+	}
+
+	/**
+	 * Creates a color from hue ( in degrees ), saturation and lightness, all but hue in the `0.0 ..= 1.0` range.
+	 */
+	pub fn from_hsl( hue: f32, saturation: f32, lightness: f32, alpha: f32 ) -> Self {
+		let chroma = ( 1.0 - ( 2.0 * lightness - 1.0 ).abs() ) * saturation;
+		let ( red, green, blue ) = from_hue( hue, chroma );
+		let min = lightness - chroma / 2.0;
+		Color::from_rgba_f32( red + min, green + min, blue + min, alpha )
+	}
+
+	/**
+	 * Returns hue ( in degrees ), saturation and lightness of this color.
+	 */
+	pub fn to_hsl( &self ) -> ( f32, f32, f32 ) {
+		let [ red, green, blue, _ ] = self.to_rgba_f32();
+		let max = red.max( green ).max( blue );
+		let min = red.min( green ).min( blue );
+		let chroma = max - min;
+		let lightness = ( max + min ) / 2.0;
+
+		let saturation = if chroma == 0.0 { 0.0 } else { chroma / ( 1.0 - ( 2.0 * lightness - 1.0 ).abs() ) };
+		( to_hue( red, green, blue, max, chroma ), saturation, lightness )
+	}
+
+	/**
+	 * Converts this color from sRGB to linear space, alpha is left as is.
+	 */
+	pub fn to_linear( &self ) -> [f32; 4] {
+		let [ red, green, blue, alpha ] = self.to_rgba_f32();
+		[ srgb_to_linear( red ), srgb_to_linear( green ), srgb_to_linear( blue ), alpha ]
+	}
+
+	/**
+	 * Creates an sRGB color from linear space components, alpha is left as is.
+	 */
+	pub fn from_linear( red: f32, green: f32, blue: f32, alpha: f32 ) -> Self {
+		Color::from_rgba_f32( linear_to_srgb( red ), linear_to_srgb( green ), linear_to_srgb( blue ), alpha )
+	}
+
+	/**
+	 * Linearly interpolates between this color and `other`, `t` is clamped to `0.0 ..= 1.0`.
+	 */
+	pub fn lerp( &self, other: Color, t: f32 ) -> Self {
+		let t = t.clamp( 0.0, 1.0 );
+		let from = self.to_rgba_f32();
+		let to = other.to_rgba_f32();
+		let mix = |index: usize| from[index] + ( to[index] - from[index] ) * t;
+		Color::from_rgba_f32( mix( 0 ), mix( 1 ), mix( 2 ), mix( 3 ) )
+	}
+
+	/**
+	 * Multiplies the color components by alpha.
+	 */
+	pub fn premultiply( &self ) -> Self {
+		let [ red, green, blue, alpha ] = self.to_rgba_f32();
+		Color::from_rgba_f32( red * alpha, green * alpha, blue * alpha, alpha )
+	}
+
+	/**
+	 * Blends `over` on top of this color.
+	 */
+	pub fn blend( &self, over: Color, mode: BlendMode ) -> Self {
+		let [ dst_r, dst_g, dst_b, dst_a ] = self.to_rgba_f32();
+		let [ src_r, src_g, src_b, src_a ] = over.to_rgba_f32();
+
+		if mode == BlendMode::Normal {
+			let alpha = src_a + dst_a * ( 1.0 - src_a );
+			if alpha == 0.0 {
+				return Color::TRANSPARENT;
+			}
+			let over = |src: f32, dst: f32| ( src * src_a + dst * dst_a * ( 1.0 - src_a ) ) / alpha;
+			return Color::from_rgba_f32( over( src_r, dst_r ), over( src_g, dst_g ), over( src_b, dst_b ), alpha );
+		}
+
+		let combine = |src: f32, dst: f32| {
+			let mixed = match mode {
+				BlendMode::Additive => src + dst,
+				BlendMode::Multiply => src * dst,
+				BlendMode::Screen => 1.0 - ( 1.0 - src ) * ( 1.0 - dst ),
+				BlendMode::Normal => unreachable!()
+			};
+			// the blended result is weighted by the alpha of the color on top
+			dst + ( mixed - dst ) * src_a
+		};
+		Color::from_rgba_f32( combine( src_r, dst_r ), combine( src_g, dst_g ), combine( src_b, dst_b ), dst_a )
+	}
+}
+
+fn to_u8( value: f32 ) -> u8 {
+	( value.clamp( 0.0, 1.0 ) * 255.0 ).round() as u8
+}
+
+fn to_f32( value: u8 ) -> f32 {
+	value as f32 / 255.0
+}
+
+/**
+ * Returns the rgb components, without the lightness offset, of a hue and chroma.
+ */
+fn from_hue( hue: f32, chroma: f32 ) -> ( f32, f32, f32 ) {
+	let sector = hue.rem_euclid( 360.0 ) / 60.0;
+	let second = chroma * ( 1.0 - ( sector % 2.0 - 1.0 ).abs() );
+	match sector as u8 {
+		0 => ( chroma, second, 0.0 ),
+		1 => ( second, chroma, 0.0 ),
+		2 => ( 0.0, chroma, second ),
+		3 => ( 0.0, second, chroma ),
+		4 => ( second, 0.0, chroma ),
+		_ => ( chroma, 0.0, second )
+	}
+}
+
+fn to_hue( red: f32, green: f32, blue: f32, max: f32, chroma: f32 ) -> f32 {
+	if chroma == 0.0 {
+		return 0.0;
+	}
+	let hue = if max == red {
+		( ( green - blue ) / chroma ).rem_euclid( 6.0 )
+	} else if max == green {
+		( blue - red ) / chroma + 2.0
+	} else {
+		( red - green ) / chroma + 4.0
+	};
+	hue * 60.0
+}
+
+fn srgb_to_linear( value: f32 ) -> f32 {
+	if value <= 0.04045 {
+		value / 12.92
+	} else {
+		( ( value + 0.055 ) / 1.055 ).powf( 2.4 )
+	}
+}
+
+fn linear_to_srgb( value: f32 ) -> f32 {
+	if value <= 0.0031308 {
+		value * 12.92
+	} else {
+		1.055 * value.powf( 1.0 / 2.4 ) - 0.055
+	}
+}
+
 impl From<u32> for Color {
 	fn from( value: u32 ) -> Self {
 		Color {
@@ -28,13 +270,16 @@ impl From<Color> for u32 {
 	}
 }
 
+/**
+ * Missing components default to `0`, and alpha to opaque.
+ */
 impl From<Vec<u8>> for Color {
 	fn from( value: Vec<u8> ) -> Self {
 		Color {
-			red: value[0],
-			green: value[1],
-			blue: value[2],
-			alpha: value[3]
+			red: value.get( 0 ).copied().unwrap_or( 0 ),
+			green: value.get( 1 ).copied().unwrap_or( 0 ),
+			blue: value.get( 2 ).copied().unwrap_or( 0 ),
+			alpha: value.get( 3 ).copied().unwrap_or( 0xFF )
 		}
 	}
 }
@@ -53,6 +298,76 @@ impl Index<u8> for Color {
 	}
 }
 
+/**
+ * Parses either `#RRGGBB`, `#RRGGBBAA` or `r g b [a]` with 0-255 components.
+ */
+impl FromStr for Color {
+	type Err = ParseColorError;
+
+	fn from_str( value: &str ) -> Result<Self, Self::Err> {
+		let error = || ParseColorError( value.to_string() );
+		let value = value.trim();
+
+		if let Some( hex ) = value.strip_prefix( '#' ) {
+			// `from_str_radix` would also take a sign
+			if !hex.chars().all( |char| char.is_ascii_hexdigit() ) {
+				return Err( error() );
+			}
+			let rgba = u32::from_str_radix( hex, 16 ).map_err( |_| error() )?;
+			return match hex.len() {
+				6 => Ok( Color::from( ( rgba << 8 ) | 0xFF ) ),
+				8 => Ok( Color::from( rgba ) ),
+				_ => Err( error() )
+			};
+		}
+
+		let components = value.split_whitespace()
+			.map( |component| match component.chars().all( |char| char.is_ascii_digit() ) {
+				true => component.parse::<u8>().map_err( |_| () ),
+				false => Err( () )
+			} )
+			.collect::<Result<Vec<u8>, _>>()
+			.map_err( |_| error() )?;
+		match components.len() {
+			3 | 4 => Ok( Color::from( components ) ),
+			_ => Err( error() )
+		}
+	}
+}
+
+/**
+ * Formats as `#RRGGBBAA`, or as `r g b a` with the alternate flag ( `{:#}` ).
+ */
+impl Display for Color {
+	fn fmt( &self, f: &mut Formatter<'_> ) -> std::fmt::Result {
+		match f.alternate() {
+			true => write!( f, "{} {} {} {}", self.red, self.green, self.blue, self.alpha ),
+			false => write!( f, "#{:08X}", u32::from( *self ) )
+		}
+	}
+}
+
+/**
+ * Human-readable formats get the `#RRGGBBAA` string, binary ones the packed `u32`.
+ */
+impl Serialize for Color {
+	fn serialize<S: Serializer>( &self, serializer: S ) -> Result<S::Ok, S::Error> {
+		match serializer.is_human_readable() {
+			true => serializer.collect_str( self ),
+			false => serializer.serialize_u32( u32::from( *self ) )
+		}
+	}
+}
+
+impl<'de> Deserialize<'de> for Color {
+	fn deserialize<D: Deserializer<'de>>( deserializer: D ) -> Result<Self, D::Error> {
+		match deserializer.is_human_readable() {
+			true => String::deserialize( deserializer )?.parse().map_err( D::Error::custom ),
+			false => Ok( Color::from( u32::deserialize( deserializer )? ) )
+		}
+	}
+}
+
 #[test]
 fn color_test() {
 	let color = Color { red: 0xFF, green: 0xAB, blue: 0xCD, alpha: 0xEF };
@@ -61,3 +376,26 @@ fn color_test() {
 	assert_eq!( 0xFFABCDEF, colorNum, "Checking if `Color -> u32` works" );
 	assert_eq!( color, Color::from(colorNum), "Checking if `u32 -> Color` works" );
 }
+
+#[test]
+fn color_conversions_test() {
+	let color = Color::rgb( 0xFF, 0x80, 0x00 );
+
+	assert_eq!( Ok( color ), "#FF8000".parse::<Color>(), "Checking if parsing hex works" );
+	assert_eq!( Ok( color ), "255 128 0 255".parse::<Color>(), "Checking if parsing components works" );
+	assert!( "255 128".parse::<Color>().is_err(), "Checking if short input is rejected" );
+	assert!( "#+F8000".parse::<Color>().is_err(), "Checking if signed hex is rejected" );
+	assert!( "#+FF8000F".parse::<Color>().is_err(), "Checking if signed hex is rejected" );
+	assert!( "+255 128 0".parse::<Color>().is_err(), "Checking if signed components are rejected" );
+	assert_eq!( "#FF8000FF", color.to_string(), "Checking if `Display` works" );
+
+	let ( hue, saturation, value ) = color.to_hsv();
+	assert_eq!( color, Color::from_hsv( hue, saturation, value, 1.0 ), "Checking if hsv round-trips" );
+	let ( hue, saturation, lightness ) = color.to_hsl();
+	assert_eq!( color, Color::from_hsl( hue, saturation, lightness, 1.0 ), "Checking if hsl round-trips" );
+	let [ red, green, blue, alpha ] = color.to_linear();
+	assert_eq!( color, Color::from_linear( red, green, blue, alpha ), "Checking if linear round-trips" );
+
+	assert_eq!( Color::rgb( 0x80, 0x80, 0x80 ), Color::BLACK.lerp( Color::WHITE, 0.5 ) );
+	assert_eq!( Color::rgb( 0x80, 0x00, 0x00 ), Color::BLACK.blend( Color::new( 0xFF, 0, 0, 0x80 ), BlendMode::Normal ) );
+}
@@ -1,24 +1,100 @@
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{Read, Write};
-use std::num::ParseIntError;
-use std::ops::{Add, Deref};
+use std::io::{Error, ErrorKind, Read};
 use std::path::Path;
-use log::debug;
+use log::{debug, warn};
 
 use crate::color::Color;
 
 type IoResult = std::io::Result<()>;
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum KeyValue {
     Float { key: String, value: f64 },
     Int { key: String, value: i64 },
     String { key: String, value: String },
     Color { key: String, value: Color },
+    Bool { key: String, value: bool },
+    Vector2 { key: String, value: [f64; 2] },
+    Vector3 { key: String, value: [f64; 3] },
+    Vector4 { key: String, value: [f64; 4] },
+    QAngle { key: String, value: [f64; 3] },
+    /// list items are keyed by their index
+    List { key: String, value: Vec<KeyValue> },
     Empty { key: String }
 }
 
+/**
+ * The type a value can be parsed as, used to override type inference with a `ConfigSchema`.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueType {
+	Float,
+	Int,
+	String,
+	Color,
+	Bool,
+	Vector2,
+	Vector3,
+	Vector4,
+	QAngle,
+	List
+}
+
+/**
+ * Declares the types of some keys of a config file.
+ * Keys not declared here have their type inferred from the value.
+ */
+#[derive(Clone, Default)]
+pub struct ConfigSchema {
+	types: HashMap<String, ValueType>
+}
+
+impl ConfigSchema {
+	pub fn new() -> Self {
+		ConfigSchema::default()
+	}
+
+	pub fn with( mut self, key: &str, typ: ValueType ) -> Self {
+		self.declare( key, typ );
+		self
+	}
+
+	pub fn declare( &mut self, key: &str, typ: ValueType ) {
+		self.types.insert( key.to_string(), typ );
+	}
+
+	pub fn get( &self, key: &str ) -> Option<ValueType> {
+		self.types.get( key ).copied()
+	}
+}
+
+/**
+ * How a value was written in the file, so that rewriting it keeps the same notation.
+ */
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ValueStyle {
+	Plain,
+	Quoted,
+	Hex
+}
+
 impl KeyValue {
+	pub fn key( &self ) -> &str {
+		match self {
+			KeyValue::Float { key, value: _ } => key,
+			KeyValue::Int { key, value: _ } => key,
+			KeyValue::String { key, value: _ } => key,
+			KeyValue::Color { key, value: _ } => key,
+			KeyValue::Bool { key, value: _ } => key,
+			KeyValue::Vector2 { key, value: _ } => key,
+			KeyValue::Vector3 { key, value: _ } => key,
+			KeyValue::Vector4 { key, value: _ } => key,
+			KeyValue::QAngle { key, value: _ } => key,
+			KeyValue::List { key, value: _ } => key,
+			KeyValue::Empty { key } => key
+		}
+	}
 	pub fn float( &self ) -> Option<f64> {
 		match self {
 			KeyValue::Float { key: _key, value } => Some( value.clone() ),
@@ -43,16 +119,148 @@ impl KeyValue {
 			_ => None
 		}
 	}
+	pub fn boolean( &self ) -> Option<bool> {
+		match self {
+			KeyValue::Bool { key: _key, value } => Some( value.clone() ),
+			_ => None
+		}
+	}
+	pub fn vector2( &self ) -> Option<[f64; 2]> {
+		match self {
+			KeyValue::Vector2 { key: _key, value } => Some( value.clone() ),
+			_ => None
+		}
+	}
+	pub fn vector3( &self ) -> Option<[f64; 3]> {
+		match self {
+			KeyValue::Vector3 { key: _key, value } => Some( value.clone() ),
+			_ => None
+		}
+	}
+	pub fn vector4( &self ) -> Option<[f64; 4]> {
+		match self {
+			KeyValue::Vector4 { key: _key, value } => Some( value.clone() ),
+			_ => None
+		}
+	}
+	pub fn qangle( &self ) -> Option<[f64; 3]> {
+		match self {
+			KeyValue::QAngle { key: _key, value } => Some( value.clone() ),
+			_ => None
+		}
+	}
+	pub fn list( &self ) -> Option<&Vec<KeyValue>> {
+		match self {
+			KeyValue::List { key: _key, value } => Some( value ),
+			_ => None
+		}
+	}
+
+	/**
+	 * Formats the value part of this pair the same way the parser reads it back.
+	 */
+	fn format_value( &self, style: ValueStyle ) -> String {
+		fn float( value: f64 ) -> String {
+			// the parser only infers a float when there is a decimal point, `{:?}` leaves it out of exponents (`1e20`)
+			let text = format!( "{:?}", value );
+			match text.find( 'e' ) {
+				Some( idx ) if !text[..idx].contains( '.' ) => format!( "{}.0{}", &text[..idx], &text[idx..] ),
+				_ => text
+			}
+		}
+		fn components( values: &[f64] ) -> String {
+			values.iter()
+				.map( |value| float( *value ) )
+				.collect::<Vec<String>>()
+				.join( " " )
+		}
+
+		match self {
+			KeyValue::Float { key: _key, value } => float( *value ),
+			KeyValue::Int { key: _key, value } => format!( "{}", value ),
+			KeyValue::String { key: _key, value } => {
+				// bare, it would be read back as another type, or split as a list or a comment
+				let ambiguous = value.is_empty()
+					|| value.contains( char::is_whitespace )
+					|| value.contains( [ ',', '[', ']', '"' ] )
+					|| value.contains( "//" )
+					|| !matches!( parse_value( String::new(), value, None ).0, KeyValue::String { .. } );
+				if style == ValueStyle::Quoted || ambiguous {
+					format!( "\"{}\"", value )
+				} else {
+					value.clone()
+				}
+			}
+			KeyValue::Color { key: _key, value } => match style {
+				ValueStyle::Hex => format!( "{}", value ),
+				_ => format!( "{:#}", value )
+			},
+			KeyValue::Bool { key: _key, value } => format!( "{}", value ),
+			KeyValue::Vector2 { key: _key, value } => components( value ),
+			KeyValue::Vector3 { key: _key, value } => components( value ),
+			KeyValue::Vector4 { key: _key, value } => components( value ),
+			KeyValue::QAngle { key: _key, value } => components( value ),
+			KeyValue::List { key: _key, value } => {
+				let items = value.iter()
+					.map( |item| item.format_value( ValueStyle::Plain ) )
+					.collect::<Vec<String>>();
+				format!( "[ {} ]", items.join( ", " ) )
+			}
+			KeyValue::Empty { key: _key } => String::new()
+		}
+	}
+}
+
+/**
+ * A single line of the original file.
+ * Lines are kept as they were read, and only the ones whose item changed get rewritten.
+ */
+struct Line {
+	text: String,
+	item: Option<LineItem>
+}
+
+struct LineItem {
+	/// index of the item in `ConfigFile.items`
+	index: usize,
+	/// everything before the value: indentation, key and separator
+	prefix: String,
+	/// everything after the value: trailing comment and the `\r` of CRLF files
+	suffix: String,
+	/// how the value was written
+	style: ValueStyle
+}
+
+impl Line {
+	fn rewrite( &mut self, item: &KeyValue ) {
+		let layout = self.item.as_ref().unwrap();
+		let value = item.format_value( layout.style );
+
+		self.text = layout.prefix.clone();
+		if !value.is_empty() && !self.text.ends_with( char::is_whitespace ) {
+			self.text.push( ' ' );
+		}
+		self.text.push_str( value.as_str() );
+		self.text.push_str( layout.suffix.as_str() );
+	}
 }
 
 
 pub struct ConfigFile {
     items: Vec<KeyValue>,
+	lines: Vec<Line>,
     path: Option<String>
 }
 
 impl ConfigFile {
     pub fn new( path: &Path ) -> ConfigFile {
+	    ConfigFile::new_with_schema( path, &ConfigSchema::default() )
+    }
+
+	/**
+	 * Same as `new()`, but the keys declared in the schema are parsed as the declared type.
+	 */
+    pub fn new_with_schema( path: &Path, schema: &ConfigSchema ) -> ConfigFile {
         assert!( path.exists() );
         let mut file: String = String::new();
         let size = File::open( path ).unwrap().read_to_string(&mut file );
@@ -60,99 +268,109 @@ impl ConfigFile {
 	        panic!( "WTF! {}", size.err().unwrap() );
         }
 
-        let mut config = ConfigFile { items: Vec::new(), path: Some( path.to_str().unwrap().to_string() ) };
-
-        for line in file.split( "\n" ) {
-	        // skip empty lines
-	        if line.is_empty() {
-		        continue;
-	        }
-
-            let pair: Vec<&str> = line.split(" ").collect();
-            let name: String = String::from( pair.get(0).unwrap().deref().trim() );
-            let mut value: String = line.clone().trim().to_string();
-	        value.remove_matches( name.clone().add(" ").as_str() );
-	        value = value.trim().to_string();
-
-
-	        debug!( "key: {}, value: {}", name.escape_default(), value.escape_default() );
-
-	        // is it nothing?
-	        if value.is_empty() {
-		        config.items.push( KeyValue::Empty { key: name } );
-		        continue;
-	        }
-
-	        // is it a float?
-	        let float_res = value.parse::<f64>();
-	        if float_res.is_ok() && value.contains(".") {
-				config.items.push( KeyValue::Float { key: name.clone(), value: float_res.unwrap() } );
-		        continue;
-	        }
-
-	        // is it an integer?
-	        let int_res = value.parse::<i64>();
-	        if int_res.is_ok() {
-		        config.items.push( KeyValue::Int { key: name.clone(), value: int_res.unwrap() } );
-		        continue;
-	        }
-
-	        // is it a color vector?
-	        let color_res: Vec<Result<i8, ParseIntError>> = value.split_whitespace()
-		        .map( |value| value.parse::<i8>() )
-		        .filter( |v| v.is_ok() )
-		        .collect();
-	        if color_res.len() == 3 || color_res.len() == 4 {
-		        config.items.push( KeyValue::Color {
-			        key: name.clone(),
-			        value: Color::from(
-			            color_res.iter()
-						        .map( |v| v.clone().unwrap().clone() as u8 )
-						        .collect::<Vec<u8>>()
-			        )
-		        } );
-		        continue;
-	        }
-
-	        // nothing worked, its a string, but was it quoted?
-	        if value.starts_with("\"") && value.ends_with("\"") {
-		        // it was "quoted", remove the things
-		        value.remove( value.len() - 1 );
-		        value.remove( 0 );
-	        }
-	        config.items.push( KeyValue::String { key: name.clone(), value: value.clone() } );
-        }
-
-        return config;
+        let mut config = ConfigFile::loads_with_schema( file.as_str(), schema );
+	    config.path = Some( path.to_str().unwrap().to_string() );
+	    config
     }
 
-	pub fn save( &self ) -> IoResult {
-		let mut file = File::create( Path::new( self.path.clone().unwrap().as_str() ) ).unwrap();
-		let mut res;
+	/**
+	 * Parses a config file from a string, the resulting object has no path set.
+	 */
+	pub fn loads( data: &str ) -> ConfigFile {
+		ConfigFile::loads_with_schema( data, &ConfigSchema::default() )
+	}
 
-		for item in &self.items {
-			match item {
-				KeyValue::Float { key: _key, value: _value } => {
-					res = writeln!( file, "{} {}", _key, _value );
-				}
-				KeyValue::Int { key: _key, value: _value } => {
-					res = writeln!( file, "{} {}", _key, _value );
-				}
-				KeyValue::String { key: _key, value: _value } => {
-					res = writeln!( file, "{} {}", _key, _value );
-				}
-				KeyValue::Color { key: _key, value: _value } => {
-					res = writeln!( file, "{} {} {} {}", _key, _value[0], _value[1], _value[2] );
-				}
-				KeyValue::Empty { key: _key } => {
-					res = writeln!( file, "{}", _key );
+	pub fn loads_with_schema( data: &str, schema: &ConfigSchema ) -> ConfigFile {
+		let mut config = ConfigFile { items: Vec::new(), lines: Vec::new(), path: None };
+
+		for text in data.split( "\n" ) {
+			let mut line = Line { text: text.to_string(), item: None };
+
+			if let Some( ( value, mut layout ) ) = ConfigFile::parse_line( text, schema ) {
+				layout.index = config.items.len();
+				line.item = Some( layout );
+				config.items.push( value );
+			}
+			config.lines.push( line );
+		}
+
+		config
+	}
+
+	/**
+	 * Parses a single line, returning `None` for blank and comment lines.
+	 */
+	fn parse_line( text: &str, schema: &ConfigSchema ) -> Option<( KeyValue, LineItem )> {
+		// keep the `\r` of CRLF files out of the value, but put it back when rewriting
+		let ( line, cr ) = match text.strip_suffix( '\r' ) {
+			Some( line ) => ( line, "\r" ),
+			None => ( text, "" )
+		};
+
+		let body = line.trim_start();
+		// skip empty lines and comments
+		if body.is_empty() || body.starts_with( "//" ) {
+			return None;
+		}
+		let indent = &line[ .. line.len() - body.len() ];
+
+		let name = body.split_whitespace().next().unwrap().to_string();
+		let rest = &body[ name.len() .. ];
+		let after_separator = rest.trim_start();
+		let separator = &rest[ .. rest.len() - after_separator.len() ];
+
+		// split off any trailing comment, unless it is inside quotes
+		let mut comment_start = after_separator.len();
+		let mut in_quotes = false;
+		for ( index, chr ) in after_separator.char_indices() {
+			match chr {
+				'"' => in_quotes = !in_quotes,
+				'/' if !in_quotes && after_separator[ index .. ].starts_with( "//" ) => {
+					comment_start = index;
+					break;
 				}
+				_ => {}
 			}
-			if res.is_err() {
-				return res;
+		}
+		let value = after_separator[ .. comment_start ].trim_end();
+		let mut suffix = after_separator[ value.len() .. ].to_string() + cr;
+		let mut prefix = format!( "{}{}{}", indent, name, separator );
+		// without a value, the separator is the one before the comment, and must stay there
+		if value.is_empty() && comment_start < after_separator.len() {
+			suffix = format!( "{separator}{suffix}" );
+			prefix.truncate( prefix.len() - separator.len() );
+		}
+
+		debug!( "key: {}, value: {}", name.escape_default(), value.escape_default() );
+
+		let ( item, style ) = parse_value( name.clone(), value, schema.get( name.as_str() ) );
+		Some( (
+			item,
+			LineItem {
+				index: 0,
+				prefix,
+				suffix,
+				style
 			}
+		) )
+	}
+
+	/**
+	 * Returns the contents of the file as they would be saved.
+	 * Lines which were not changed with `set()` are kept exactly as they were read.
+	 */
+	pub fn dumps( &self ) -> String {
+		self.lines.iter()
+			.map( |line| line.text.as_str() )
+			.collect::<Vec<&str>>()
+			.join( "\n" )
+	}
+
+	pub fn save( &self ) -> IoResult {
+		match &self.path {
+			Some( path ) => std::fs::write( Path::new( path.as_str() ), self.dumps() ),
+			None => Err( Error::new( ErrorKind::InvalidInput, "config file has no path to save to" ) )
 		}
-		Ok( () )
 	}
 
 	pub fn set_path( &mut self, path: &Path ) {
@@ -172,76 +390,260 @@ impl ConfigFile {
 	}
 
 	pub fn get( &self, key: &str ) -> Option< &KeyValue> {
-		for pair in &self.items {
-			match pair {
-				KeyValue::Float { key: _key, value: _ } => {
-					if _key == key {
-						return Some( &pair );
-					}
-				}
-				KeyValue::Int { key: _key, value: _ } => {
-					if _key == key {
-						return Some( &pair );
-					}
-				}
-				KeyValue::String { key: _key, value: _ } => {
-					if _key == key {
-						return Some( &pair );
-					}
-				}
-				KeyValue::Color { key: _key, value: _ } => {
-					if _key == key {
-						return Some( &pair );
-					}
-				}
-				KeyValue::Empty { key: _key } => {
-					if _key == key {
-						return Some( &pair );
-					}
-				}
-			}
+		self.items.iter()
+			.find( |pair| pair.key() == key )
+	}
 
+	pub fn set( &mut self, key: &str, value: KeyValue) {
+		if let Some( idx ) = self.items.iter().position( |item| item.key() == key ) {
+			self.items[idx] = value;
+
+			// only touch the line this item came from
+			let line = self.lines.iter_mut()
+				.find( |line| line.item.as_ref().is_some_and( |item| item.index == idx ) )
+				.unwrap();
+			line.rewrite( &self.items[idx] );
+			return;
+		}
+
+		// there was no Pair with that key yet, create it
+		let mut line = Line {
+			text: String::new(),
+			item: Some( LineItem {
+				index: self.items.len(),
+				prefix: value.key().to_string(),
+				// follow the line endings the file already uses
+				suffix: match self.lines.iter().any( |line| line.text.ends_with( '\r' ) ) {
+					true => "\r".to_string(),
+					false => String::new()
+				},
+				style: ValueStyle::Plain
+			} )
+		};
+		line.rewrite( &value );
+		self.items.push( value );
+
+		// keep the trailing newline at the end of the file, if there was one
+		match self.lines.last() {
+			Some( last ) if last.text.is_empty() => self.lines.insert( self.lines.len() - 1, line ),
+			_ => self.lines.push( line )
 		}
-		return None;
 	}
+}
 
-	pub fn set( &mut self, key: &str, value: KeyValue) {
-		for idx in 0 .. self.items.len() {
-			let item = &self.items[idx];
-			match item {
-				KeyValue::Float { key: _key, value: _ } => {
-					if key == _key {
-						self.items[idx] = value.clone();
-						return;
-					}
-				}
-				KeyValue::Int { key: _key, value: _ } => {
-					if key == _key {
-						self.items[idx] = value.clone();
-						return;
-					}
-				}
-				KeyValue::String { key: _key, value: _ } => {
-					if key == _key {
-						self.items[idx] = value.clone();
-						return;
-					}
-				}
-				KeyValue::Color { key: _key, value: _ } => {
-					if key == _key {
-						self.items[idx] = value.clone();
-						return;
-					}
-				}
-				KeyValue::Empty { key: _key } => {
-					if key == _key {
-						self.items[idx] = value.clone();
-						return;
+/**
+ * Parses a value, as the given type if any, or by inferring its type otherwise.
+ */
+fn parse_value( key: String, value: &str, typ: Option<ValueType> ) -> ( KeyValue, ValueStyle ) {
+	// is it nothing?
+	if value.is_empty() {
+		return ( KeyValue::Empty { key }, ValueStyle::Plain );
+	}
+
+	if let Some( typ ) = typ {
+		if let Some( parsed ) = parse_as( key.clone(), value, typ ) {
+			return parsed;
+		}
+		warn!( "value of `{}` is not a valid {:?}, inferring its type instead: {}", key, typ, value.escape_default() );
+	}
+
+	// is it a list?
+	if let Some( parsed ) = parse_as( key.clone(), value, ValueType::List ) {
+		return parsed;
+	}
+
+	// is it a boolean?
+	if value == "true" || value == "false" {
+		return ( KeyValue::Bool { key, value: value == "true" }, ValueStyle::Plain );
+	}
+
+	// is it a float? `NaN` and infinities have no decimal point, so they are matched as written
+	let float_res = value.parse::<f64>();
+	if float_res.is_ok() && ( value.contains(".") || matches!( value, "NaN" | "inf" | "-inf" ) ) {
+		return ( KeyValue::Float { key, value: float_res.unwrap() }, ValueStyle::Plain );
+	}
+
+	// is it an integer?
+	let int_res = value.parse::<i64>();
+	if int_res.is_ok() {
+		return ( KeyValue::Int { key, value: int_res.unwrap() }, ValueStyle::Plain );
+	}
+
+	// is it a color, either in hex or as a vector of 0-255 integers?
+	if let Some( parsed ) = parse_as( key.clone(), value, ValueType::Color ) {
+		return parsed;
+	}
+
+	// is it a vector?
+	let vector_type = match value.split_whitespace().count() {
+		2 => Some( ValueType::Vector2 ),
+		3 => Some( ValueType::Vector3 ),
+		4 => Some( ValueType::Vector4 ),
+		_ => None
+	};
+	if let Some( parsed ) = vector_type.and_then( |typ| parse_as( key.clone(), value, typ ) ) {
+		return parsed;
+	}
+
+	// nothing worked, its a string
+	parse_as( key, value, ValueType::String ).unwrap()
+}
+
+/**
+ * Parses a value as the given type, returns `None` if it isn't valid for it.
+ */
+fn parse_as( key: String, value: &str, typ: ValueType ) -> Option<( KeyValue, ValueStyle )> {
+	fn floats<const N: usize>( value: &str ) -> Option<[f64; N]> {
+		let components = value.split_whitespace()
+			.map( |component| component.parse::<f64>().ok() )
+			.collect::<Option<Vec<f64>>>()?;
+		components.try_into().ok()
+	}
+
+	let plain = |item: KeyValue| Some( ( item, ValueStyle::Plain ) );
+	match typ {
+		ValueType::Float => plain( KeyValue::Float { key, value: value.parse::<f64>().ok()? } ),
+		ValueType::Int => plain( KeyValue::Int { key, value: value.parse::<i64>().ok()? } ),
+		ValueType::String => {
+			// was it quoted?
+			if value.len() >= 2 && value.starts_with("\"") && value.ends_with("\"") {
+				// it was "quoted", remove the things
+				let value = value[ 1 .. value.len() - 1 ].to_string();
+				return Some( ( KeyValue::String { key, value }, ValueStyle::Quoted ) );
+			}
+			plain( KeyValue::String { key, value: value.to_string() } )
+		}
+		ValueType::Color => {
+			let color = value.parse::<Color>().ok()?;
+			let style = if value.starts_with( '#' ) { ValueStyle::Hex } else { ValueStyle::Plain };
+			Some( ( KeyValue::Color { key, value: color }, style ) )
+		}
+		ValueType::Bool => match value {
+			"true" | "1" => plain( KeyValue::Bool { key, value: true } ),
+			"false" | "0" => plain( KeyValue::Bool { key, value: false } ),
+			_ => None
+		},
+		ValueType::Vector2 => plain( KeyValue::Vector2 { key, value: floats( value )? } ),
+		ValueType::Vector3 => plain( KeyValue::Vector3 { key, value: floats( value )? } ),
+		ValueType::Vector4 => plain( KeyValue::Vector4 { key, value: floats( value )? } ),
+		ValueType::QAngle => plain( KeyValue::QAngle { key, value: floats( value )? } ),
+		ValueType::List => {
+			let inner = value.strip_prefix( '[' )?.strip_suffix( ']' )?.trim();
+			if inner.is_empty() {
+				return plain( KeyValue::List { key, value: Vec::new() } );
+			}
+
+			// split on commas, unless they are inside quotes
+			let mut items = Vec::new();
+			let mut in_quotes = false;
+			let mut start = 0;
+			for ( index, chr ) in inner.char_indices() {
+				match chr {
+					'"' => in_quotes = !in_quotes,
+					',' if !in_quotes => {
+						items.push( &inner[ start .. index ] );
+						start = index + 1;
 					}
+					_ => {}
 				}
 			}
+			items.push( &inner[ start .. ] );
+
+			let values = items.iter()
+				.enumerate()
+				.map( |( index, item )| parse_value( index.to_string(), item.trim(), None ).0 )
+				.collect();
+			plain( KeyValue::List { key, value: values } )
 		}
-		// there was no Pair with that key yet, create it
-		self.items.push( value );
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::color::Color;
+	use crate::config_file::{ConfigFile, ConfigSchema, KeyValue, ValueType};
+
+	const CONFIG: &str = "// video settings\r\n\r\n\tfullscreen 1 // toggled by the menu\r\nname \"Some Player\"\r\nfov 90.0\r\nbind\r\ncrosshair // unset\r\n";
+
+	#[test]
+	fn untouched_roundtrip() {
+		assert_eq!( CONFIG, ConfigFile::loads( CONFIG ).dumps() );
+	}
+
+	#[test]
+	fn set_keeps_layout() {
+		let mut config = ConfigFile::loads( CONFIG );
+		config.set( "fullscreen", KeyValue::Int { key: "fullscreen".to_string(), value: 0 } );
+		config.set( "name", KeyValue::String { key: "name".to_string(), value: "Other".to_string() } );
+		config.set( "fov", KeyValue::Float { key: "fov".to_string(), value: 100.0 } );
+		config.set( "volume", KeyValue::Float { key: "volume".to_string(), value: 0.5 } );
+		config.set( "crosshair", KeyValue::Int { key: "crosshair".to_string(), value: 1 } );
+
+		assert_eq!(
+			"// video settings\r\n\r\n\tfullscreen 0 // toggled by the menu\r\nname \"Other\"\r\nfov 100.0\r\nbind\r\ncrosshair 1 // unset\r\nvolume 0.5\r\n",
+			config.dumps()
+		);
+		assert_eq!( Some( 0 ), config.get( "fullscreen" ).unwrap().integer() );
+	}
+
+	#[test]
+	fn extended_types() {
+		let data = "red 255 0 0\ntint #FF000080\npos 1.5 2 -3\nsize 640 480\nvsync true\nangles 0 90 0\nbinds [ w, \"a, d\", 2.5 ]\n";
+		let schema = ConfigSchema::new()
+			.with( "size", ValueType::Vector2 )
+			.with( "angles", ValueType::QAngle );
+		let mut config = ConfigFile::loads_with_schema( data, &schema );
+
+		assert_eq!( Some( Color::from( 0xFF0000FFu32 ) ), config.get( "red" ).unwrap().color() );
+		assert_eq!( Some( Color::from( 0xFF000080u32 ) ), config.get( "tint" ).unwrap().color() );
+		assert_eq!( Some( [ 1.5, 2.0, -3.0 ] ), config.get( "pos" ).unwrap().vector3() );
+		assert_eq!( Some( [ 640.0, 480.0 ] ), config.get( "size" ).unwrap().vector2() );
+		assert_eq!( Some( true ), config.get( "vsync" ).unwrap().boolean() );
+		assert_eq!( Some( [ 0.0, 90.0, 0.0 ] ), config.get( "angles" ).unwrap().qangle() );
+
+		let binds = config.get( "binds" ).unwrap().list().unwrap();
+		assert_eq!( Some( "a, d".to_string() ), binds[1].string() );
+		assert_eq!( Some( 2.5 ), binds[2].float() );
+
+		config.set( "tint", KeyValue::Color { key: "tint".to_string(), value: Color::from( 0x00FF00FFu32 ) } );
+		assert!( config.dumps().contains( "tint #00FF00FF\n" ) );
+	}
+
+	#[test]
+	fn floats_stay_floats() {
+		for value in [ 1.0, -0.5, 1e20, 1e-7, f64::MAX, f64::INFINITY, f64::NEG_INFINITY, f64::NAN ] {
+			let mut config = ConfigFile::loads( "scale\n" );
+			config.set( "scale", KeyValue::Float { key: "scale".to_string(), value } );
+			config.set( "origin", KeyValue::Vector3 { key: "origin".to_string(), value: [ value, 0.0, value ] } );
+
+			let config = ConfigFile::loads( config.dumps().as_str() );
+			let read = config.get( "scale" ).unwrap().float();
+			assert!( read.is_some_and( |read| read == value || read.is_nan() && value.is_nan() ), "{}", config.dumps() );
+			let origin = config.get( "origin" ).unwrap().vector3().unwrap();
+			assert!( origin[0] == value || origin[0].is_nan() && value.is_nan(), "{}", config.dumps() );
+		}
+		// a string spelled like one is quoted
+		let mut config = ConfigFile::loads( "name\n" );
+		config.set( "name", KeyValue::String { key: "name".to_string(), value: "inf".to_string() } );
+		assert_eq!( "name \"inf\"\n", config.dumps() );
+	}
+
+	#[test]
+	fn strings_stay_strings() {
+		let string = |value: &str| KeyValue::String { key: "name".to_string(), value: value.to_string() };
+		for value in [ "true", "123", "1.5", "a,b", "[x]", "#FF0000", "a//b", "plain" ] {
+			let mut config = ConfigFile::loads( "name\n" );
+			config.set( "name", string( value ) );
+			config.set( "names", KeyValue::List { key: "names".to_string(), value: vec![ string( value ), string( value ) ] } );
+
+			let config = ConfigFile::loads( config.dumps().as_str() );
+			assert_eq!( Some( value.to_string() ), config.get( "name" ).unwrap().string(), "{}", config.dumps() );
+			let names = config.get( "names" ).unwrap().list().unwrap();
+			assert_eq!( vec![ Some( value.to_string() ); 2 ], names.iter().map( |name| name.string() ).collect::<Vec<Option<String>>>() );
+		}
+		// unambiguous strings stay bare
+		let mut config = ConfigFile::loads( "name\n" );
+		config.set( "name", string( "plain" ) );
+		assert_eq!( "name plain\n", config.dumps() );
 	}
 }
@@ -0,0 +1,299 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io::Error;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use filesystem::layered::LayeredFS;
+use log::{info, warn};
+use tier0::format::e;
+use tier0::format::e::E;
+
+use crate::config_file::{ConfigFile, ConfigSchema, KeyValue};
+
+/**
+ * A value of a watched file, either a pair from a `.cfg` file or a value from an E document.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigValue {
+	Config( KeyValue ),
+	E( E )
+}
+
+/**
+ * A key whose value changed after a reload, `old` is `None` if the key was added and `new` is `None` if it was removed.
+ */
+pub struct ConfigChange<'a> {
+	pub path: &'a str,
+	pub key: &'a str,
+	pub old: Option<&'a ConfigValue>,
+	pub new: Option<&'a ConfigValue>
+}
+
+type Callback = Box<dyn FnMut( &ConfigChange )>;
+
+struct WatchedFile {
+	path: String,
+	/// hash of the contents and the layer they were loaded from
+	fingerprint: u64,
+	/// types to parse the values of a `.cfg` file with, instead of inferring them
+	schema: Option<ConfigSchema>,
+	values: HashMap<String, ConfigValue>,
+	/// callbacks to fire for a key, or for every key if `None`
+	callbacks: Vec<( Option<String>, Callback )>
+}
+
+/**
+ * Reloads `ConfigFile`s and E documents when they change in the layered filesystem.
+ *
+ * Call `poll()` regularly (once per frame is fine), when a watched file changed on disk, or a file from
+ * another layer started overriding it, it gets reparsed and the callbacks of every changed key get fired.
+ */
+pub struct ConfigWatcher {
+	files: Vec<WatchedFile>,
+	interval: Duration,
+	last_poll: Option<Instant>
+}
+
+impl Default for ConfigWatcher {
+	fn default() -> Self {
+		ConfigWatcher::new()
+	}
+}
+
+impl ConfigWatcher {
+	pub fn new() -> Self {
+		ConfigWatcher { files: Vec::new(), interval: Duration::from_millis( 500 ), last_poll: None }
+	}
+
+	/**
+	 * Sets the minimum time between two checks of the watched files.
+	 */
+	pub fn set_interval( &mut self, interval: Duration ) {
+		self.interval = interval;
+	}
+
+	/**
+	 * Starts watching a file, loading its current values.
+	 */
+	pub fn watch( &mut self, fs: &LayeredFS, path: &str ) -> Result<(), Error> {
+		self.watch_with( fs, path, None )
+	}
+
+	/**
+	 * Starts watching a `.cfg` file, parsing its values with the types declared in `schema`.
+	 */
+	pub fn watch_with_schema( &mut self, fs: &LayeredFS, path: &str, schema: ConfigSchema ) -> Result<(), Error> {
+		self.watch_with( fs, path, Some( schema ) )
+	}
+
+	fn watch_with( &mut self, fs: &LayeredFS, path: &str, schema: Option<ConfigSchema> ) -> Result<(), Error> {
+		if self.files.iter().any( |file| file.path == path ) {
+			return Ok(());
+		}
+
+		let ( fingerprint, values ) = ConfigWatcher::load( fs, path, schema.as_ref() )?;
+		self.files.push( WatchedFile { path: path.to_string(), fingerprint, schema, values, callbacks: Vec::new() } );
+		Ok(())
+	}
+
+	pub fn unwatch( &mut self, path: &str ) {
+		self.files.retain( |file| file.path != path );
+	}
+
+	/**
+	 * Registers a callback to be fired when the value of `key` in the watched file `path` changes.
+	 * Keys of E documents are the path to the value, separated by dots ( ex. `video.width` ).
+	 */
+	pub fn on_change( &mut self, path: &str, key: &str, callback: impl FnMut( &ConfigChange ) + 'static ) {
+		self.add_callback( path, Some( key.to_string() ), Box::new( callback ) )
+	}
+
+	/**
+	 * Registers a callback to be fired for every key that changes in the watched file `path`.
+	 */
+	pub fn on_any_change( &mut self, path: &str, callback: impl FnMut( &ConfigChange ) + 'static ) {
+		self.add_callback( path, None, Box::new( callback ) )
+	}
+
+	fn add_callback( &mut self, path: &str, key: Option<String>, callback: Callback ) {
+		match self.files.iter_mut().find( |file| file.path == path ) {
+			Some( file ) => file.callbacks.push( ( key, callback ) ),
+			None => warn!( "tried to add a change callback to `{}`, which is not being watched", path )
+		}
+	}
+
+	/**
+	 * Returns the last loaded value of a key.
+	 */
+	pub fn get( &self, path: &str, key: &str ) -> Option<&ConfigValue> {
+		self.files.iter()
+			.find( |file| file.path == path )
+			.and_then( |file| file.values.get( key ) )
+	}
+
+	/**
+	 * Checks the watched files for changes, if enough time passed since the last check.
+	 * Returns the number of keys that changed.
+	 */
+	pub fn poll( &mut self, fs: &LayeredFS ) -> usize {
+		if self.last_poll.is_some_and( |last| last.elapsed() < self.interval ) {
+			return 0;
+		}
+		self.reload( fs )
+	}
+
+	/**
+	 * Checks the watched files for changes right away.
+	 * Returns the number of keys that changed.
+	 */
+	pub fn reload( &mut self, fs: &LayeredFS ) -> usize {
+		self.last_poll = Some( Instant::now() );
+
+		let mut changes = 0;
+		for file in &mut self.files {
+			let ( fingerprint, values ) = match ConfigWatcher::load( fs, file.path.as_str(), file.schema.as_ref() ) {
+				Ok( loaded ) => loaded,
+				Err( err ) => {
+					// keep the old values around, the file may be in the middle of being saved
+					warn!( "failed to reload watched file `{}`: {}", file.path, err );
+					continue;
+				}
+			};
+			if fingerprint == file.fingerprint {
+				continue;
+			}
+			info!( "reloading changed file `{}`", file.path );
+
+			let mut keys: Vec<&String> = file.values.keys()
+				.chain( values.keys().filter( |key| !file.values.contains_key( *key ) ) )
+				.collect();
+			keys.sort();
+
+			for key in keys {
+				let old = file.values.get( key );
+				let new = values.get( key );
+				if old == new {
+					continue;
+				}
+				changes += 1;
+
+				let change = ConfigChange { path: file.path.as_str(), key: key.as_str(), old, new };
+				for ( filter, callback ) in &mut file.callbacks {
+					if filter.as_ref().map_or( true, |filter| filter == key ) {
+						callback( &change );
+					}
+				}
+			}
+
+			file.fingerprint = fingerprint;
+			file.values = values;
+		}
+		changes
+	}
+
+	fn load( fs: &LayeredFS, path: &str, schema: Option<&ConfigSchema> ) -> Result<( u64, HashMap<String, ConfigValue> ), Error> {
+		let file = fs.get_file( path )?;
+		let data = file.read_string()?;
+
+		let mut hasher = DefaultHasher::new();
+		file.layer().hash( &mut hasher );
+		data.hash( &mut hasher );
+
+		let mut values = HashMap::new();
+		match Path::new( path ).extension().and_then( |ext| ext.to_str() ) {
+			Some( "e" ) => flatten( &mut values, String::new(), &e::loads( data.as_str(), path )? ),
+			_ => {
+				let config = match schema {
+					Some( schema ) => ConfigFile::loads_with_schema( data.as_str(), schema ),
+					None => ConfigFile::loads( data.as_str() )
+				};
+				for item in config.iterator() {
+					values.insert( item.key().to_string(), ConfigValue::Config( item.clone() ) );
+				}
+			}
+		}
+
+		Ok( ( hasher.finish(), values ) )
+	}
+}
+
+/**
+ * Flattens the maps and objects of an E document into dotted keys, anything else is a leaf value.
+ */
+fn flatten( values: &mut HashMap<String, ConfigValue>, prefix: String, value: &E ) {
+	match value {
+		E::Map { values: fields } | E::Object { class: _, fields } => {
+			for field in fields {
+				let key = match prefix.is_empty() {
+					true => field.key().to_string(),
+					false => format!( "{}.{}", prefix, field.key() )
+				};
+				flatten( values, key, field.value() );
+			}
+		}
+		value => {
+			values.insert( prefix, ConfigValue::E( value.clone() ) );
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::cell::RefCell;
+	use std::rc::Rc;
+	use std::sync::Arc;
+
+	use filesystem::layered::layers::memory::MemoryLayer;
+	use filesystem::layered::LayeredFS;
+
+	use crate::config_file::{ConfigSchema, KeyValue, ValueType};
+	use crate::config_watcher::{ConfigValue, ConfigWatcher};
+
+	#[test]
+	fn reload_fires_changes() {
+		let layer = Arc::new( MemoryLayer::new( "config" ).with( "balance.cfg", "damage 10\nspeed 1.5\n" ) );
+		let mut fs = LayeredFS::new();
+		fs.insert_layer( layer.clone(), false, &[] );
+
+		let changed = Rc::new( RefCell::new( Vec::new() ) );
+		let mut watcher = ConfigWatcher::new();
+		watcher.watch( &fs, "balance.cfg" ).unwrap();
+		{
+			let changed = changed.clone();
+			watcher.on_any_change( "balance.cfg", move |change| changed.borrow_mut().push( change.key.to_string() ) );
+		}
+
+		assert_eq!( 0, watcher.reload( &fs ) );
+
+		layer.insert( "balance.cfg", "damage 12\nspeed 1.5\nrange 300\n" ).unwrap();
+		assert_eq!( 2, watcher.reload( &fs ) );
+		assert_eq!( vec![ "damage".to_string(), "range".to_string() ], *changed.borrow() );
+		assert_eq!(
+			Some( &ConfigValue::Config( KeyValue::Int { key: "damage".to_string(), value: 12 } ) ),
+			watcher.get( "balance.cfg", "damage" )
+		);
+	}
+
+	#[test]
+	fn reload_uses_schema() {
+		let layer = Arc::new( MemoryLayer::new( "config" ).with( "spawn.cfg", "angles 0 90 0\n" ) );
+		let mut fs = LayeredFS::new();
+		fs.insert_layer( layer.clone(), false, &[] );
+
+		let mut watcher = ConfigWatcher::new();
+		watcher.watch_with_schema( &fs, "spawn.cfg", ConfigSchema::new().with( "angles", ValueType::QAngle ) ).unwrap();
+		assert_eq!(
+			Some( &ConfigValue::Config( KeyValue::QAngle { key: "angles".to_string(), value: [ 0.0, 90.0, 0.0 ] } ) ),
+			watcher.get( "spawn.cfg", "angles" )
+		);
+
+		layer.insert( "spawn.cfg", "angles 0 180 0\n" ).unwrap();
+		assert_eq!( 1, watcher.reload( &fs ) );
+		assert_eq!(
+			Some( &ConfigValue::Config( KeyValue::QAngle { key: "angles".to_string(), value: [ 0.0, 180.0, 0.0 ] } ) ),
+			watcher.get( "spawn.cfg", "angles" )
+		);
+	}
+}
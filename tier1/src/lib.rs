@@ -1,5 +1,4 @@
-#![feature(string_remove_matches)]
-
 pub mod config_file;
+pub mod config_watcher;
 pub mod console;
 pub mod color;
@@ -17,8 +17,7 @@ pub fn main() {
 	let commands = getCommands();
 
 	let mut fs = LayeredFS::new();
-	// FIXME: This errors
-	// fs.add_layer( std::env::current_dir().unwrap(), false ).expect("Failed to add current dir as layer.");
+	fs.add_layer( std::env::current_dir().unwrap(), false ).expect("Failed to add current dir as layer.");
 
 	println!( "FileSystem shell v1.4" );
 
@@ -26,45 +25,47 @@ pub fn main() {
 	let mut currentDir = "/".to_string();
 
 	'outer: loop {
-		// first thing we do is cleaning, as we `break` to here when a command is executed
+		// first thing we do is cleaning, as we `continue` to here when a command is executed
 		input.clear();
 		print!( ">>> " );
 		std::io::stdout().flush().expect("Failed to flush STDOUT");
 		match std::io::stdin().read_line( &mut input ) {
+			// end of input
+			Ok(0) => break,
 			Ok(_n) => {
-				input.clear().extend( input.trim() );
-				let command: Vec<&str> = input.split(" ").collect();
+				let line = input.trim().to_string();
+				let command: Vec<&str> = line.split(" ").collect();
 
 				match command.as_slice() {
+					[ "" ] => continue,
 					[ "help", "help" ] => { // help w/help as parameter command
 						println!("help: Prints this message")
 					}
 					[ "help", cmd ] => { // help w/parameter command
-						match commands.get(cmd) {
-							Some((_, help)) => println!("{help}"),
+						match commands.iter().find( |hdlr| hdlr.name == *cmd ) {
+							Some( hdlr ) => println!("{}", hdlr.help),
 							None => eprintln!("help: Unknown command {cmd}")
 						}
 					}
 					[ "help" ] => { // help command
 						println!("Available COMMANDS:");
-						for ( _, ( _, help ) ) in commands.iter() {
-							println!(" - {help}")
+						for hdlr in commands.iter() {
+							println!(" - {}", hdlr.help)
 						}
 					},
-					_ => None.unwrap()
-				}
-
-				// FIXME: This errors
-				// handle command
-				for hdlr in commands {
-					if hdlr.name == command[0] {
-						// registered command to execute
-						hdlr.handler( ( &mut fs, &command, &mut currentDir ) );
-						break 'outer
+					_ => {
+						// handle command
+						for hdlr in commands {
+							if hdlr.name == command[0] {
+								// registered command to execute
+								( hdlr.handler )( &mut fs, command.clone(), &mut currentDir );
+								continue 'outer
+							}
+						}
+						// unknown command
+						eprintln!( "ERROR: Unknown command {}", command[0] )
 					}
 				}
-				// unknown command
-				eprintln!( "ERROR: Unknown command {}", command[0] )
 			}
 			Err(error) => eprintln!( "ERROR: {error}" )
 		}
@@ -0,0 +1,26 @@
+use filesystem::layered::LayeredFS;
+
+use crate::shell::path::absolute;
+
+/**
+ * Prints the files which more layers have, one JSON object per line, for scripts and CI checks.
+ */
+pub(crate) fn conflictsHandler( fs: &mut LayeredFS, mut argv: Vec<&str>, cwd: &mut String ) {
+	argv.remove(0); // remove "conflicts" prefix
+	let hashes = argv.first() == Some( &"--hash" );
+	if hashes {
+		argv.remove(0);
+	}
+	let path = match argv.as_slice() {
+		[ rawPath ] => absolute( cwd, rawPath ),
+		[ ] => cwd.clone(),
+		_ => return eprintln!( "usage: conflicts [--hash] [$PATH]" )
+	};
+
+	match fs.conflicts( path.as_str(), hashes ) {
+		Ok( conflicts ) => for conflict in conflicts {
+			println!( "{}", conflict.to_json().dump() )
+		},
+		Err( err ) => eprintln!( "conflicts: cannot check \"{}\": {}", path, err )
+	}
+}
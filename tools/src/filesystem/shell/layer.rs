@@ -1,38 +1,90 @@
 use std::path::Path;
 
-use filesystem::layered::LayeredFS;
+use filesystem::layered::{LayeredFS, LayeredFSError};
+use filesystem::layered::stack::StackConfig;
+use uuid::Uuid;
 
-pub(crate) fn layerHandler( fs: &mut LayeredFS, mut argv: Vec<&str>, cwd: &mut String ) {
+const USAGE: &str = "usage: layer ( help | list | reverse | ( append | prepend | save | load ) $PATH | ( remove | enable | disable ) $UUID | move $UUID $INDEX )";
+
+pub(crate) fn layerHandler( fs: &mut LayeredFS, mut argv: Vec<&str>, _cwd: &mut String ) {
 	argv.remove(0); // remove "layer" prefix
 	match argv.as_slice() {
 		[ "help" ] => {
 			println!( "Layer manager v1.1" );
-			println!( "usage: layer ( help | list | reverse | ( append | prepend ) $PATH )" );
+			println!( "{USAGE}" );
 			println!( "\thelp              prints this message" );
-			println!( "\tappend     $PATH  Adds a layer to the end of the fs, may be a path to a folder or .bpak/.vpk file" );
-			println!( "\tprepend    $PATH  Adds a layer to the start of the fs, may be a path to a folder or .bpak/.vpk file" );
+			println!( "\tappend     $PATH  Adds a layer to the end of the fs, may be a path to a folder or .vpk/.zip/.pk3 file" );
+			println!( "\tprepend    $PATH  Adds a layer to the start of the fs, may be a path to a folder or .vpk/.zip/.pk3 file" );
 			println!( "\tlist              list all available layers" );
 			println!( "\treverse           reverse the order of the layer list" );
+			println!( "\tremove     $UUID  removes a layer" );
+			println!( "\tmove $UUID $INDEX moves a layer to another position, 0 is the top priority" );
+			println!( "\tenable     $UUID  enables a disabled layer" );
+			println!( "\tdisable    $UUID  disables a layer, it's skipped by all lookups until enabled again" );
+			println!( "\tsave       $PATH  saves the layer stack to an E file" );
+			println!( "\tload       $PATH  mounts a layer stack saved with `save`" );
 		}
-		// [ "list" ] => println!( "Layer in pos {}: {}", fs.layers.as_slice().index_of( layer ), layer.meta().filename ),
+		[ "list" ] => for info in fs.list_layers() {
+			println!(
+				"Layer in pos {}: {} {}{}{} [{}]",
+				info.index,
+				info.uuid,
+				info.meta.filename,
+				if info.writable { " (writable)" } else { "" },
+				if info.enabled { "" } else { " (disabled)" },
+				info.path_ids.join( ", " )
+			)
+		},
 		[ "reverse" ] => {
-			fs.layers.reverse();
+			fs.reverse_layers();
 			println!("Layer list successfully reversed.")
 		}
+		[ action @ ( "remove" | "enable" | "disable" ), rawUuid ] => {
+			let Ok( uuid ) = Uuid::parse_str( rawUuid ) else {
+				return eprintln!( "ERROR: Invalid layer UUID: {rawUuid}" );
+			};
+			let result = match *action {
+				"remove" => fs.remove_layer( &uuid ).map( |_| () ),
+				action => fs.set_layer_enabled( &uuid, action == "enable" )
+			};
+			match result {
+				Ok(_) => println!( "{action}d layer {uuid}" ),
+				Err( err ) => eprintln!( "ERROR: {err}" )
+			}
+		}
+		[ "move", rawUuid, rawIndex ] => {
+			let ( Ok( uuid ), Ok( index ) ) = ( Uuid::parse_str( rawUuid ), rawIndex.parse::<usize>() ) else {
+				return eprintln!( "{USAGE}" );
+			};
+			match fs.move_layer( &uuid, index ) {
+				Ok(_) => println!( "Moved layer {uuid} to pos {}", fs.layer_index( &uuid ).unwrap() ),
+				Err( err ) => eprintln!( "ERROR: {err}" )
+			}
+		}
+		[ "save", rawPath ] => match fs.stack_config().save( Path::new( rawPath ) ) {
+			Ok(_) => println!( "Saved the layer stack to {rawPath}" ),
+			Err( err ) => eprintln!( "ERROR: Failed to save the layer stack: {err}" )
+		}
+		[ "load", rawPath ] => match StackConfig::load( Path::new( rawPath ) ).and_then( |config| Ok( fs.restore_stack( &config )? ) ) {
+			Ok(_) => println!( "Loaded the layer stack from {rawPath}" ),
+			Err( err ) => eprintln!( "ERROR: Failed to load the layer stack: {err}" )
+		}
 		[ action @ ( "append" | "prepend" ), rawPath ] => {
-			let path = Path::new(rawPath).canonicalize().unwrap();
+			let Ok( path ) = Path::new(rawPath).canonicalize() else {
+				return eprintln!( "ERROR: Path {rawPath} doesn't exist" );
+			};
 
-			// FIXME: This errors
-			// if let Err(err) = fs.add_layer( path, *action == "prepend" ) {
-			// 	match err {
-			// 		LayeredFSError::NoExtension => eprintln!("ERROR: If adding a file, please make sure it has a valid extension."),
-			// 		LayeredFSError::Unsupported(ext) => eprintln!( "ERROR: Unsupported file type: {ext}" )
-			// 	}
-			// 	return;
-			// }
+			if let Err(err) = fs.add_layer( path.clone(), *action == "prepend" ) {
+				match err {
+					LayeredFSError::NoExtension => eprintln!("ERROR: If adding a file, please make sure it has a valid extension."),
+					LayeredFSError::Unsupported(ext) => eprintln!( "ERROR: Unsupported file type: {ext}" ),
+					err => eprintln!( "ERROR: {err}" )
+				}
+				return;
+			}
 
 			println!( "{action}ded {:?} as new layer", path )
 		}
-		_ => eprintln!( "usage: layer ( help | list | reverse | ( append | prepend ) $PATH )" )
+		_ => eprintln!( "{USAGE}" )
 	}
 }
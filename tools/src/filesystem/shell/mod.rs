@@ -1,25 +1,29 @@
-use std::cell::OnceCell;
+use std::sync::OnceLock;
 use filesystem::layered::LayeredFS;
 use crate::shell::builtin::clearHandler;
+use crate::shell::conflicts::conflictsHandler;
 
 use crate::shell::layer::layerHandler;
 use crate::shell::parse::parseHandler;
-use crate::shell::path::{cdHandler, findHandler, hasHandler, lsHandler};
+use crate::shell::path::{cdHandler, findHandler, globHandler, hasHandler, lsHandler};
 use crate::shell::read::readHandler;
+use crate::shell::trace::traceHandler;
 
 mod path;
 mod layer;
 mod parse;
 mod read;
+mod trace;
 mod builtin;
+mod conflicts;
 
 pub struct Command {
 	pub name: &'static str,
-	pub handler: fn(fs: &mut LayeredFS<'static>, argv: Vec<&str>, cwd: &mut String),
+	pub handler: fn(fs: &mut LayeredFS, argv: Vec<&str>, cwd: &mut String),
 	pub help: &'static str
 }
 
-static COMMANDS: OnceCell<Vec<Command>> = OnceCell::new();
+static COMMANDS: OnceLock<Vec<Command>> = OnceLock::new();
 
 pub fn getCommands() -> &'static Vec<Command> {
 	COMMANDS.get_or_init( || {
@@ -29,7 +33,10 @@ pub fn getCommands() -> &'static Vec<Command> {
 			Command { name: "has", handler: hasHandler, help: "has $PATH: Prints true if the file exists, false otherwise" },
 			Command { name: "read", handler: readHandler, help: "read $PATH: Prints the contents of the file, if found" },
 			Command { name: "find", handler: findHandler, help: "find $PATH: Prints the full path to the file, if found" },
+			Command { name: "glob", handler: globHandler, help: "glob $PATTERN: Prints all paths matching the pattern, supports `**`, `*`, `?` and `[...]`" },
+			Command { name: "conflicts", handler: conflictsHandler, help: "conflicts [--hash] [$PATH]: Prints the files more layers have as JSON lines, `--hash` also flags identical copies" },
 			Command { name: "layer", handler: layerHandler, help: "layer $SUBCOMMAND [$ARGUMENTS]: Manages layers" },
+			Command { name: "trace", handler: traceHandler, help: "trace ( start | status | stop [$PATH] ): Records the files accessed by commands, and saves them as a manifest" },
 			Command { name: "parse", handler: parseHandler, help: "parse [$OPTIONS] $PATH: Parses a file of a supported format, use `parse --help` for more info" },
 			Command { name: "clear", handler: clearHandler, help: "clear: Clears the terminal" },
 		]
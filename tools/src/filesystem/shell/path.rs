@@ -1,42 +1,88 @@
 use filesystem::layered::LayeredFS;
+use filesystem::layered::whiteout::Resolution;
+
+/**
+ * Resolves a path relative to the current directory, the result always starts with `/`.
+ */
+pub(crate) fn absolute( cwd: &str, path: &str ) -> String {
+	let mut parts: Vec<&str> = match path.starts_with( '/' ) {
+		true => Vec::new(),
+		false => cwd.split( '/' ).filter( |part| !part.is_empty() ).collect()
+	};
+	for part in path.split( '/' ) {
+		match part {
+			"" | "." => { }
+			".." => { parts.pop(); }
+			part => parts.push( part )
+		}
+	}
+	format!( "/{}", parts.join( "/" ) )
+}
 
 pub(crate) fn lsHandler( fs: &mut LayeredFS, mut argv: Vec<&str>, cwd: &mut String ) {
-	argv.remove(0); // remove "layer" prefix
+	argv.remove(0); // remove "ls" prefix
 	let path = match argv.as_slice() {
-		[ rawPath ] => rawPath,
-		// TODO: This
-		_ => None.unwrap()
+		[ rawPath ] => absolute( cwd, rawPath ),
+		[ ] => cwd.clone(),
+		_ => return eprintln!( "usage: ls [$PATH]" )
 	};
+
+	match fs.read_dir( path.as_str() ) {
+		Ok( entries ) => for entry in entries {
+			let layer = fs.find_layer( &entry.layer ).unwrap().meta().filename;
+			match entry.is_dir {
+				true => println!( "{}/\t({})", entry.name, layer ),
+				false => println!( "{}\t({})", entry.name, layer )
+			}
+		},
+		Err( err ) => eprintln!( "ls: cannot list \"{}\": {}", path, err )
+	}
 }
 
-pub(crate) fn cdHandler( fs: &mut LayeredFS, mut argv: Vec<&str>, cwd: &mut String ) {
+pub(crate) fn cdHandler( fs: &mut LayeredFS, argv: Vec<&str>, cwd: &mut String ) {
 	match argv.as_slice() {
+		[ "cd" ] => println!( "{}", cwd ),
 		[ "cd", path ] => {
-			// TODO: This
+			let path = absolute( cwd, path );
+			match fs.read_dir( path.as_str() ) {
+				Ok(_) => *cwd = path,
+				Err( err ) => eprintln!( "cd: cannot change directory to \"{}\": {}", path, err )
+			}
 		}
-		_ => None.unwrap()
+		_ => eprintln!( "usage: cd [$PATH]" )
 	}
 }
 
-pub(crate) fn findHandler( fs: &mut LayeredFS, mut argv: Vec<&str>, cwd: &mut String ) {
+pub(crate) fn findHandler( fs: &mut LayeredFS, argv: Vec<&str>, cwd: &mut String ) {
 	match argv.as_slice() {
-		[ "find", path ] => match fs.resolve( path ) {
-			None => eprintln!( "find: cannot find path \"{}\"", path ),
-			Some( path ) => println!( "{}", path.to_str().unwrap() )
+		[ "find", rawPath ] => match fs.resolution( absolute( cwd, rawPath ).as_str() ) {
+			Resolution::Missing => eprintln!( "find: cannot find path \"{}\"", rawPath ),
+			Resolution::Hidden { layer_name, .. } => eprintln!( "find: path \"{}\" is hidden by a whiteout in layer {}", rawPath, layer_name ),
+			Resolution::Found { path, layer_name, .. } => println!( "{} ({})", path.to_str().unwrap(), layer_name )
 		},
 		_ => eprintln!("usage: find $PATH")
 	}
 }
 
-pub(crate) fn hasHandler( fs: &mut LayeredFS, mut argv: Vec<&str>, cwd: &mut String ) {
+pub(crate) fn globHandler( fs: &mut LayeredFS, argv: Vec<&str>, cwd: &mut String ) {
 	match argv.as_slice() {
-		// FIXME: This errors
-		// [ "has", path ] => {
-		// 	match fs.get_file( path ) {
-		// 		Ok( file ) => println!( "File `{}` found in layer {}", file.path(), fs.find_layer( file.layer() ).unwrap().meta().filename ),
-		// 		Err(_) => {}
-		// 	}
-		// },
+		[ "glob", pattern ] => match fs.glob( absolute( cwd, pattern ).as_str() ) {
+			Ok( entries ) if entries.is_empty() => eprintln!( "glob: no matches for \"{}\"", pattern ),
+			Ok( entries ) => for entry in entries {
+				println!( "/{}{}", entry.path, if entry.is_dir { "/" } else { "" } )
+			},
+			Err( err ) => eprintln!( "glob: failed to search \"{}\": {}", pattern, err )
+		},
+		_ => eprintln!("usage: glob $PATTERN")
+	}
+}
+
+pub(crate) fn hasHandler( fs: &mut LayeredFS, argv: Vec<&str>, cwd: &mut String ) {
+	match argv.as_slice() {
+		[ "has", path ] => match fs.get_file( absolute( cwd, path ).as_str() ) {
+			Ok( file ) => println!( "true: file `{}` found in layer {}", file.path(), fs.find_layer( &file.layer() ).unwrap().meta().filename ),
+			Err(_) => println!( "false" )
+		},
 		_ => eprintln!("usage: has $PATH")
 	}
 }
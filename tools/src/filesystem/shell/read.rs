@@ -1,15 +1,16 @@
 use filesystem::layered::LayeredFS;
 
-pub(crate) fn readHandler(fs: &mut LayeredFS, mut argv: Vec<&str>, cwd: &mut String ) {
+use crate::shell::path::absolute;
+
+pub(crate) fn readHandler(fs: &mut LayeredFS, argv: Vec<&str>, cwd: &mut String ) {
 	match argv.as_slice() {
-		// FIXME: This errors
-		// [ "read", path ] => match fs.get_file(path) {
-		// 	Ok( file ) => match file.read_string() {
-		// 		Ok( data ) => println!( "{}", data ),
-		// 		Err( err ) => println!( "read: Failed to read file \"{}\": {}", path, err )
-		// 	},
-		// 	_ => eprintln!( "File {path} was not found" )
-		// },
+		[ "read", path ] => match fs.get_file( absolute( cwd, path ).as_str() ) {
+			Ok( file ) => match file.read_string() {
+				Ok( data ) => println!( "{}", data ),
+				Err( err ) => eprintln!( "read: Failed to read file \"{}\": {}", path, err )
+			},
+			Err( err ) => eprintln!( "read: {err}" )
+		},
 		_ => eprintln!( "usage: read $PATH" )
 	}
 }
@@ -0,0 +1,39 @@
+use std::path::Path;
+
+use filesystem::layered::LayeredFS;
+
+const USAGE: &str = "usage: trace ( start | status | stop [$PATH] )";
+
+pub(crate) fn traceHandler( fs: &mut LayeredFS, mut argv: Vec<&str>, _cwd: &mut String ) {
+	argv.remove(0); // remove "trace" prefix
+	match argv.as_slice() {
+		[ "start" ] => {
+			fs.start_trace();
+			println!( "Tracing file accesses" )
+		}
+		[ "status" ] => match fs.trace_manifest() {
+			Some( manifest ) => println!( "Tracing, {} files accessed and {} misses so far", manifest.files.len(), manifest.misses.len() ),
+			None => println!( "Not tracing" )
+		},
+		[ "stop", rest @ .. ] if rest.len() <= 1 => {
+			let Some( manifest ) = fs.stop_trace() else {
+				return eprintln!( "trace: not tracing" );
+			};
+			match rest.first() {
+				Some( rawPath ) => match manifest.save( Path::new( rawPath ) ) {
+					Ok(_) => println!( "Saved the manifest of {} files to {rawPath}", manifest.files.len() ),
+					Err( err ) => eprintln!( "trace: failed to save the manifest: {err}" )
+				},
+				None => {
+					for file in &manifest.files {
+						println!( "{}\t{}\t(opened {}, checked {})", file.path, file.layer, file.opens, file.checks )
+					}
+					for miss in &manifest.misses {
+						println!( "{miss}\tmissing" )
+					}
+				}
+			}
+		}
+		_ => eprintln!( "{USAGE}" )
+	}
+}